@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::args::BorshSchema;
+
+// ------------------------------- WATCHED CONTRACTS ----------------------------------
+// other contracts can be indexed without writing a handler for them. a JSON file
+//  (pointed to by the WATCH_CONFIG env variable) lists the contracts, the methods to
+//  capture on each of them and the fields to pull out of the args, as JSON pointers:
+//
+//  { "contracts": [ { "account_id": "nft.example.near", "methods": [
+//      { "name": "nft_mint", "fields": { "token_id": "/token_id", "title": "/metadata/title" } }
+//  ] } ] }
+//
+// every successful call to a listed method becomes a ContractCall event. methods taking
+//  Borsh args also give the layout of their args in "borsh_schema".
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct WatchConfig {
+    #[serde(default)]
+    pub contracts: Vec<WatchedContract>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct WatchedContract {
+    pub account_id: String,
+    pub methods: Vec<WatchedMethod>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct WatchedMethod {
+    pub name: String,
+    //name of the field in the event --> JSON pointer into the args
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    //layout of the args, for methods that take Borsh instead of JSON (see args.rs)
+    #[serde(default)]
+    pub borsh_schema: Option<BorshSchema>,
+}
+
+impl WatchConfig {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read watch config {:?}: {}", path, err))?;
+        let config: WatchConfig = serde_json::from_str(&contents)
+            .map_err(|err| format!("invalid watch config {:?}: {}", path, err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    //JSON pointers are either empty (the whole args) or start with a "/"
+    fn validate(&self) -> Result<(), String> {
+        for contract in &self.contracts {
+            for method in &contract.methods {
+                for (field, pointer) in &method.fields {
+                    if !pointer.is_empty() && !pointer.starts_with('/') {
+                        return Err(format!(
+                            "field {} of {} on {} has an invalid JSON pointer {:?}, it should start with \"/\"",
+                            field, method.name, contract.account_id, pointer
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_watched(&self, account_id: &str) -> bool {
+        self.contracts.iter().any(|contract| contract.account_id == account_id)
+    }
+
+    pub(crate) fn method(&self, account_id: &str, method_name: &str) -> Option<&WatchedMethod> {
+        self.contracts
+            .iter()
+            .filter(|contract| contract.account_id == account_id)
+            .flat_map(|contract| contract.methods.iter())
+            .find(|method| method.name == method_name)
+    }
+}
+
+impl WatchedMethod {
+    //pull the configured fields out of the args, missing ones are null
+    pub(crate) fn extract_fields(&self, args: &serde_json::Value) -> BTreeMap<String, serde_json::Value> {
+        self.fields
+            .iter()
+            .map(|(field, pointer)| {
+                let value = args.pointer(pointer).cloned().unwrap_or(serde_json::Value::Null);
+                (field.clone(), value)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeSet;
+
+use near_indexer::near_primitives::views::{ActionView, ExecutionStatusView, ReceiptEnumView};
+use near_indexer::IndexerExecutionOutcomeWithReceipt;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::events::IndexerEvent;
+use crate::sink::deliver_event;
+use crate::IndexerContext;
+
+// ------------------------------- ACCOUNT ACTIVITY ----------------------------------
+// every receipt signed or received by a watched account (the admin plus the accounts
+//  in the WATCH_ACCOUNTS env variable) is reported action by action, whatever contract
+//  it is for and whether or not it succeeded. this feeds the activity feed and the
+//  audit trail.
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WatchedAccounts {
+    accounts: BTreeSet<String>,
+}
+
+impl WatchedAccounts {
+    pub(crate) fn new<I: IntoIterator<Item = String>>(accounts: I) -> Self {
+        let accounts = accounts
+            .into_iter()
+            .map(|account| account.trim().to_string())
+            .filter(|account| !account.is_empty())
+            .collect();
+        Self { accounts }
+    }
+
+    //the admin account plus a comma separated list of accounts
+    pub(crate) fn from_list(admin_account: &str, accounts: &str) -> Self {
+        let accounts = accounts.split(',').map(|account| account.to_string());
+        Self::new(std::iter::once(admin_account.to_string()).chain(accounts))
+    }
+
+    fn contains(&self, account_id: &str) -> bool {
+        self.accounts.contains(account_id)
+    }
+}
+
+//how a watched account took part in a receipt
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityRole {
+    Signer,
+    Receiver,
+}
+
+//an action of a receipt, as reported in the activity feed
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActivityAction {
+    CreateAccount,
+    DeployContract,
+    FunctionCall {
+        method_name: String,
+        //in yocto
+        deposit: String,
+    },
+    Transfer {
+        //in yocto
+        deposit: String,
+    },
+    Stake {
+        //in yocto
+        stake: String,
+        public_key: String,
+    },
+    AddKey {
+        public_key: String,
+        //"FullAccess", or the contract and methods a function call key is limited to
+        permission: serde_json::Value,
+    },
+    DeleteKey {
+        public_key: String,
+    },
+    DeleteAccount {
+        beneficiary_id: String,
+    },
+}
+
+impl From<&ActionView> for ActivityAction {
+    fn from(action: &ActionView) -> Self {
+        match action {
+            ActionView::CreateAccount => ActivityAction::CreateAccount,
+            ActionView::DeployContract { .. } => ActivityAction::DeployContract,
+            ActionView::FunctionCall { method_name, deposit, .. } => ActivityAction::FunctionCall {
+                method_name: method_name.to_string(),
+                deposit: deposit.to_string(),
+            },
+            ActionView::Transfer { deposit } => ActivityAction::Transfer { deposit: deposit.to_string() },
+            ActionView::Stake { stake, public_key } => ActivityAction::Stake {
+                stake: stake.to_string(),
+                public_key: public_key.to_string(),
+            },
+            ActionView::AddKey { public_key, access_key } => ActivityAction::AddKey {
+                public_key: public_key.to_string(),
+                permission: serde_json::to_value(&access_key.permission).unwrap_or(serde_json::Value::Null),
+            },
+            ActionView::DeleteKey { public_key } => ActivityAction::DeleteKey { public_key: public_key.to_string() },
+            ActionView::DeleteAccount { beneficiary_id } => ActivityAction::DeleteAccount {
+                beneficiary_id: beneficiary_id.to_string(),
+            },
+        }
+    }
+}
+
+//report every action of the receipt for each watched account that signed or received it
+pub(crate) async fn deliver_account_activity(
+    receipt_and_execution_outcome: &IndexerExecutionOutcomeWithReceipt,
+    block_height: u64,
+    block_timestamp: u64,
+    context: &IndexerContext,
+) {
+    let receipt = &receipt_and_execution_outcome.receipt;
+    let (signer_id, actions) = match &receipt.receipt {
+        ReceiptEnumView::Action { signer_id, actions, .. } => (signer_id.to_string(), actions),
+        //data receipts only carry the result of another receipt
+        _ => return,
+    };
+    let receiver_id = receipt.receiver_id.to_string();
+
+    let mut roles = vec![];
+    if context.watched_accounts.contains(&signer_id) {
+        roles.push((signer_id.clone(), ActivityRole::Signer));
+    }
+    if context.watched_accounts.contains(&receiver_id) {
+        roles.push((receiver_id.clone(), ActivityRole::Receiver));
+    }
+    if roles.is_empty() {
+        return;
+    }
+
+    let success = matches!(
+        receipt_and_execution_outcome.execution_outcome.outcome.status,
+        ExecutionStatusView::SuccessValue(_) | ExecutionStatusView::SuccessReceiptId(_)
+    );
+    for (account_id, role) in roles {
+        for action in actions {
+            let account_activity = IndexerEvent::AccountActivity {
+                account_id: account_id.clone(),
+                role,
+                receipt_id: receipt.receipt_id.to_string(),
+                block_height,
+                block_timestamp,
+                signer_id: signer_id.clone(),
+                predecessor_id: receipt.predecessor_id.to_string(),
+                receiver_id: receiver_id.clone(),
+                action: action.into(),
+                success,
+            };
+            deliver_event(account_activity, context).await;
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::state_dir;
+use crate::database;
+use crate::events::IndexerEvent;
+use crate::IndexerContext;
+
+// ------------------------------- SALE ANALYTICS ----------------------------------
+// the sales and listings going through the sink are rolled up per collection (nft
+//  contract) into hourly and daily buckets: floor price, volume, last and average sale
+//  price. the buckets and the open listings are saved to fayyr-indexer/analytics.json
+//  every few seconds when they changed, `sale-stats` prints them and `run` pushes the
+//  buckets that changed to the API every --stats-interval-secs. hourly buckets are kept
+//  for a week and daily ones for a year.
+// a reverted sale is taken out of the counts and the volume, the last sale price stays.
+//  a reverted listing or price update puts back the price the token had before, the
+//  floor it set while it was there stays.
+
+//how often the stats are written to analytics.json while the indexer runs
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+const ONE_DAY_NANOS: u64 = 86_400_000_000_000;
+//how many earlier listing prices of a token are kept to undo reverted price updates
+const MAX_REPLACED_PRICES: usize = 16;
+
+fn analytics_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("analytics.json")
+}
+
+//"nft_contract||token_id"
+fn listing_key(contract_id: &str, token_id: &str) -> String {
+    format!("{}||{}", contract_id, token_id)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsPeriod {
+    Hour,
+    Day,
+}
+
+impl FromStr for StatsPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(StatsPeriod::Hour),
+            "day" => Ok(StatsPeriod::Day),
+            _ => Err(format!("unknown stats period {:?}, expected \"hour\" or \"day\"", s)),
+        }
+    }
+}
+
+impl StatsPeriod {
+    //the period a block timestamp falls in, e.g. "2021-06-15T13" for an hour
+    fn label(&self, block_timestamp: u64) -> String {
+        let time = Utc.timestamp_nanos(block_timestamp as i64);
+        match self {
+            StatsPeriod::Hour => time.format("%Y-%m-%dT%H").to_string(),
+            StatsPeriod::Day => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    //how long the buckets of the period are kept, in nanoseconds
+    fn retention(&self) -> u64 {
+        match self {
+            StatsPeriod::Hour => 7 * ONE_DAY_NANOS,
+            StatsPeriod::Day => 365 * ONE_DAY_NANOS,
+        }
+    }
+}
+
+//the sales of a collection during one hour or day
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct CollectionStats {
+    pub contract_id: String,
+    pub period: StatsPeriod,
+    pub period_start: String,
+    pub sales: u64,
+    //sales paid in near, the only ones the prices below are worked out from
+    pub priced_sales: u64,
+    pub volume_near: f64,
+    pub last_sale_near: Option<f64>,
+    pub average_sale_near: Option<f64>,
+    //lowest listing price of the collection while the period was going
+    pub floor_near: Option<f64>,
+}
+
+impl CollectionStats {
+    fn new(contract_id: &str, period: StatsPeriod, period_start: String) -> Self {
+        Self {
+            contract_id: contract_id.to_string(),
+            period,
+            period_start,
+            sales: 0,
+            priced_sales: 0,
+            volume_near: 0.0,
+            last_sale_near: None,
+            average_sale_near: None,
+            floor_near: None,
+        }
+    }
+
+    fn lower_floor(&mut self, floor_near: Option<f64>) {
+        self.floor_near = match (self.floor_near, floor_near) {
+            (Some(floor), Some(new_floor)) => Some(floor.min(new_floor)),
+            (floor, new_floor) => floor.or(new_floor),
+        };
+    }
+
+    fn add_sale(&mut self, price_near: Option<f64>) {
+        self.sales += 1;
+        if let Some(price_near) = price_near {
+            self.priced_sales += 1;
+            self.volume_near += price_near;
+            self.last_sale_near = Some(price_near);
+        }
+        self.update_average();
+    }
+
+    fn remove_sale(&mut self, price_near: Option<f64>) {
+        self.sales = self.sales.saturating_sub(1);
+        if let Some(price_near) = price_near {
+            self.priced_sales = self.priced_sales.saturating_sub(1);
+            self.volume_near = (self.volume_near - price_near).max(0.0);
+        }
+        self.update_average();
+    }
+
+    fn update_average(&mut self) {
+        self.average_sale_near = match self.priced_sales {
+            0 => None,
+            priced_sales => Some(self.volume_near / priced_sales as f64),
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AnalyticsState {
+    //nft contract --> token id --> listed price in near
+    listings: BTreeMap<String, BTreeMap<String, f64>>,
+    //"nft_contract||period||period_start" --> stats
+    buckets: BTreeMap<String, CollectionStats>,
+    //"nft_contract||token_id" --> the prices the token was listed at before, latest last
+    #[serde(default)]
+    replaced_prices: BTreeMap<String, Vec<f64>>,
+    //buckets that changed since they were last pushed to the API
+    #[serde(skip)]
+    changed: BTreeSet<String>,
+    //whether anything changed since the state was last saved
+    #[serde(skip)]
+    unsaved: bool,
+    //the hour old buckets were last pruned in
+    #[serde(skip)]
+    pruned_in: String,
+}
+
+impl AnalyticsState {
+    fn floor_near(&self, contract_id: &str) -> Option<f64> {
+        self.listings
+            .get(contract_id)?
+            .values()
+            .copied()
+            .reduce(f64::min)
+    }
+
+    //the hourly and daily buckets a block timestamp falls in
+    fn buckets_at(&mut self, contract_id: &str, block_timestamp: u64) -> Vec<&mut CollectionStats> {
+        let floor_near = self.floor_near(contract_id);
+        let mut keys = vec![];
+        for period in [StatsPeriod::Hour, StatsPeriod::Day] {
+            let period_start = period.label(block_timestamp);
+            let key = format!("{}||{:?}||{}", contract_id, period, period_start);
+            self.buckets.entry(key.clone()).or_insert_with(|| CollectionStats::new(contract_id, period, period_start));
+            self.changed.insert(key.clone());
+            keys.push(key);
+        }
+        self.buckets
+            .iter_mut()
+            .filter(|(key, _)| keys.contains(*key))
+            .map(|(_, stats)| {
+                stats.lower_floor(floor_near);
+                stats
+            })
+            .collect()
+    }
+
+    //drop the buckets older than the retention of their period, at most once an hour
+    fn prune(&mut self, block_timestamp: u64) {
+        let hour = StatsPeriod::Hour.label(block_timestamp);
+        if hour <= self.pruned_in {
+            return;
+        }
+        self.pruned_in = hour;
+        let oldest_hour = StatsPeriod::Hour.label(block_timestamp.saturating_sub(StatsPeriod::Hour.retention()));
+        let oldest_day = StatsPeriod::Day.label(block_timestamp.saturating_sub(StatsPeriod::Day.retention()));
+        self.buckets.retain(|_, stats| match stats.period {
+            StatsPeriod::Hour => stats.period_start >= oldest_hour,
+            StatsPeriod::Day => stats.period_start >= oldest_day,
+        });
+        let buckets = &self.buckets;
+        self.changed.retain(|key| buckets.contains_key(key));
+    }
+
+    fn list(&mut self, contract_id: &str, token_id: &str, price_near: f64, block_timestamp: u64) {
+        let replaced = self.listings.entry(contract_id.to_string()).or_default().insert(token_id.to_string(), price_near);
+        let key = listing_key(contract_id, token_id);
+        match replaced {
+            Some(replaced) => {
+                let replaced_prices = self.replaced_prices.entry(key).or_default();
+                replaced_prices.push(replaced);
+                if replaced_prices.len() > MAX_REPLACED_PRICES {
+                    replaced_prices.remove(0);
+                }
+            }
+            None => {
+                self.replaced_prices.remove(&key);
+            }
+        }
+        self.prune(block_timestamp);
+        self.buckets_at(contract_id, block_timestamp);
+    }
+
+    fn unlist(&mut self, contract_id: &str, token_id: &str) {
+        if let Some(listings) = self.listings.get_mut(contract_id) {
+            listings.remove(token_id);
+        }
+        self.replaced_prices.remove(&listing_key(contract_id, token_id));
+    }
+
+    //put back the price the token was listed at before, or take it off the listings
+    fn revert_listing(&mut self, contract_id: &str, token_id: &str) {
+        let key = listing_key(contract_id, token_id);
+        let previous_price = self.replaced_prices.get_mut(&key).and_then(|replaced_prices| replaced_prices.pop());
+        let listings = self.listings.entry(contract_id.to_string()).or_default();
+        match previous_price {
+            Some(previous_price) => {
+                listings.insert(token_id.to_string(), previous_price);
+            }
+            None => {
+                listings.remove(token_id);
+                self.replaced_prices.remove(&key);
+            }
+        }
+    }
+
+    fn sell(&mut self, contract_id: &str, token_id: &str, price_near: Option<f64>, block_timestamp: u64) {
+        self.unlist(contract_id, token_id);
+        self.prune(block_timestamp);
+        for stats in self.buckets_at(contract_id, block_timestamp) {
+            stats.add_sale(price_near);
+        }
+    }
+
+    fn revert_sale(&mut self, contract_id: &str, price_near: Option<f64>, block_timestamp: u64) {
+        for stats in self.buckets_at(contract_id, block_timestamp) {
+            stats.remove_sale(price_near);
+        }
+    }
+}
+
+pub(crate) struct SaleAnalytics {
+    //None when the stats are only kept in memory
+    path: Option<PathBuf>,
+    state: Mutex<AnalyticsState>,
+}
+
+impl SaleAnalytics {
+    pub(crate) fn load(home_dir: &Path) -> Self {
+        let path = analytics_path(home_dir);
+        let state = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                eprintln!("Ignoring unreadable analytics file --> {:?}", err);
+                AnalyticsState::default()
+            }),
+            Err(_) => AnalyticsState::default(),
+        };
+        Self { path: Some(path), state: Mutex::new(state) }
+    }
+
+    //stop saving the stats, for dry runs and replays which mustn't touch the real ones
+    pub(crate) fn keep_in_memory(&mut self) {
+        self.path = None;
+    }
+
+    //fold a delivered event into the stats, the events that say nothing about sales or listings are ignored
+    pub(crate) fn record(&self, event: &IndexerEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            IndexerEvent::TokenListed { token_id, contract_id, price_near, block_timestamp }
+            | IndexerEvent::PriceUpdated { token_id, contract_id, price_near, block_timestamp } => {
+                state.list(contract_id, token_id, *price_near, *block_timestamp)
+            }
+            IndexerEvent::ListingRemoved { token_id, contract_id } => state.unlist(contract_id, token_id),
+            IndexerEvent::TokenSold { token_id, contract_id, price_near, block_timestamp, .. }
+            | IndexerEvent::OfferAccepted { token_id, contract_id, price_near, block_timestamp, .. } => {
+                state.sell(contract_id, token_id, *price_near, *block_timestamp)
+            }
+            IndexerEvent::Reverted { event, .. } => match event.as_ref() {
+                IndexerEvent::TokenSold { contract_id, price_near, block_timestamp, .. }
+                | IndexerEvent::OfferAccepted { contract_id, price_near, block_timestamp, .. } => {
+                    state.revert_sale(contract_id, *price_near, *block_timestamp)
+                }
+                IndexerEvent::TokenListed { token_id, contract_id, .. }
+                | IndexerEvent::PriceUpdated { token_id, contract_id, .. } => state.revert_listing(contract_id, token_id),
+                _ => return,
+            },
+            _ => return,
+        }
+        state.unsaved = true;
+    }
+
+    //the stats of a period, sorted by collection then period start
+    pub(crate) fn collection_stats(&self, contract_id: Option<&str>, period: StatsPeriod) -> Vec<CollectionStats> {
+        let state = self.state.lock().unwrap();
+        state
+            .buckets
+            .values()
+            .filter(|stats| stats.period == period && contract_id.map_or(true, |contract_id| stats.contract_id == contract_id))
+            .cloned()
+            .collect()
+    }
+
+    //the buckets that changed since the last call
+    fn take_changed(&self) -> Vec<(String, CollectionStats)> {
+        let mut state = self.state.lock().unwrap();
+        let changed = std::mem::take(&mut state.changed);
+        changed.into_iter().filter_map(|key| state.buckets.get(&key).cloned().map(|stats| (key, stats))).collect()
+    }
+
+    //push them again next time
+    fn mark_changed<I: IntoIterator<Item = String>>(&self, keys: I) {
+        self.state.lock().unwrap().changed.extend(keys);
+    }
+
+    //write the stats to analytics.json if they changed since the last save. this blocks on
+    //  the file system, the indexer calls it from a blocking task
+    pub(crate) fn save_if_changed(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let contents = {
+            let mut state = self.state.lock().unwrap();
+            if !state.unsaved {
+                return;
+            }
+            state.unsaved = false;
+            serde_json::to_vec_pretty(&*state)
+        };
+        //write to a temporary file first so a crash can't leave half a file behind
+        let tmp_path = path.with_extension("json.tmp");
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&tmp_path, contents?))
+            .and_then(|_| std::fs::rename(&tmp_path, path));
+        if let Err(err) = saved {
+            eprintln!("Could not save the sale analytics to {:?}, retrying next time --> {:?}", path, err);
+            self.state.lock().unwrap().unsaved = true;
+        }
+    }
+}
+
+//save the stats every few seconds, for as long as the indexer runs
+pub(crate) async fn save_sale_analytics_every(context: Arc<IndexerContext>) {
+    loop {
+        tokio::time::sleep(SAVE_INTERVAL).await;
+        let context = context.clone();
+        if let Err(err) = tokio::task::spawn_blocking(move || context.analytics.save_if_changed()).await {
+            eprintln!("Saving the sale analytics failed --> {:?}", err);
+        }
+    }
+}
+
+//send the buckets that changed to the API
+pub(crate) async fn push_collection_stats(context: &IndexerContext) {
+    let changed = context.analytics.take_changed();
+    if changed.is_empty() {
+        return;
+    }
+    let (keys, collections): (Vec<String>, Vec<CollectionStats>) = changed.into_iter().unzip();
+    let result = database::push_collection_stats_in_database(
+        collections,
+        context.signature_header.clone(),
+        &context.private_api_root,
+        context.debug_mode.clone(),
+    )
+    .await;
+    context.stats.record_sink_result(&result);
+    if let Err(err) = result {
+        eprintln!("Failed to push the collection stats to the API, retrying next time --> {:?}", err);
+        context.analytics.mark_changed(keys);
+    }
+}
+
+//push the collection stats every interval, for as long as the indexer runs
+pub(crate) async fn push_collection_stats_every(context: Arc<IndexerContext>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        push_collection_stats(&context).await;
+    }
+}
+
+pub(crate) fn print_collection_stats(collection_stats: &[CollectionStats]) {
+    if collection_stats.is_empty() {
+        println!("No sales or listings recorded yet");
+        return;
+    }
+    println!(
+        "{:<32} {:<14} {:>6} {:>12} {:>10} {:>10} {:>10}",
+        "COLLECTION", "PERIOD", "SALES", "VOLUME", "LAST", "AVERAGE", "FLOOR"
+    );
+    let near = |price: Option<f64>| price.map_or("-".to_string(), |price| format!("{:.2}", price));
+    for stats in collection_stats {
+        println!(
+            "{:<32} {:<14} {:>6} {:>12.2} {:>10} {:>10} {:>10}",
+            stats.contract_id,
+            stats.period_start,
+            stats.sales,
+            stats.volume_near,
+            near(stats.last_sale_near),
+            near(stats.average_sale_near),
+            near(stats.floor_near)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::abi::WatchConfig;
+
+// ------------------------------- ARGS DECODING ----------------------------------
+// function call args are just bytes. most contracts send JSON, but some take Borsh or
+//  raw bytes. the args of every call go through a list of decoders, the first one
+//  that understands them wins:
+//   - Borsh, for the methods a borsh_schema is registered for in the watch config
+//   - JSON
+//   - hex, which always works and gives { "hex": "..." } so the bytes are never lost
+// anything that ends up as hex couldn't really be decoded and is counted as a failure.
+
+//how the args of a call were decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ArgsEncoding {
+    Json,
+    Borsh,
+    Hex,
+}
+
+pub(crate) trait ArgsDecoder: Send + Sync {
+    fn encoding(&self) -> ArgsEncoding;
+
+    //turn the args of a call into JSON, or None if they aren't in this decoder's format
+    fn decode(&self, contract_id: &str, method_name: &str, args: &[u8]) -> Option<serde_json::Value>;
+}
+
+pub(crate) struct JsonArgsDecoder;
+
+impl ArgsDecoder for JsonArgsDecoder {
+    fn encoding(&self) -> ArgsEncoding {
+        ArgsEncoding::Json
+    }
+
+    fn decode(&self, _contract_id: &str, _method_name: &str, args: &[u8]) -> Option<serde_json::Value> {
+        //calls without args are common, treat them as an empty object
+        if args.is_empty() {
+            return Some(serde_json::json!({}));
+        }
+        serde_json::from_slice(args).ok()
+    }
+}
+
+pub(crate) struct HexArgsDecoder;
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl ArgsDecoder for HexArgsDecoder {
+    fn encoding(&self) -> ArgsEncoding {
+        ArgsEncoding::Hex
+    }
+
+    fn decode(&self, _contract_id: &str, _method_name: &str, args: &[u8]) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "hex": to_hex(args) }))
+    }
+}
+
+//layout of Borsh encoded args, e.g. { "struct": [["token_id", "string"], ["amount", "u128"]] }
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum BorshSchema {
+    Primitive(BorshPrimitive),
+    Compound(BorshCompound),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BorshPrimitive {
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Bool,
+    String,
+    //a Vec<u8>, shown as hex
+    Bytes,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BorshCompound {
+    Vec(Box<BorshSchema>),
+    Option(Box<BorshSchema>),
+    //fixed length array: [item, length]
+    Array(Box<BorshSchema>, usize),
+    //named fields, in order
+    Struct(Vec<(String, BorshSchema)>),
+}
+
+struct BorshReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BorshReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err(format!("expected {} more bytes, only {} left", len, self.bytes.len()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn read_len(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.take_array()?) as usize)
+    }
+
+    fn read(&mut self, schema: &BorshSchema) -> Result<serde_json::Value, String> {
+        use serde_json::Value;
+        Ok(match schema {
+            BorshSchema::Primitive(primitive) => match primitive {
+                BorshPrimitive::U8 => Value::from(self.take_array::<1>()?[0]),
+                BorshPrimitive::U16 => Value::from(u16::from_le_bytes(self.take_array()?)),
+                BorshPrimitive::U32 => Value::from(u32::from_le_bytes(self.take_array()?)),
+                BorshPrimitive::U64 => Value::from(u64::from_le_bytes(self.take_array()?)),
+                //128 bit numbers don't fit in JSON numbers, they are strings like near_sdk's U128
+                BorshPrimitive::U128 => Value::from(u128::from_le_bytes(self.take_array()?).to_string()),
+                BorshPrimitive::I8 => Value::from(i8::from_le_bytes(self.take_array()?)),
+                BorshPrimitive::I16 => Value::from(i16::from_le_bytes(self.take_array()?)),
+                BorshPrimitive::I32 => Value::from(i32::from_le_bytes(self.take_array()?)),
+                BorshPrimitive::I64 => Value::from(i64::from_le_bytes(self.take_array()?)),
+                BorshPrimitive::I128 => Value::from(i128::from_le_bytes(self.take_array()?).to_string()),
+                BorshPrimitive::Bool => match self.take_array::<1>()?[0] {
+                    0 => Value::Bool(false),
+                    1 => Value::Bool(true),
+                    other => return Err(format!("invalid bool {}", other)),
+                },
+                BorshPrimitive::String => {
+                    let len = self.read_len()?;
+                    let string = std::str::from_utf8(self.take(len)?).map_err(|err| err.to_string())?;
+                    Value::from(string)
+                }
+                BorshPrimitive::Bytes => {
+                    let len = self.read_len()?;
+                    Value::from(to_hex(self.take(len)?))
+                }
+            },
+            BorshSchema::Compound(compound) => match compound {
+                BorshCompound::Vec(item) => {
+                    let len = self.read_len()?;
+                    (0..len).map(|_| self.read(item)).collect::<Result<Vec<_>, _>>()?.into()
+                }
+                BorshCompound::Option(item) => match self.take_array::<1>()?[0] {
+                    0 => Value::Null,
+                    1 => self.read(item)?,
+                    other => return Err(format!("invalid option tag {}", other)),
+                },
+                BorshCompound::Array(item, len) => {
+                    (0..*len).map(|_| self.read(item)).collect::<Result<Vec<_>, _>>()?.into()
+                }
+                BorshCompound::Struct(fields) => {
+                    let mut object = serde_json::Map::new();
+                    for (name, field) in fields {
+                        object.insert(name.clone(), self.read(field)?);
+                    }
+                    Value::Object(object)
+                }
+            },
+        })
+    }
+}
+
+//decode Borsh bytes that must follow the schema exactly, with nothing left over
+pub(crate) fn decode_borsh(schema: &BorshSchema, bytes: &[u8]) -> Result<serde_json::Value, String> {
+    let mut reader = BorshReader { bytes };
+    let value = reader.read(schema)?;
+    if !reader.bytes.is_empty() {
+        return Err(format!("{} bytes left over", reader.bytes.len()));
+    }
+    Ok(value)
+}
+
+//Borsh decoding for the methods a schema was registered for
+pub(crate) struct BorshArgsDecoder {
+    //(contract, method) --> schema of its args
+    schemas: HashMap<(String, String), BorshSchema>,
+}
+
+impl BorshArgsDecoder {
+    pub(crate) fn from_watch_config(watch_config: &WatchConfig) -> Self {
+        let mut schemas = HashMap::new();
+        for contract in &watch_config.contracts {
+            for method in &contract.methods {
+                if let Some(schema) = &method.borsh_schema {
+                    schemas.insert((contract.account_id.clone(), method.name.clone()), schema.clone());
+                }
+            }
+        }
+        Self { schemas }
+    }
+}
+
+impl ArgsDecoder for BorshArgsDecoder {
+    fn encoding(&self) -> ArgsEncoding {
+        ArgsEncoding::Borsh
+    }
+
+    fn decode(&self, contract_id: &str, method_name: &str, args: &[u8]) -> Option<serde_json::Value> {
+        let schema = self.schemas.get(&(contract_id.to_string(), method_name.to_string()))?;
+        match decode_borsh(schema, args) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                eprintln!("Args of {} on {} don't match the registered Borsh schema --> {}", method_name, contract_id, err);
+                None
+            }
+        }
+    }
+}
+
+//the decoders to try, in order
+pub(crate) struct ArgsDecoders {
+    decoders: Vec<Box<dyn ArgsDecoder>>,
+}
+
+impl ArgsDecoders {
+    pub(crate) fn new(decoders: Vec<Box<dyn ArgsDecoder>>) -> Self {
+        Self { decoders }
+    }
+
+    //Borsh for the registered methods, then JSON, then hex
+    pub(crate) fn from_watch_config(watch_config: &WatchConfig) -> Self {
+        Self::new(vec![
+            Box::new(BorshArgsDecoder::from_watch_config(watch_config)),
+            Box::new(JsonArgsDecoder),
+            Box::new(HexArgsDecoder),
+        ])
+    }
+
+    //decode the base64 args of a function call action
+    pub(crate) fn decode(
+        &self,
+        contract_id: &str,
+        method_name: &str,
+        base64_args: &str,
+    ) -> Result<(serde_json::Value, ArgsEncoding), String> {
+        let args = base64::decode(base64_args).map_err(|err| format!("args are not valid base64: {}", err))?;
+        self.decoders
+            .iter()
+            .find_map(|decoder| {
+                decoder
+                    .decode(contract_id, method_name, &args)
+                    .map(|value| (value, decoder.encoding()))
+            })
+            .ok_or_else(|| "no decoder understood the args".to_string())
+    }
+}
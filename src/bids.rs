@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::state_dir;
+use crate::finality::UndoLog;
+
+// ------------------------------- BIDS ----------------------------------
+// the market only tells us about the bid being placed, so to know who was outbid
+//  (and who wins when the owner accepts) we keep the highest bid on every token.
+// the book is saved after every change so a restart doesn't forget open auctions.
+// bids have to reach the book in the order they were placed, which is why the stream
+//  hands us one block at a time (see MAX_IN_FLIGHT_BLOCKS).
+
+fn bids_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("bids.json")
+}
+
+//the highest bid currently standing on a token
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct HighestBid {
+    pub bidder_account_id: String,
+    pub ft_token_id: String,
+    //in the smallest unit of the FT (yocto for near), as a string so it survives JSON
+    pub amount: String,
+    pub receipt_id: String,
+}
+
+impl HighestBid {
+    pub(crate) fn amount(&self) -> u128 {
+        self.amount.parse().unwrap_or(0)
+    }
+}
+
+//what placing a bid did to the book
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BidOutcome {
+    //there was no bid on the token yet
+    First,
+    //the bid beat the previous highest bid, which is returned
+    Outbid(HighestBid),
+    //the bid didn't beat the standing bid (the market should have refused it)
+    NotHighest(HighestBid),
+}
+
+pub(crate) struct BidBook {
+    //None when the book is only kept in memory
+    path: Option<PathBuf>,
+    //highest bid keyed by "nft_contract||token_id", same as the market's sale keys
+    bids: Mutex<HashMap<String, HighestBid>>,
+    changes: UndoLog<String, HighestBid>,
+}
+
+fn bid_key(contract_id: &str, token_id: &str) -> String {
+    format!("{}||{}", contract_id, token_id)
+}
+
+impl BidBook {
+    pub(crate) fn load(home_dir: &Path) -> Self {
+        let path = bids_path(home_dir);
+        let bids = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                eprintln!("Ignoring unreadable bids file --> {:?}", err);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self { path: Some(path), bids: Mutex::new(bids), changes: UndoLog::default() }
+    }
+
+    //stop saving the book, for dry runs and replays which mustn't touch the real one
+    pub(crate) fn keep_in_memory(&mut self) {
+        self.path = None;
+    }
+
+    pub(crate) fn highest_bid(&self, contract_id: &str, token_id: &str) -> Option<HighestBid> {
+        self.bids.lock().unwrap().get(&bid_key(contract_id, token_id)).cloned()
+    }
+
+    //record a bid, keeping it only if it is higher than the standing one
+    pub(crate) fn place_bid(&self, contract_id: &str, token_id: &str, bid: HighestBid) -> BidOutcome {
+        let mut bids = self.bids.lock().unwrap();
+        let key = bid_key(contract_id, token_id);
+        let outcome = match bids.get(&key) {
+            None => BidOutcome::First,
+            //a bid in another FT can't be compared, the new one replaces it
+            Some(standing) if standing.ft_token_id != bid.ft_token_id || bid.amount() > standing.amount() => {
+                BidOutcome::Outbid(standing.clone())
+            }
+            Some(standing) => return BidOutcome::NotHighest(standing.clone()),
+        };
+        self.changes.insert(&mut bids, key, bid);
+        self.save(&bids);
+        outcome
+    }
+
+    //forget the bids on a token once its sale is settled, returning the winning bid
+    pub(crate) fn settle(&self, contract_id: &str, token_id: &str) -> Option<HighestBid> {
+        let mut bids = self.bids.lock().unwrap();
+        let settled = self.changes.remove(&mut bids, &bid_key(contract_id, token_id));
+        if settled.is_some() {
+            self.save(&bids);
+        }
+        settled
+    }
+
+    //the block left the chain, its bids and settled sales never happened
+    pub(crate) fn undo_block(&self, block_hash: &str) {
+        let mut bids = self.bids.lock().unwrap();
+        if self.changes.undo_block(block_hash, &mut bids) {
+            self.save(&bids);
+        }
+    }
+
+    pub(crate) fn forget_blocks(&self, block_hashes: &[String]) {
+        self.changes.forget_blocks(block_hashes);
+    }
+
+    fn save(&self, bids: &HashMap<String, HighestBid>) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        //write to a temporary file first so a crash can't leave half a file behind
+        let tmp_path = path.with_extension("json.tmp");
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&tmp_path, serde_json::to_vec_pretty(bids)?))
+            .and_then(|_| std::fs::rename(&tmp_path, path));
+        if let Err(err) = saved {
+            eprintln!("Could not save bids to {:?} --> {:?}", path, err);
+        }
+    }
+}
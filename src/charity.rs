@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::state_dir;
+use crate::human;
+
+// ------------------------------- CHARITY LEDGER ----------------------------------
+// every sale gives a share to the charity of the token. the share is taken from the
+//  on-chain payout when there is one, otherwise it is worked out from the split set in
+//  the CHARITY_SPLIT_PERCENT env variable. each donation is sent through the sink and
+//  appended to a local ledger that `charity-report` aggregates. donations of blocks that
+//  leave the chain are marked as reverted in the ledger, so the report stops counting them.
+
+pub(crate) fn charity_ledger_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("charity_ledger.jsonl")
+}
+
+//where the charity share of a sale came from
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DonationSource {
+    //the payout returned by the contract
+    Payout,
+    //the configured split applied to the sale price
+    Split,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct CharityDonation {
+    pub charity_account_id: String,
+    pub token_id: String,
+    pub contract_id: String,
+    //in yocto
+    pub amount: String,
+    pub amount_near: f64,
+    pub source: DonationSource,
+    pub receipt_id: String,
+    pub block_height: u64,
+    //nanoseconds since the unix epoch
+    pub block_timestamp: u64,
+}
+
+impl CharityDonation {
+    fn amount(&self) -> u128 {
+        self.amount.parse().unwrap_or(0)
+    }
+}
+
+//the charity share of a sale price, for a split given in percent (kept to a hundredth of a percent)
+pub(crate) fn charity_share(price: u128, split_percent: f64) -> u128 {
+    let split_basis_points = (split_percent * 100.0).round().max(0.0) as u128;
+    price / 10_000 * split_basis_points + price % 10_000 * split_basis_points / 10_000
+}
+
+//a donation whose block left the chain (see finality.rs), it no longer counts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RevertedDonation {
+    reverted_receipt_id: String,
+    charity_account_id: String,
+    token_id: String,
+}
+
+//a line of the ledger, the reverted ones carry `reverted_receipt_id` instead of a full donation
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LedgerLine {
+    Reverted(RevertedDonation),
+    Donation(CharityDonation),
+}
+
+fn append_to_ledger(home_dir: &Path, line: &impl Serialize) -> std::io::Result<()> {
+    std::fs::create_dir_all(state_dir(home_dir))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(charity_ledger_path(home_dir))?;
+    writeln!(file, "{}", serde_json::to_string(line)?)
+}
+
+pub(crate) fn record_donation(home_dir: &Path, donation: &CharityDonation) {
+    if let Err(err) = append_to_ledger(home_dir, donation) {
+        eprintln!("Could not add donation {:?} to the charity ledger --> {:?}", donation, err);
+    }
+}
+
+//the ledger is append only, so a reverted donation is written down and left out when reading it back
+pub(crate) fn record_reverted_donation(home_dir: &Path, donation: &CharityDonation) {
+    let reverted = RevertedDonation {
+        reverted_receipt_id: donation.receipt_id.clone(),
+        charity_account_id: donation.charity_account_id.clone(),
+        token_id: donation.token_id.clone(),
+    };
+    if let Err(err) = append_to_ledger(home_dir, &reverted) {
+        eprintln!("Could not mark donation {:?} as reverted in the charity ledger --> {:?}", donation, err);
+    }
+}
+
+//every donation in the ledger that still counts, once (replaying blocks can record the same donation twice)
+pub(crate) fn read_ledger(home_dir: &Path) -> Vec<CharityDonation> {
+    let contents = std::fs::read_to_string(charity_ledger_path(home_dir)).unwrap_or_default();
+    let mut seen = HashSet::new();
+    let mut donations: Vec<CharityDonation> = Vec::new();
+    for line in contents.lines() {
+        match serde_json::from_str::<LedgerLine>(line) {
+            Ok(LedgerLine::Donation(donation)) => {
+                if seen.insert((donation.receipt_id.clone(), donation.charity_account_id.clone(), donation.token_id.clone())) {
+                    donations.push(donation);
+                }
+            }
+            //the receipt can still land in a later block, so only what came before is dropped
+            Ok(LedgerLine::Reverted(reverted)) => {
+                let key = (reverted.reverted_receipt_id, reverted.charity_account_id, reverted.token_id);
+                if seen.remove(&key) {
+                    donations.retain(|donation| {
+                        !(donation.receipt_id == key.0 && donation.charity_account_id == key.1 && donation.token_id == key.2)
+                    });
+                }
+            }
+            Err(err) => eprintln!("Skipping unreadable charity ledger line --> {:?}", err),
+        }
+    }
+    donations
+}
+
+//the periods donations can be grouped by in the report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ReportPeriod {
+    Day,
+    Month,
+    Year,
+    //a single total per charity
+    All,
+}
+
+impl FromStr for ReportPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(ReportPeriod::Day),
+            "month" => Ok(ReportPeriod::Month),
+            "year" => Ok(ReportPeriod::Year),
+            "all" => Ok(ReportPeriod::All),
+            _ => Err(format!("unknown report period {:?}, expected \"day\", \"month\", \"year\" or \"all\"", s)),
+        }
+    }
+}
+
+impl ReportPeriod {
+    //the period a block timestamp falls in, e.g. "2021-06" for a month
+    fn label(&self, block_timestamp: u64) -> String {
+        let time = Utc.timestamp_nanos(block_timestamp as i64);
+        match self {
+            ReportPeriod::Day => time.format("%Y-%m-%d").to_string(),
+            ReportPeriod::Month => time.format("%Y-%m").to_string(),
+            ReportPeriod::Year => time.format("%Y").to_string(),
+            ReportPeriod::All => "all".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct CharityTotal {
+    pub charity_account_id: String,
+    pub period: String,
+    //in yocto
+    pub amount: String,
+    pub amount_near: f64,
+    pub donations: usize,
+}
+
+//total donations per charity and period, sorted by charity then period
+pub(crate) fn aggregate_donations(donations: &[CharityDonation], period: ReportPeriod) -> Vec<CharityTotal> {
+    let mut totals: BTreeMap<(String, String), (u128, usize)> = BTreeMap::new();
+    for donation in donations {
+        let key = (donation.charity_account_id.clone(), period.label(donation.block_timestamp));
+        let total = totals.entry(key).or_default();
+        total.0 += donation.amount();
+        total.1 += 1;
+    }
+    totals
+        .into_iter()
+        .map(|((charity_account_id, period), (amount, donations))| CharityTotal {
+            charity_account_id,
+            period,
+            amount: amount.to_string(),
+            amount_near: human(amount),
+            donations,
+        })
+        .collect()
+}
+
+pub(crate) fn print_report(totals: &[CharityTotal]) {
+    if totals.is_empty() {
+        println!("No donations in the charity ledger yet");
+        return;
+    }
+    println!("{:<40} {:<12} {:>16} {:>10}", "CHARITY", "PERIOD", "NEAR", "DONATIONS");
+    for total in totals {
+        println!(
+            "{:<40} {:<12} {:>16.4} {:>10}",
+            total.charity_account_id, total.period, total.amount_near, total.donations
+        );
+    }
+}
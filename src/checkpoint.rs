@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// ------------------------------- CHECKPOINT ----------------------------------
+// the checkpoint is rewritten with the status after every block and when the indexer
+//  shuts down. it remembers the last block we finished handling and the blocks that were
+//  still in flight or held back, so the next run can start streaming again from the first
+//  unfinished block.
+
+//directory (inside the near home dir) where the indexer keeps its own files
+pub(crate) fn state_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join("fayyr-indexer")
+}
+
+fn checkpoint_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("checkpoint.json")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct Checkpoint {
+    pub last_processed_height: u64,
+    //blocks that were streamed to us but not fully handled
+    pub unfinished_heights: Vec<u64>,
+}
+
+impl Checkpoint {
+    //the block the next run should start from, if some blocks were left unfinished
+    pub(crate) fn resume_height(&self) -> Option<u64> {
+        self.unfinished_heights.iter().min().copied()
+    }
+}
+
+//where `run` was asked to start
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StartFrom {
+    Latest,
+    Height(u64),
+    //where the last run stopped, going back `delta` blocks
+    Interruption { delta: u64 },
+}
+
+//where streaming actually starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StartAt {
+    Latest,
+    Height(u64),
+}
+
+//work out the first block to stream from what was asked, the checkpoint and the
+//  last block handled (which the status file keeps up to date even after a crash)
+pub(crate) fn start_at(start_from: StartFrom, checkpoint: Option<&Checkpoint>, last_handled_height: Option<u64>) -> StartAt {
+    let delta = match start_from {
+        StartFrom::Latest => return StartAt::Latest,
+        StartFrom::Height(height) => return StartAt::Height(height),
+        StartFrom::Interruption { delta } => delta,
+    };
+    //a checkpoint older than the status was left behind by a run that went on without it
+    let checkpoint = checkpoint.filter(|checkpoint| {
+        last_handled_height.map_or(true, |last_handled_height| last_handled_height <= checkpoint.last_processed_height)
+    });
+    //blocks left unfinished by the last run come first
+    if let Some(resume_height) = checkpoint.and_then(|checkpoint| checkpoint.resume_height()) {
+        return StartAt::Height(resume_height.saturating_sub(delta));
+    }
+    let last_handled_height = checkpoint
+        .map(|checkpoint| checkpoint.last_processed_height)
+        .into_iter()
+        .chain(last_handled_height)
+        .max()
+        .filter(|height| *height > 0);
+    match last_handled_height {
+        //the block after the last one handled, not the node's own marker which moves during dry runs
+        Some(last_handled_height) => StartAt::Height((last_handled_height + 1).saturating_sub(delta)),
+        //we never ran from this home dir, don't replay the chain from genesis
+        None => StartAt::Latest,
+    }
+}
+
+pub(crate) fn load_checkpoint(home_dir: &Path) -> Option<Checkpoint> {
+    let contents = std::fs::read_to_string(checkpoint_path(home_dir)).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(checkpoint) => Some(checkpoint),
+        Err(err) => {
+            eprintln!("Ignoring unreadable checkpoint file --> {:?}", err);
+            None
+        }
+    }
+}
+
+pub(crate) fn save_checkpoint(home_dir: &Path, checkpoint: &Checkpoint) -> std::io::Result<()> {
+    std::fs::create_dir_all(state_dir(home_dir))?;
+    //write to a temporary file first so a crash can't leave half a checkpoint behind
+    let path = checkpoint_path(home_dir);
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(checkpoint)?)?;
+    std::fs::rename(tmp_path, path)
+}
@@ -1,7 +1,17 @@
+use std::collections::BTreeMap;
+
 use clap::Clap;
 
 use tracing_subscriber::EnvFilter;
 
+use crate::analytics::StatsPeriod;
+use crate::charity::ReportPeriod;
+use crate::checkpoint::StartFrom;
+use crate::dry_run::{dry_run_path, DryRun, DryRunFormat};
+use crate::finality::FinalityMode;
+use crate::settings::localnet_market_settings;
+use crate::viewer::ViewFallback;
+
 /// NEAR Indexer Example
 /// Watches for stream of blocks from the chain
 #[derive(Clap, Debug)]
@@ -17,9 +27,185 @@ pub(crate) struct Opts {
 #[derive(Clap, Debug)]
 pub(crate) enum SubCommand {
     /// Run NEAR Indexer Example. Start observe the network
-    Run,
+    Run(RunArgs),
     /// Initialize necessary configs
     Init(InitConfigArgs),
+    /// Feed blocks recorded with `run --record` through the handlers, without a node
+    Replay(ReplayArgs),
+    /// Print the donations in the charity ledger, totalled per charity and period
+    CharityReport(CharityReportArgs),
+    /// Check the home dir, settings, tracked shards, contracts, API and disk space, and say how to fix what's wrong
+    Doctor(DoctorArgs),
+    /// Show how far the indexer got, from the home dir (works whether it is running or not).
+    /// The head of the chain is only shown while a node answers RPC calls
+    Status(StatusArgs),
+    /// Print (or write to a directory) the JSON Schema of the body POSTed to every API endpoint
+    Schema(SchemaArgs),
+    /// Print the floor price, volume and sale prices of the collections, per hour or day
+    SaleStats(SaleStatsArgs),
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct RunArgs {
+    /// Address the /healthz and /readyz endpoints listen on
+    #[clap(long, default_value = "0.0.0.0:3030")]
+    pub health_addr: std::net::SocketAddr,
+    /// Report not ready when the last processed block is older than this many seconds
+    #[clap(long, default_value = "120")]
+    pub max_block_age_secs: u64,
+    /// Report not ready when more than this many blocks are waiting to be handled or to have their events sent
+    #[clap(long, default_value = "100")]
+    pub max_backlog: usize,
+    /// Report not ready when the share of failed API calls goes above this (0.0 - 1.0)
+    #[clap(long, default_value = "0.5")]
+    pub max_sink_error_rate: f64,
+    /// Seconds to wait for in-flight blocks to finish after SIGINT/SIGTERM before exiting
+    #[clap(long, default_value = "30")]
+    pub shutdown_timeout_secs: u64,
+    /// Milliseconds to wait for a view call to the node before giving up on it
+    #[clap(long, default_value = "5000")]
+    pub view_timeout_ms: u64,
+    /// How many view call results to keep cached
+    #[clap(long, default_value = "1000")]
+    pub view_cache_size: usize,
+    /// What to do when the state of a block is no longer available for view calls ("fail" or "latest").
+    /// "latest" answers from the current state, which can misclassify old receipts
+    #[clap(long, default_value = "fail")]
+    pub view_fallback: ViewFallback,
+    /// Append every block (and the view calls made for it) to this newline-delimited JSON file
+    #[clap(long)]
+    pub record: Option<std::path::PathBuf>,
+    /// When to deliver the events of a block: "none" (right away), "final" (once the block is final) or a number of blocks to wait for on top of it
+    #[clap(long, default_value = "none")]
+    pub finality: FinalityMode,
+    /// Start from the latest block of the node, skipping everything before it
+    #[clap(long)]
+    pub from_latest: bool,
+    /// Start from this block height
+    #[clap(long)]
+    pub from_height: Option<u64>,
+    /// Start from where the last run stopped (the default, or the latest block if the indexer never ran)
+    #[clap(long)]
+    pub from_interruption: bool,
+    /// With --from-interruption, start this many blocks before where the last run stopped
+    #[clap(long, default_value = "0")]
+    pub delta: u64,
+    /// Wait until the node is fully synced before streaming blocks, instead of streaming while it syncs
+    #[clap(long)]
+    pub wait_for_sync: bool,
+    /// Seconds between two pushes of the collection stats (floor, volume, sale prices) to the API
+    #[clap(long, default_value = "300")]
+    pub stats_interval_secs: u64,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+impl RunArgs {
+    pub(crate) fn start_from(&self) -> Result<StartFrom, String> {
+        let chosen = [self.from_latest, self.from_height.is_some(), self.from_interruption];
+        if chosen.iter().filter(|chosen| **chosen).count() > 1 {
+            return Err("only one of --from-latest, --from-height and --from-interruption can be given".to_string());
+        }
+        if self.delta > 0 && (self.from_latest || self.from_height.is_some()) {
+            return Err("--delta only goes with --from-interruption".to_string());
+        }
+        Ok(match (self.from_latest, self.from_height) {
+            (true, _) => StartFrom::Latest,
+            (_, Some(height)) => StartFrom::Height(height),
+            _ => StartFrom::Interruption { delta: self.delta },
+        })
+    }
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct ReplayArgs {
+    /// Recording made with `run --record`
+    #[clap(long)]
+    pub file: std::path::PathBuf,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct DryRunArgs {
+    /// Print the events and write them to a file instead of sending them to the API
+    #[clap(long)]
+    pub dry_run: bool,
+    /// File the dry run writes the events to, one JSON object per line (fayyr-indexer/dry-run.jsonl in the home dir by default)
+    #[clap(long)]
+    pub dry_run_file: Option<std::path::PathBuf>,
+    /// How the dry run prints the events ("pretty" or "json")
+    #[clap(long, default_value = "pretty")]
+    pub dry_run_format: DryRunFormat,
+}
+
+impl DryRunArgs {
+    pub(crate) fn open(&self, home_dir: &std::path::Path) -> Option<DryRun> {
+        if !self.dry_run {
+            return None;
+        }
+        let path = self.dry_run_file.clone().unwrap_or_else(|| dry_run_path(home_dir));
+        let dry_run = DryRun::create(&path, self.dry_run_format)
+            .unwrap_or_else(|err| panic!("Could not create the dry run file {:?}: {:?}", path, err));
+        eprintln!("Dry run, the events go to {:?} instead of the API", path);
+        Some(dry_run)
+    }
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct CharityReportArgs {
+    /// Period to total the donations over ("day", "month", "year" or "all")
+    #[clap(long, default_value = "month")]
+    pub period: ReportPeriod,
+    /// Only report the donations to this charity account
+    #[clap(long)]
+    pub charity: Option<String>,
+    /// Print the report as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct DoctorArgs {
+    /// NEAR RPC to look the contract accounts up with. Defaults to the RPC of the local node
+    #[clap(long)]
+    pub rpc_url: Option<String>,
+    /// Fail when the disk holding the home dir has less free space than this, in GB
+    #[clap(long, default_value = "20")]
+    pub min_free_disk_gb: u64,
+    /// Print the checks as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct StatusArgs {
+    /// NEAR RPC to ask the head of the chain. Defaults to the RPC of the local node, which has to be running
+    #[clap(long)]
+    pub rpc_url: Option<String>,
+    /// Print the status as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct SaleStatsArgs {
+    /// Period the stats are rolled up over ("hour" or "day")
+    #[clap(long, default_value = "day")]
+    pub period: StatsPeriod,
+    /// Only show the stats of this nft contract
+    #[clap(long)]
+    pub contract: Option<String>,
+    /// Print the stats as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct SchemaArgs {
+    /// Write one <endpoint>.json file per endpoint into this directory instead of printing them
+    #[clap(long)]
+    pub out_dir: Option<std::path::PathBuf>,
 }
 
 #[derive(Clap, Debug)]
@@ -58,6 +244,21 @@ pub(crate) struct InitConfigArgs {
     pub download_config_url: Option<String>,
     #[clap(long)]
     pub max_gas_burnt_view: Option<u64>,
+    /// Set up for the local testing guide: localnet, NFT contract on test.near, market on market.test.near and the API on localhost:3000
+    #[clap(long)]
+    pub localnet_market: bool,
+    /// NFT contract to put in the starter indexer settings
+    #[clap(long)]
+    pub nft_contract: Option<String>,
+    /// Market contract to put in the starter indexer settings
+    #[clap(long)]
+    pub market_contract: Option<String>,
+    /// Admin account to put in the starter indexer settings
+    #[clap(long)]
+    pub admin_account: Option<String>,
+    /// Root of the API the events are sent to, used for both PRIVATE_API and PUBLIC_API in the starter settings
+    #[clap(long)]
+    pub api_root: Option<String>,
 }
 
 pub(crate) fn init_logging() {
@@ -70,6 +271,26 @@ pub(crate) fn init_logging() {
         .init();
 }
 
+impl InitConfigArgs {
+    //what to write in the starter indexer settings, the flags win over the --localnet-market preset
+    pub(crate) fn starter_settings(&self) -> BTreeMap<String, String> {
+        let mut settings = if self.localnet_market { localnet_market_settings() } else { BTreeMap::new() };
+        let given = [
+            ("NFT", &self.nft_contract),
+            ("MARKET", &self.market_contract),
+            ("ADMIN", &self.admin_account),
+            ("PRIVATE_API", &self.api_root),
+            ("PUBLIC_API", &self.api_root),
+        ];
+        for (name, value) in given.iter() {
+            if let Some(value) = value {
+                settings.insert(name.to_string(), value.clone());
+            }
+        }
+        settings
+    }
+}
+
 impl From<InitConfigArgs> for near_indexer::InitConfigArgs {
     fn from(config_args: InitConfigArgs) -> Self {
         Self {
@@ -1,16 +1,49 @@
 #![allow(non_snake_case)]
 use reqwest::Error;
 use reqwest::StatusCode;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
 
+use schemars::schema::RootSchema;
+use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 
+use crate::activity::{ActivityAction, ActivityRole};
+use crate::analytics::CollectionStats;
+use crate::charity::CharityDonation;
+use crate::payouts::PayoutRole;
+use crate::storage::StorageChange;
+
 // ------------------------------- API CALLS ----------------------------------
-// this file was created to separate the handling of various API calls into their 
+// this file was created to separate the handling of various API calls into their
 //  own functions, which can be called in main.rs if imported correctly (main.rs, line 31)
 // we have left examples of GET and POST methods to use as reference.
-
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+// the endpoint paths below are relative to the API root passed into each function,
+//  change them to match your own API.
+// the POST bodies below are what the API gets to see. every body goes out with the
+//  schema_version it follows, and `schema` dumps the JSON Schema of each of them.
+//  fields can be added without changing the version, anything that would break a
+//  consumer (removing or renaming a field, changing its type) needs a new SCHEMA_VERSION.
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub(crate) const REMOVE_FOR_SALE_ENDPOINT: &str = "tokens/forsale/remove";
+pub(crate) const SELL_TOKEN_ENDPOINT: &str = "tokens/sell";
+pub(crate) const INSERT_FOR_SALE_ENDPOINT: &str = "tokens/forsale/insert";
+pub(crate) const UPDATE_PRICE_ENDPOINT: &str = "tokens/forsale/price";
+pub(crate) const MINTED_TOKEN_ENDPOINT: &str = "tokens/minted";
+pub(crate) const MINT_TOKEN_ENDPOINT: &str = "tokens/mint";
+pub(crate) const PLACE_BID_ENDPOINT: &str = "tokens/bids/place";
+pub(crate) const OUTBID_ENDPOINT: &str = "tokens/bids/outbid";
+pub(crate) const PAYOUT_ENDPOINT: &str = "tokens/payouts";
+pub(crate) const CHARITY_DONATION_ENDPOINT: &str = "charities/donations";
+pub(crate) const CONTRACT_CALL_ENDPOINT: &str = "contracts/calls";
+pub(crate) const ACCOUNT_ACTIVITY_ENDPOINT: &str = "accounts/activity";
+pub(crate) const STORAGE_BALANCE_ENDPOINT: &str = "accounts/storage";
+pub(crate) const REVERTED_ENDPOINT: &str = "events/reverted";
+pub(crate) const COLLECTION_STATS_ENDPOINT: &str = "collections/stats";
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
 pub struct MintedTokenPOSTBody {
     pub token_id: String,
     pub contract_id: String,
@@ -25,29 +58,175 @@ pub struct MintedTokenPOSTBody {
     pub description: String,
 }
 
-#[derive(Serialize, Debug)]
-struct InsertForSalePOSTBody {
-    token_id: String,
-    contract_id: String,
-    price_near: f64,
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct RemoveForSalePOSTBody {
+    pub token_id: String,
+    pub contract_id: String,
 }
 
-#[derive(Serialize, Debug)]
-struct UpdatePricePOSTBody {
-    token_id: String,
-    contract_id: String,
-    price_near: f64,
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct InsertForSalePOSTBody {
+    pub token_id: String,
+    pub contract_id: String,
+    pub price_near: f64,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct UpdatePricePOSTBody {
+    pub token_id: String,
+    pub contract_id: String,
+    pub price_near: f64,
 }
 
 
-#[derive(Serialize, Debug)]
-struct SellTokenPostBody {
-    token_id: String,
-    contract_id: String,
-    purchaser_account_id: String,
-    price_near: Option<f64>,
-    admin_account_id: String,
-    receipt_id: String,
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct SellTokenPostBody {
+    pub token_id: String,
+    pub contract_id: String,
+    pub purchaser_account_id: String,
+    pub price_near: Option<f64>,
+    pub admin_account_id: String,
+    pub receipt_id: String,
+    pub lazy: bool,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct BidPOSTBody {
+    pub token_id: String,
+    pub contract_id: String,
+    pub bidder_account_id: String,
+    pub ft_token_id: String,
+    pub amount: String,
+    pub receipt_id: Option<String>,
+    pub outbid_by_account_id: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct PayoutPOSTBody {
+    pub token_id: String,
+    pub contract_id: String,
+    pub recipient_account_id: String,
+    pub role: PayoutRole,
+    pub amount: String,
+    pub amount_near: f64,
+    pub receipt_id: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct ContractCallPOSTBody {
+    pub contract_id: String,
+    pub method_name: String,
+    pub signer_id: String,
+    pub predecessor_id: String,
+    pub deposit: String,
+    pub receipt_id: String,
+    pub block_height: u64,
+    pub fields: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct AccountActivityPOSTBody {
+    pub account_id: String,
+    pub role: ActivityRole,
+    pub receipt_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub signer_id: String,
+    pub predecessor_id: String,
+    pub receiver_id: String,
+    pub action: ActivityAction,
+    pub success: bool,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct StorageBalancePOSTBody {
+    pub account_id: String,
+    pub change: StorageChange,
+    pub amount: Option<String>,
+    pub balance: String,
+    pub balance_near: f64,
+    pub listings: u64,
+    pub listings_affordable: u64,
+    pub listing_would_fail: bool,
+    pub receipt_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct CollectionStatsPOSTBody {
+    pub collections: Vec<CollectionStats>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct RevertedPOSTBody {
+    pub block_height: u64,
+    pub block_hash: String,
+    pub event: serde_json::Value,
+}
+
+// a POST body along with the version of the schema it follows
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct VersionedPOSTBody<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+// the schema of the body POSTed to each endpoint
+pub fn endpoint_schemas() -> Vec<(&'static str, RootSchema)> {
+    vec![
+        (MINT_TOKEN_ENDPOINT, schema_for!(VersionedPOSTBody<MintedTokenPOSTBody>)),
+        (INSERT_FOR_SALE_ENDPOINT, schema_for!(VersionedPOSTBody<InsertForSalePOSTBody>)),
+        (UPDATE_PRICE_ENDPOINT, schema_for!(VersionedPOSTBody<UpdatePricePOSTBody>)),
+        (REMOVE_FOR_SALE_ENDPOINT, schema_for!(VersionedPOSTBody<RemoveForSalePOSTBody>)),
+        (SELL_TOKEN_ENDPOINT, schema_for!(VersionedPOSTBody<SellTokenPostBody>)),
+        (PLACE_BID_ENDPOINT, schema_for!(VersionedPOSTBody<BidPOSTBody>)),
+        (OUTBID_ENDPOINT, schema_for!(VersionedPOSTBody<BidPOSTBody>)),
+        (PAYOUT_ENDPOINT, schema_for!(VersionedPOSTBody<PayoutPOSTBody>)),
+        (CHARITY_DONATION_ENDPOINT, schema_for!(VersionedPOSTBody<CharityDonation>)),
+        (CONTRACT_CALL_ENDPOINT, schema_for!(VersionedPOSTBody<ContractCallPOSTBody>)),
+        (ACCOUNT_ACTIVITY_ENDPOINT, schema_for!(VersionedPOSTBody<AccountActivityPOSTBody>)),
+        (STORAGE_BALANCE_ENDPOINT, schema_for!(VersionedPOSTBody<StorageBalancePOSTBody>)),
+        (REVERTED_ENDPOINT, schema_for!(VersionedPOSTBody<RevertedPOSTBody>)),
+        (COLLECTION_STATS_ENDPOINT, schema_for!(VersionedPOSTBody<CollectionStatsPOSTBody>)),
+    ]
+}
+
+// POST the body to the API and turn any non-success status into an error
+async fn post_to_api<T: Serialize + Debug>(
+    final_url: String,
+    PostBody: &T,
+    SIGNATURE_HEADER: &str,
+    debug_mode: &str,
+    action: &str,
+) -> Result<(), Error> {
+    let PostBody = VersionedPOSTBody { schema_version: SCHEMA_VERSION, body: PostBody };
+    if debug_mode == "TRUE" {
+        println!(
+            "Passing In This Body To {} --> {:?} With this signature header: {:?}",
+            action, PostBody, SIGNATURE_HEADER);
+    }
+
+    // make POST request
+    let client = reqwest::Client::new();
+    let res = client
+        .post(final_url)
+        .header("Signature", SIGNATURE_HEADER)
+        .json(&PostBody)
+        .send()
+        .await?;
+
+    match res.status() {
+        StatusCode::OK => println!("Success when trying to {}", action),
+
+        s => println!(
+            "Received response status when trying to {} --> {:?}: {:?} | {:?}",
+            action, PostBody, s, res
+        ),
+    };
+    res.error_for_status()?;
+    Ok(())
 }
 
 pub async fn remove_token_forsale_in_database(
@@ -61,38 +240,14 @@ pub async fn remove_token_forsale_in_database(
     let clean_token_id = str::replace(&tok_id, '"', "");
     let clean_contract_id = str::replace(&contr_id, '"', "");
 
-    eprintln!("Please Add Your API Endpoint and Uncomment Lines 67-94");
+    let final_url = format!("{}/{}", URL, REMOVE_FOR_SALE_ENDPOINT);
 
-//    let final_url = format!("YOUR API ENDPOINT HERE!", URL); 
-
-    // This will POST a body defined by the hashmap
-//    let mut map = HashMap::new();
-//    map.insert("token_id", clean_token_id);
-//    map.insert("contract_id", clean_contract_id);
-    // make POST request
-//    let client = reqwest::Client::new();
-//    let res = client
-//        .post(final_url)
-//        .header("Signature", SIGNATURE_HEADER.clone())
-//        .json(&map)
-//        .send()
-//        .await?;
-
-//    match res.status() {
-//        StatusCode::OK => println!("Success when removing token for sale in database"),
-
-//        s => println!(
-//            "Received response status when removing token for sale in db --> {:?}: {:?} | {:?}",
-//            map, s, res
-//        ),
-//    };
-//    if debug_mode == "TRUE".to_string() {
-//        println!(
-//            "Passing In This Body To Remove Token For Sale --> {:?} With this signature header: {:?}",
-//            map, SIGNATURE_HEADER);
-//    }
+    let PostBody = RemoveForSalePOSTBody {
+        token_id: clean_token_id,
+        contract_id: clean_contract_id,
+    };
 
-    Ok(())
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Remove Token For Sale").await
 }
 
 pub async fn sell_token_in_database(
@@ -102,6 +257,7 @@ pub async fn sell_token_in_database(
     purchaser_account_id: String,
     admin_account: String,
     blockchain_receipt_id: String,
+    lazy: bool,
     SIGNATURE_HEADER: String,
     URL: &str,
     debug_mode: String,
@@ -119,34 +275,12 @@ pub async fn sell_token_in_database(
         admin_account_id: admin_account,
         receipt_id: clean_blockchain_receipt_id,
         price_near,
+        lazy,
     };
 
-    eprintln!("Please Add Your API Endpoint and Uncomment Lines 126-150");
-//    let final_url = format!("YOUR URL ENDPOINT HERE !!", URL);
+    let final_url = format!("{}/{}", URL, SELL_TOKEN_ENDPOINT);
 
-    // make POST request
-//    let client = reqwest::Client::new();
-//    let res = client
-//        .post(final_url)
-//        .header("Signature", SIGNATURE_HEADER.clone())
-//        .json(&PostBody)
-//        .send()
-//        .await?;
-
-//    match res.status() {
-//        StatusCode::OK => println!("Success when selling token in database"),
-
-//        s => println!(
-//            "Received response status when selling token in database --> {:?}: {:?} | {:?}",
-//            PostBody, s, res
-//        ),
-//    };
-//    if debug_mode == "TRUE".to_string() {
-//        println!(
-//            "Passing In This Body To Sell NFT --> {:?} With this signature header: {:?}",
-//            PostBody, SIGNATURE_HEADER);
-//    }
-    Ok(())
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Sell NFT").await
 }
 
 pub async fn insert_token_forsale_in_database(
@@ -167,30 +301,9 @@ pub async fn insert_token_forsale_in_database(
         price_near: price,
     };
 
-    eprintln!("Please Add Your API Endpoint and Uncomment Lines 171-194");
-//    let final_url = format!("YOUR URL ENPOINT HERE!!", URL);
-
-    //make POST request
-//    let client = reqwest::Client::new();
-//    let res = client
-//        .post(final_url)
-//        .header("Signature", SIGNATURE_HEADER.clone())
-//        .json(&PostBody)
-//        .send()
-//        .await?;
-
-//    match res.status() {
-//            StatusCode::OK => println!("Success when inserting token for sale in database!"),
-                                  
-//            s => println!("Received response status when inserting token for sale in database --> {:?}: {:?} | {:?}", PostBody, s, res),
-//        };
-    
-//    if debug_mode == "TRUE".to_string() {
-//        println!(
-//            "Passing In This Body To Insert Token For Sale --> {:?} With this signature header: {:?}",
-//            PostBody, SIGNATURE_HEADER);
-//    }
-    Ok(())
+    let final_url = format!("{}/{}", URL, INSERT_FOR_SALE_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Insert Token For Sale").await
 }
 
 pub async fn update_price_for_token_in_database(
@@ -211,32 +324,282 @@ pub async fn update_price_for_token_in_database(
         price_near: price,
     };
 
-    eprintln!("Please Add Your API Endpoint and Uncomment Lines 216-239");
-//    let final_url = format!("YOUR API URL ENDPOINT HERE!!", URL);
-
-    //make POST request
-//    let client = reqwest::Client::new();
-//    let res = client
-//        .post(final_url)
-//        .header("Signature", SIGNATURE_HEADER.clone())
-//        .json(&PostBody)
-//        .send()
-//        .await?;
-
-//    match res.status() {
-//        StatusCode::OK => println!("Success when updating price for token in database!"),
-
-//        s => println!(
-//            "Received response status when updating price for token in db --> {:?}: {:?} | {:?}",
-//            PostBody, s, res
-//        ),
-//    };
-//    if debug_mode == "TRUE".to_string() {
-//        println!(
-//            "Passing In This Body To Update Price --> {:?} With this signature header: {:?}",
-//            PostBody, SIGNATURE_HEADER);
-//    }
-    Ok(())
+    let final_url = format!("{}/{}", URL, UPDATE_PRICE_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Update Price").await
+}
+
+pub async fn insert_minted_token_in_database(
+    minted_token: MintedTokenPOSTBody,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    //cleaning up rust strings
+    let PostBody = MintedTokenPOSTBody {
+        token_id: str::replace(&minted_token.token_id, '"', ""),
+        contract_id: str::replace(&minted_token.contract_id, '"', ""),
+        ..minted_token
+    };
+
+    let final_url = format!("{}/{}", URL, MINT_TOKEN_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Insert Minted Token").await
+}
+
+pub async fn place_bid_in_database(
+    tok_id: String,
+    contr_id: String,
+    bidder_account_id: String,
+    ft_token_id: String,
+    amount: String,
+    blockchain_receipt_id: String,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    //cleaning up rust strings
+    let PostBody = BidPOSTBody {
+        token_id: str::replace(&tok_id, '"', ""),
+        contract_id: str::replace(&contr_id, '"', ""),
+        bidder_account_id: str::replace(&bidder_account_id, '"', ""),
+        ft_token_id,
+        amount,
+        receipt_id: Some(str::replace(&blockchain_receipt_id, '"', "")),
+        outbid_by_account_id: None,
+    };
+
+    let final_url = format!("{}/{}", URL, PLACE_BID_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Place Bid").await
+}
+
+pub async fn outbid_in_database(
+    tok_id: String,
+    contr_id: String,
+    bidder_account_id: String,
+    ft_token_id: String,
+    amount: String,
+    outbid_by_account_id: String,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    //cleaning up rust strings
+    let PostBody = BidPOSTBody {
+        token_id: str::replace(&tok_id, '"', ""),
+        contract_id: str::replace(&contr_id, '"', ""),
+        bidder_account_id: str::replace(&bidder_account_id, '"', ""),
+        ft_token_id,
+        amount,
+        receipt_id: None,
+        outbid_by_account_id: Some(str::replace(&outbid_by_account_id, '"', "")),
+    };
+
+    let final_url = format!("{}/{}", URL, OUTBID_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Mark Bid As Outbid").await
+}
+
+pub async fn record_payout_in_database(
+    tok_id: String,
+    contr_id: String,
+    recipient_account_id: String,
+    role: PayoutRole,
+    amount: String,
+    amount_near: f64,
+    blockchain_receipt_id: String,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    //cleaning up rust strings
+    let PostBody = PayoutPOSTBody {
+        token_id: str::replace(&tok_id, '"', ""),
+        contract_id: str::replace(&contr_id, '"', ""),
+        recipient_account_id: str::replace(&recipient_account_id, '"', ""),
+        role,
+        amount,
+        amount_near,
+        receipt_id: str::replace(&blockchain_receipt_id, '"', ""),
+    };
+
+    let final_url = format!("{}/{}", URL, PAYOUT_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Record Payout").await
+}
+
+pub async fn record_charity_donation_in_database(
+    donation: CharityDonation,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    let final_url = format!("{}/{}", URL, CHARITY_DONATION_ENDPOINT);
+
+    post_to_api(final_url, &donation, &SIGNATURE_HEADER, &debug_mode, "Record Charity Donation").await
+}
+
+pub async fn record_contract_call_in_database(
+    contract_id: String,
+    method_name: String,
+    signer_id: String,
+    predecessor_id: String,
+    deposit: String,
+    blockchain_receipt_id: String,
+    block_height: u64,
+    fields: BTreeMap<String, serde_json::Value>,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    let PostBody = ContractCallPOSTBody {
+        contract_id,
+        method_name,
+        signer_id,
+        predecessor_id,
+        deposit,
+        receipt_id: blockchain_receipt_id,
+        block_height,
+        fields,
+    };
+
+    let final_url = format!("{}/{}", URL, CONTRACT_CALL_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Record Contract Call").await
+}
+
+pub async fn record_account_activity_in_database(
+    account_id: String,
+    role: ActivityRole,
+    blockchain_receipt_id: String,
+    block_height: u64,
+    block_timestamp: u64,
+    signer_id: String,
+    predecessor_id: String,
+    receiver_id: String,
+    action: ActivityAction,
+    success: bool,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    let PostBody = AccountActivityPOSTBody {
+        account_id,
+        role,
+        receipt_id: blockchain_receipt_id,
+        block_height,
+        block_timestamp,
+        signer_id,
+        predecessor_id,
+        receiver_id,
+        action,
+        success,
+    };
+
+    let final_url = format!("{}/{}", URL, ACCOUNT_ACTIVITY_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Record Account Activity").await
+}
+
+pub async fn record_storage_balance_in_database(
+    account_id: String,
+    change: StorageChange,
+    amount: Option<String>,
+    balance: String,
+    balance_near: f64,
+    listings: u64,
+    listings_affordable: u64,
+    listing_would_fail: bool,
+    blockchain_receipt_id: String,
+    block_height: u64,
+    block_timestamp: u64,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    let PostBody = StorageBalancePOSTBody {
+        account_id,
+        change,
+        amount,
+        balance,
+        balance_near,
+        listings,
+        listings_affordable,
+        listing_would_fail,
+        receipt_id: blockchain_receipt_id,
+        block_height,
+        block_timestamp,
+    };
+
+    let final_url = format!("{}/{}", URL, STORAGE_BALANCE_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Record Storage Balance").await
+}
+
+// the event is sent as it was first delivered, the API finds what to undo from it
+pub async fn revert_event_in_database(
+    block_height: u64,
+    block_hash: String,
+    event: serde_json::Value,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    let PostBody = RevertedPOSTBody {
+        block_height,
+        block_hash,
+        event,
+    };
+
+    let final_url = format!("{}/{}", URL, REVERTED_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Revert Event").await
+}
+
+// the hourly and daily stats of the collections that changed since the last push
+pub async fn push_collection_stats_in_database(
+    collections: Vec<CollectionStats>,
+    SIGNATURE_HEADER: String,
+    URL: &str,
+    debug_mode: String,
+) -> Result<(), Error> {
+    let PostBody = CollectionStatsPOSTBody { collections };
+
+    let final_url = format!("{}/{}", URL, COLLECTION_STATS_ENDPOINT);
+
+    post_to_api(final_url, &PostBody, &SIGNATURE_HEADER, &debug_mode, "Push Collection Stats").await
+}
+
+// GET an endpoint with the signature header, to see whether the API is up and accepts the header.
+// any answer the API gives (even a 404 or 400 for the missing query) means it is reachable
+pub async fn check_api_in_database(
+    SIGNATURE_HEADER: String,
+    URL: &str,
+) -> Result<StatusCode, Error> {
+    let final_url: String = format!("{}/{}", URL, MINTED_TOKEN_ENDPOINT);
+
+    let client = reqwest::Client::new();
+    let res = client
+        .get(final_url)
+        .header("Signature", SIGNATURE_HEADER)
+        .send()
+        .await?;
+    Ok(res.status())
+}
+
+//why get_minted_token_from_database came back without a token
+#[derive(Debug)]
+pub enum MintedTokenError {
+    Request(Error),
+    //the API answered with an empty list
+    NotFound { token_id: String, contract_id: String },
+}
+
+impl From<Error> for MintedTokenError {
+    fn from(err: Error) -> Self {
+        MintedTokenError::Request(err)
+    }
 }
 
 // example of using reqwest::get to query information about a token and store the retrived information as a struct of your choosing
@@ -247,25 +610,30 @@ pub async fn get_minted_token_from_database(
     SIGNATURE_HEADER: String,
     URL: &str,
     debug_mode: String,
-) -> Result<MintedTokenPOSTBody, Error> {
+) -> Result<MintedTokenPOSTBody, MintedTokenError> {
     // cleaning up rust strings
     let clean_token_id = str::replace(&token_id, '"', "");
     let clean_contract = str::replace(&contract_id, '"', "");
 
-    eprintln!("Please Add Your API Endpoint and Uncomment Lines 257-272");
-//    let final_url: String = format!(
-//        "YOUR API URL ENDPOINT HERE",
-//        URL, clean_contract, clean_token_id
-//    )
-//    .to_string();
-
-//    let res = reqwest::get(final_url.clone()).await?;
-//    let json_body: Vec<MintedTokenPOSTBody> = res.json().await?;
-
-//    if debug_mode == "TRUE".to_string() {
-//        println!(
-//            "Passing In This URL To Get Minted Token From Database --> {:?}",
-//            final_url.clone());
-//    }
-//    Ok(json_body[0].clone())
+    let final_url: String = format!("{}/{}", URL, MINTED_TOKEN_ENDPOINT);
+
+    if debug_mode == "TRUE".to_string() {
+        println!(
+            "Passing In This URL To Get Minted Token From Database --> {:?} for {:?} on {:?}",
+            final_url.clone(), clean_token_id, clean_contract);
+    }
+
+    let client = reqwest::Client::new();
+    let res = client
+        .get(final_url)
+        .header("Signature", SIGNATURE_HEADER)
+        .query(&[("contract_id", clean_contract.clone()), ("token_id", clean_token_id.clone())])
+        .send()
+        .await?
+        .error_for_status()?;
+    let json_body: Vec<MintedTokenPOSTBody> = res.json().await?;
+    json_body.first().cloned().ok_or(MintedTokenError::NotFound {
+        token_id: clean_token_id,
+        contract_id: clean_contract,
+    })
 }
@@ -0,0 +1,316 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::abi::WatchConfig;
+use crate::checkpoint::state_dir;
+use crate::database::check_api_in_database;
+use crate::settings::{settings_path, Settings, REQUIRED_SETTINGS};
+use crate::shards::{check_tracked_shards, genesis_num_shards, node_tracked_shards, watched_shards};
+
+// ------------------------------- DOCTOR ----------------------------------
+// `doctor` looks for the usual setup mistakes before they turn into a panic or an
+//  opaque nearcore error: a missing home dir, settings that aren't set, the node not
+//  tracking the shards of our contracts, contracts that don't exist, an API that is
+//  down or refuses our signature header, and a disk about to fill up.
+// every check says what is wrong and how to fix it.
+
+//code hash of an account without a contract
+const NO_CONTRACT_CODE_HASH: &str = "11111111111111111111111111111111";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CheckStatus {
+    Ok,
+    Warning,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    //what to do about it, for warnings and failures
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, detail: String) -> Self {
+        Self { name: name.to_string(), status: CheckStatus::Ok, detail, fix: None }
+    }
+
+    fn warning(name: &str, detail: String, fix: String) -> Self {
+        Self { name: name.to_string(), status: CheckStatus::Warning, detail, fix: Some(fix) }
+    }
+
+    fn failed(name: &str, detail: String, fix: String) -> Self {
+        Self { name: name.to_string(), status: CheckStatus::Failed, detail, fix: Some(fix) }
+    }
+}
+
+fn read_near_config(home_dir: &Path) -> Result<serde_json::Value, String> {
+    let path = home_dir.join("config.json");
+    let contents = std::fs::read_to_string(&path).map_err(|err| format!("could not read {:?}: {}", path, err))?;
+    serde_json::from_str(&contents).map_err(|err| format!("invalid near config {:?}: {}", path, err))
+}
+
+//the files the node needs in the home dir, and the ones we keep next to them
+pub(crate) fn check_home_dir(home_dir: &Path) -> Vec<Check> {
+    let init_fix = format!("run `cargo run -- --home-dir {} init`", home_dir.display());
+    if !home_dir.is_dir() {
+        return vec![Check::failed("home dir", format!("{:?} does not exist", home_dir), init_fix)];
+    }
+    let mut checks = vec![Check::ok("home dir", format!("{:?}", home_dir))];
+
+    for file_name in &["config.json", "genesis.json", "node_key.json"] {
+        let name = format!("home dir: {}", file_name);
+        if home_dir.join(file_name).is_file() {
+            checks.push(Check::ok(&name, "present".to_string()));
+        } else {
+            checks.push(Check::failed(&name, "missing".to_string(), init_fix.clone()));
+        }
+    }
+    if let Err(err) = read_near_config(home_dir) {
+        checks.push(Check::failed("home dir: config.json", err, "fix the JSON, or move the file away and run init again".to_string()));
+    }
+    if !home_dir.join("data").is_dir() {
+        checks.push(Check::warning(
+            "home dir: data",
+            "the node has no data yet".to_string(),
+            "nothing to do if the indexer never ran, it is created on the first `run`".to_string(),
+        ));
+    }
+    if !state_dir(home_dir).is_dir() {
+        checks.push(Check::warning(
+            "home dir: fayyr-indexer",
+            format!("{:?} does not exist", state_dir(home_dir)),
+            "run init to get a starter indexer.json, the rest is created by `run`".to_string(),
+        ));
+    }
+    checks
+}
+
+//every setting the indexer needs, from the env or indexer.json
+pub(crate) fn check_settings(settings: &Settings) -> Vec<Check> {
+    let mut checks: Vec<Check> = REQUIRED_SETTINGS
+        .iter()
+        .map(|name| {
+            let check_name = format!("setting {}", name);
+            match settings.get(name) {
+                //the signature header is a secret, don't print it
+                Some(_) if *name == "HEADER" => Check::ok(&check_name, "set".to_string()),
+                Some(value) => Check::ok(&check_name, value),
+                None => Check::failed(
+                    &check_name,
+                    "not set".to_string(),
+                    format!("export {}=... or set it in fayyr-indexer/indexer.json", name),
+                ),
+            }
+        })
+        .collect();
+    if let Some(path) = settings.get("WATCH_CONFIG") {
+        checks.push(match WatchConfig::load(path.as_ref()) {
+            Ok(watch_config) => Check::ok("setting WATCH_CONFIG", format!("{} contracts watched", watch_config.contracts.len())),
+            Err(err) => Check::failed("setting WATCH_CONFIG", err, "fix the watch config, see Watching Other Contracts in the README".to_string()),
+        });
+    }
+    checks
+}
+
+//the contracts whose receipts we handle, as far as the settings say
+pub(crate) fn configured_contracts(settings: &Settings) -> Vec<String> {
+    let mut contracts: Vec<String> = settings.get("NFT").into_iter().chain(settings.get("MARKET")).collect();
+    if let Some(watch_config) = settings.get("WATCH_CONFIG").and_then(|path| WatchConfig::load(path.as_ref()).ok()) {
+        contracts.extend(watch_config.contracts.into_iter().map(|contract| contract.account_id));
+    }
+    contracts
+}
+
+//the node has to track the shards of our contracts
+pub(crate) fn check_tracked_shards_setting(home_dir: &Path, contracts: &[String]) -> Check {
+    const NAME: &str = "tracked_shards";
+    let (config, num_shards) = match (read_near_config(home_dir), genesis_num_shards(home_dir)) {
+        (Ok(config), Ok(num_shards)) => (config, num_shards),
+        (Err(err), _) | (_, Err(err)) => {
+            return Check::warning(NAME, format!("can't check: {}", err), "fix the home dir first".to_string())
+        }
+    };
+    if contracts.is_empty() {
+        return Check::warning(NAME, "can't check: NFT and MARKET are not set".to_string(), "set them first".to_string());
+    }
+    let number_list = |key: &str| -> Vec<u64> {
+        config[key].as_array().map(|list| list.iter().filter_map(|value| value.as_u64()).collect()).unwrap_or_default()
+    };
+    let string_list = |key: &str| -> Vec<String> {
+        config[key]
+            .as_array()
+            .map(|list| list.iter().filter_map(|value| value.as_str().map(|value| value.to_string())).collect())
+            .unwrap_or_default()
+    };
+    let tracked_shards = node_tracked_shards(&number_list("tracked_shards"), &string_list("tracked_accounts"), num_shards);
+    let contract_shards = watched_shards(contracts.iter().map(|contract| contract.as_str()), num_shards);
+    match check_tracked_shards(&contract_shards, &tracked_shards) {
+        Ok(()) => Check::ok(
+            NAME,
+            format!("tracking {:?}, our contracts are on {:?}", tracked_shards, contract_shards.keys().collect::<BTreeSet<_>>()),
+        ),
+        Err(err) => Check::failed(NAME, err, "restart the indexer after changing config.json".to_string()),
+    }
+}
+
+//the RPC of the local node, as set in its config
+pub(crate) fn node_rpc_url(home_dir: &Path) -> Option<String> {
+    let config = read_near_config(home_dir).ok()?;
+    let addr = config["rpc"]["addr"].as_str()?;
+    Some(format!("http://{}", addr.replace("0.0.0.0", "127.0.0.1")))
+}
+
+//the watched contracts exist and have code deployed
+pub(crate) async fn check_accounts(rpc_url: &str, contracts: &[String]) -> Vec<Check> {
+    let client = reqwest::Client::new();
+    let mut checks = vec![];
+    for contract in contracts {
+        let name = format!("account {}", contract);
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "doctor",
+            "method": "query",
+            "params": { "request_type": "view_account", "finality": "final", "account_id": contract },
+        });
+        let response = match client.post(rpc_url).json(&request).send().await {
+            Ok(response) => response.json::<serde_json::Value>().await,
+            Err(err) => Err(err),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                checks.push(Check::warning(
+                    &name,
+                    format!("can't check, no answer from the RPC at {}: {}", rpc_url, err),
+                    "start the indexer so its node answers RPC calls, or pass --rpc-url (e.g. https://rpc.testnet.near.org)".to_string(),
+                ));
+                continue;
+            }
+        };
+        checks.push(match response["result"]["code_hash"].as_str() {
+            Some(NO_CONTRACT_CODE_HASH) => Check::failed(
+                &name,
+                "exists but has no contract deployed".to_string(),
+                format!("deploy the contract with `near deploy --accountId {} ...`", contract),
+            ),
+            Some(_) => Check::ok(&name, "exists with a contract deployed".to_string()),
+            None => Check::failed(
+                &name,
+                format!("not found: {}", response["error"]),
+                "check the account id in the settings, and that the RPC is on the right network".to_string(),
+            ),
+        });
+    }
+    checks
+}
+
+//the API answers and doesn't turn our signature header down
+pub(crate) async fn check_api(settings: &Settings) -> Check {
+    const NAME: &str = "API";
+    let (api_root, signature_header) = match (settings.get("PRIVATE_API"), settings.get("HEADER")) {
+        (Some(api_root), Some(signature_header)) => (api_root, signature_header),
+        _ => return Check::warning(NAME, "can't check: PRIVATE_API and HEADER are not set".to_string(), "set them first".to_string()),
+    };
+    match check_api_in_database(signature_header, &api_root).await {
+        Ok(StatusCode::UNAUTHORIZED) | Ok(StatusCode::FORBIDDEN) => Check::failed(
+            NAME,
+            format!("{} refused the signature header", api_root),
+            "set HEADER to the signature the API expects".to_string(),
+        ),
+        Ok(status) if status.is_server_error() => Check::warning(
+            NAME,
+            format!("{} answered {}", api_root, status),
+            "look at the API logs".to_string(),
+        ),
+        Ok(status) => Check::ok(NAME, format!("{} answered {}", api_root, status)),
+        Err(err) => Check::failed(
+            NAME,
+            format!("{} is unreachable: {}", api_root, err),
+            "start the API, or fix PRIVATE_API".to_string(),
+        ),
+    }
+}
+
+//available bytes, from the output of `df -Pk`
+pub(crate) fn parse_df_available(output: &str) -> Option<u64> {
+    let available_kb: u64 = output.lines().nth(1)?.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available_kb * 1024)
+}
+
+pub(crate) fn check_disk(home_dir: &Path, min_free_gb: u64) -> Check {
+    const NAME: &str = "free disk space";
+    let path = if home_dir.exists() { home_dir } else { Path::new("/") };
+    let output = match Command::new("df").arg("-Pk").arg(path).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(err) => return Check::warning(NAME, format!("can't run df: {}", err), "check the free space by hand".to_string()),
+    };
+    let available = match parse_df_available(&output) {
+        Some(available) => available,
+        None => return Check::warning(NAME, "can't read the output of df".to_string(), "check the free space by hand".to_string()),
+    };
+    let available_gb = available / 1_000_000_000;
+    if available_gb < min_free_gb {
+        Check::failed(
+            NAME,
+            format!("{} GB left for {:?}", available_gb, path),
+            format!("free up space, the node needs at least {} GB to keep syncing", min_free_gb),
+        )
+    } else {
+        Check::ok(NAME, format!("{} GB left", available_gb))
+    }
+}
+
+//run every check
+pub(crate) async fn run_checks(home_dir: &Path, rpc_url: Option<String>, min_free_disk_gb: u64) -> Vec<Check> {
+    let mut checks = check_home_dir(home_dir);
+
+    let settings = match Settings::try_load(home_dir) {
+        Ok(settings) => settings,
+        Err(err) => {
+            checks.push(Check::failed(
+                "indexer settings",
+                err,
+                format!("fix the JSON in {:?}, or remove it and run init again", settings_path(home_dir)),
+            ));
+            return checks;
+        }
+    };
+    checks.extend(check_settings(&settings));
+
+    let contracts = configured_contracts(&settings);
+    checks.push(check_tracked_shards_setting(home_dir, &contracts));
+    match rpc_url.or_else(|| node_rpc_url(home_dir)) {
+        Some(rpc_url) => checks.extend(check_accounts(&rpc_url, &contracts).await),
+        None => checks.push(Check::warning(
+            "accounts",
+            "can't check, no RPC address in the near config".to_string(),
+            "pass --rpc-url".to_string(),
+        )),
+    }
+    checks.push(check_api(&settings).await);
+    checks.push(check_disk(home_dir, min_free_disk_gb));
+    checks
+}
+
+pub(crate) fn print_checks(checks: &[Check]) {
+    for check in checks {
+        let status = match check.status {
+            CheckStatus::Ok => "[ ok ]",
+            CheckStatus::Warning => "[warn]",
+            CheckStatus::Failed => "[FAIL]",
+        };
+        println!("{} {}: {}", status, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("       fix: {}", fix);
+        }
+    }
+}
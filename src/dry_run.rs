@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::checkpoint::state_dir;
+
+// ------------------------------- DRY RUN ----------------------------------
+// with --dry-run the sink prints every event instead of calling the API, and writes it
+//  to a file as one JSON object per line. the lines only hold the events themselves, so
+//  the files of two dry runs over the same blocks (e.g. before and after changing a
+//  handler) can be compared with diff.
+
+pub(crate) fn dry_run_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("dry-run.jsonl")
+}
+
+//how events are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DryRunFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for DryRunFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(DryRunFormat::Pretty),
+            "json" => Ok(DryRunFormat::Json),
+            _ => Err(format!("unknown dry run format {:?}, expected \"pretty\" or \"json\"", s)),
+        }
+    }
+}
+
+pub(crate) struct DryRun {
+    format: DryRunFormat,
+    file: Mutex<File>,
+}
+
+impl DryRun {
+    //starts a new file, a dry run never appends to the one of a previous run
+    pub(crate) fn create(path: &Path, format: DryRunFormat) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        Ok(Self { format, file: Mutex::new(file) })
+    }
+
+    //what would have been sent to the API
+    pub(crate) fn record(&self, event_name: &str, event: &serde_json::Value) {
+        match self.format {
+            DryRunFormat::Pretty => println!(
+                "[dry run] {}\n{}",
+                event_name,
+                serde_json::to_string_pretty(event).unwrap_or_default()
+            ),
+            DryRunFormat::Json => println!("{}", event),
+        }
+        if let Err(err) = writeln!(self.file.lock().unwrap(), "{}", event) {
+            eprintln!("Could not write the {} event to the dry run file --> {:?}", event_name, err);
+        }
+    }
+}
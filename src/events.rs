@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::activity::{ActivityAction, ActivityRole};
+use crate::charity::CharityDonation;
+use crate::database::MintedTokenPOSTBody;
+use crate::payouts::PayoutRole;
+use crate::storage::StorageChange;
+
+// ------------------------------- EVENTS ----------------------------------
+// the handlers in main.rs turn receipts into events, and the sink (sink.rs) turns
+//  each event into a call to the API. keeping the two apart means an event can be
+//  delivered, held back or printed without the handler having to know.
+// events are held back until their block is final enough (see finality.rs).
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event")]
+pub(crate) enum IndexerEvent {
+    //a token (or the base token of a multi-edition series) was minted
+    TokenMinted(MintedTokenPOSTBody),
+    //a token was put up for sale on the market
+    TokenListed {
+        token_id: String,
+        contract_id: String,
+        price_near: f64,
+        //nanoseconds since the unix epoch
+        block_timestamp: u64,
+    },
+    //the price of a listed token changed
+    PriceUpdated {
+        token_id: String,
+        contract_id: String,
+        price_near: f64,
+        block_timestamp: u64,
+    },
+    //a token is no longer for sale
+    ListingRemoved {
+        token_id: String,
+        contract_id: String,
+    },
+    //a token was bought
+    TokenSold {
+        token_id: String,
+        contract_id: String,
+        price_near: Option<f64>,
+        purchaser_account_id: String,
+        receipt_id: String,
+        //the token is an edition that was minted when it was bought (nft_mint_payout)
+        lazy: bool,
+        block_timestamp: u64,
+    },
+    //a bid was placed on a token
+    BidPlaced {
+        token_id: String,
+        contract_id: String,
+        bidder_account_id: String,
+        ft_token_id: String,
+        //in the smallest unit of the FT (yocto for near)
+        amount: String,
+        receipt_id: String,
+    },
+    //a bid was beaten by a higher one
+    BidOutbid {
+        token_id: String,
+        contract_id: String,
+        bidder_account_id: String,
+        ft_token_id: String,
+        amount: String,
+        outbid_by_account_id: String,
+    },
+    //the owner accepted the highest bid, which settles the sale
+    OfferAccepted {
+        token_id: String,
+        contract_id: String,
+        buyer_account_id: String,
+        ft_token_id: String,
+        amount: String,
+        //only set when the bid was in near
+        price_near: Option<f64>,
+        receipt_id: String,
+        block_timestamp: u64,
+    },
+    //one recipient of the payout of a sale (artist royalty, charity share or the owner's proceeds)
+    PayoutRecorded {
+        token_id: String,
+        contract_id: String,
+        recipient_account_id: String,
+        role: PayoutRole,
+        //in yocto
+        amount: String,
+        amount_near: f64,
+        receipt_id: String,
+    },
+    //the share of a sale that went to the charity of the token
+    CharityDonated(CharityDonation),
+    //a call to a method listed in the watch config (see abi.rs), with the fields it asks for
+    ContractCall {
+        contract_id: String,
+        method_name: String,
+        signer_id: String,
+        predecessor_id: String,
+        //in yocto
+        deposit: String,
+        receipt_id: String,
+        block_height: u64,
+        fields: BTreeMap<String, serde_json::Value>,
+    },
+    //one action of a receipt signed or received by a watched account (see activity.rs)
+    AccountActivity {
+        account_id: String,
+        role: ActivityRole,
+        receipt_id: String,
+        block_height: u64,
+        //nanoseconds since the unix epoch
+        block_timestamp: u64,
+        signer_id: String,
+        predecessor_id: String,
+        receiver_id: String,
+        action: ActivityAction,
+        success: bool,
+    },
+    //the storage an account paid for on the market changed (see storage.rs)
+    StorageBalanceChanged {
+        account_id: String,
+        change: StorageChange,
+        //in yocto, only known for deposits
+        amount: Option<String>,
+        //in yocto
+        balance: String,
+        balance_near: f64,
+        listings: u64,
+        //how many more tokens the account can list before it has to deposit again
+        listings_affordable: u64,
+        listing_would_fail: bool,
+        receipt_id: String,
+        block_height: u64,
+        block_timestamp: u64,
+    },
+    //an event that was delivered from a block that is no longer on the chain, the API should undo it (see finality.rs)
+    Reverted {
+        block_height: u64,
+        block_hash: String,
+        event: Box<IndexerEvent>,
+    },
+}
+
+impl IndexerEvent {
+    //name of the event, as used in logs
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            IndexerEvent::TokenMinted(_) => "TokenMinted",
+            IndexerEvent::TokenListed { .. } => "TokenListed",
+            IndexerEvent::PriceUpdated { .. } => "PriceUpdated",
+            IndexerEvent::ListingRemoved { .. } => "ListingRemoved",
+            IndexerEvent::TokenSold { .. } => "TokenSold",
+            IndexerEvent::BidPlaced { .. } => "BidPlaced",
+            IndexerEvent::BidOutbid { .. } => "BidOutbid",
+            IndexerEvent::OfferAccepted { .. } => "OfferAccepted",
+            IndexerEvent::PayoutRecorded { .. } => "PayoutRecorded",
+            IndexerEvent::CharityDonated(_) => "CharityDonated",
+            IndexerEvent::ContractCall { .. } => "ContractCall",
+            IndexerEvent::AccountActivity { .. } => "AccountActivity",
+            IndexerEvent::StorageBalanceChanged { .. } => "StorageBalanceChanged",
+            IndexerEvent::Reverted { .. } => "Reverted",
+        }
+    }
+}
+
+//suffix of the base token of a multi-edition series, editions replace the 0 with their number
+pub(crate) const BASE_TOKEN_SUFFIX: &str = "_0";
+
+//read a string field, whether it was passed as a JSON string or as a number
+fn string_field(value: &serde_json::Value, field: &str) -> Option<String> {
+    match value.get(field)? {
+        serde_json::Value::String(string) => Some(string.clone()),
+        serde_json::Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+//look for a field in the metadata first, then in the JSON string the metadata keeps in `extra`
+fn metadata_field(metadata: &serde_json::Value, field: &str) -> Option<serde_json::Value> {
+    if let Some(value) = metadata.get(field).filter(|value| !value.is_null()) {
+        return Some(value.clone());
+    }
+    let extra = metadata.get("extra")?.as_str()?;
+    let extra: serde_json::Value = serde_json::from_str(extra).ok()?;
+    extra.get(field).filter(|value| !value.is_null()).cloned()
+}
+
+//build the TokenMinted event from the args passed to nft_mint
+pub(crate) fn minted_token_from_args(
+    args: &serde_json::Value,
+    contract_id: &str,
+    signer_id: &str,
+) -> Result<MintedTokenPOSTBody, String> {
+    let token_id = string_field(args, "token_id").ok_or("nft_mint was called without a token_id")?;
+    let metadata = args.get("metadata").ok_or("nft_mint was called without metadata")?;
+
+    //the token goes to receiver_id when it is passed, otherwise to whoever minted it
+    let owner_account_id = string_field(args, "receiver_id").unwrap_or_else(|| signer_id.to_string());
+
+    let copies = match metadata_field(metadata, "copies") {
+        Some(copies) => copies
+            .as_u64()
+            .or_else(|| copies.as_str().and_then(|copies| copies.parse().ok()))
+            .ok_or_else(|| format!("invalid copies in nft_mint metadata: {}", copies))?,
+        None => 1,
+    };
+    //multi-edition tokens are minted as the base token, the editions are minted on purchase (nft_mint_payout)
+    if copies > 1 && !token_id.ends_with(BASE_TOKEN_SUFFIX) {
+        eprintln!(
+            "nft_mint of {} copies but {} is not a base token (it should end with {})",
+            copies, token_id, BASE_TOKEN_SUFFIX
+        );
+    }
+
+    let tags = match metadata_field(metadata, "tags") {
+        Some(serde_json::Value::Array(tags)) => {
+            tags.iter().filter_map(|tag| tag.as_str().map(|tag| tag.to_string())).collect()
+        }
+        //tags can also be sent as a comma separated string
+        Some(serde_json::Value::String(tags)) => tags
+            .split(',')
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+        _ => vec![],
+    };
+    let aspect_ratio = metadata_field(metadata, "aspect_ratio")
+        .and_then(|aspect_ratio| {
+            aspect_ratio
+                .as_f64()
+                .or_else(|| aspect_ratio.as_str().and_then(|aspect_ratio| aspect_ratio.parse().ok()))
+        })
+        .unwrap_or(1.0) as f32;
+
+    Ok(MintedTokenPOSTBody {
+        token_id,
+        contract_id: contract_id.to_string(),
+        tags,
+        artwork_url: string_field(metadata, "media").unwrap_or_default(),
+        owner_account_id,
+        artist_account_id: string_field(metadata, "artist_account_id").unwrap_or_default(),
+        charity_account_id: string_field(metadata, "charity_account_id").unwrap_or_default(),
+        aspect_ratio,
+        copies,
+        title: string_field(metadata, "title").unwrap_or_default(),
+        description: string_field(metadata, "description").unwrap_or_default(),
+    })
+}
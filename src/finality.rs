@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use near_indexer::near_primitives::views::BlockHeaderView;
+
+use crate::events::IndexerEvent;
+
+// ------------------------------- FINALITY ----------------------------------
+// blocks can come off the stream before they are final, and a block that isn't final
+//  can still be replaced by another fork. the events produced while handling a block
+//  are collected and put in the outbox, which hands them to the sink once the block is
+//  deep enough for the --finality mode:
+//   - "none": as soon as the block is handled
+//   - a number N: once the stream is N blocks past it
+//   - "final": once a later block names it (or a block after it) as the last final block
+// a block that disappears from the chain before its events went out is dropped. one
+//  that disappears after is undone with a Reverted event for each of its events.
+// blocks are always released in height order, so a slow block holds back the ones after it.
+// the state kept from one block to the next (bids, lazy purchases, payouts) is changed
+//  through an UndoLog, so what a block did to it is undone when the block leaves the chain.
+
+//how many blocks are kept around to be reverted, when finality info never arrives
+const MAX_TRACKED_BLOCKS: usize = 1000;
+//how many changes an UndoLog keeps, when the blocks that made them never become final
+const MAX_UNDO_CHANGES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FinalityMode {
+    Immediate,
+    Depth(u64),
+    Final,
+}
+
+impl FromStr for FinalityMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FinalityMode::Immediate),
+            "final" => Ok(FinalityMode::Final),
+            depth => depth.parse().map(FinalityMode::Depth).map_err(|_| {
+                format!("unknown finality {:?}, expected \"none\", \"final\" or a number of blocks", s)
+            }),
+        }
+    }
+}
+
+//where a block sits in the chain
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BlockInfo {
+    pub height: u64,
+    pub hash: String,
+    pub prev_hash: String,
+    pub last_final_block: String,
+}
+
+impl From<&BlockHeaderView> for BlockInfo {
+    fn from(header: &BlockHeaderView) -> Self {
+        Self {
+            height: header.height,
+            hash: header.hash.to_string(),
+            prev_hash: header.prev_hash.to_string(),
+            last_final_block: header.last_final_block.to_string(),
+        }
+    }
+}
+
+tokio::task_local! {
+    //events produced by the receipts of the block being handled
+    static BLOCK_EVENTS: RefCell<Vec<IndexerEvent>>;
+    //hash of the block being handled
+    static BLOCK_HASH: String;
+}
+
+//handle a block and return the events it produced instead of delivering them
+pub(crate) async fn collect_events<F: Future<Output = ()>>(block_hash: &str, handling: F) -> Vec<IndexerEvent> {
+    let collecting = BLOCK_EVENTS.scope(RefCell::new(vec![]), async move {
+        handling.await;
+        BLOCK_EVENTS.with(|events| events.take())
+    });
+    BLOCK_HASH.scope(block_hash.to_string(), collecting).await
+}
+
+//keep the event with the rest of its block. gives it back when no block is being collected
+pub(crate) fn hold_event(event: IndexerEvent) -> Option<IndexerEvent> {
+    let mut event = Some(event);
+    let _ = BLOCK_EVENTS.try_with(|events| events.borrow_mut().extend(event.take()));
+    event
+}
+
+//changes made to a map while handling blocks, so they can be undone if the block
+//  leaves the chain. changes made outside of a block (e.g. in tests) can't be undone
+pub(crate) struct UndoLog<K, V> {
+    //block hash, key, what the key held before the block changed it. oldest first
+    changes: Mutex<Vec<(String, K, Option<V>)>>,
+}
+
+impl<K, V> Default for UndoLog<K, V> {
+    fn default() -> Self {
+        Self { changes: Mutex::new(vec![]) }
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> UndoLog<K, V> {
+    pub(crate) fn insert(&self, map: &mut HashMap<K, V>, key: K, value: V) -> Option<V> {
+        let previous = map.insert(key.clone(), value);
+        self.record(key, previous.clone());
+        previous
+    }
+
+    pub(crate) fn remove(&self, map: &mut HashMap<K, V>, key: &K) -> Option<V> {
+        let previous = map.remove(key);
+        if previous.is_some() {
+            self.record(key.clone(), previous.clone());
+        }
+        previous
+    }
+
+    fn record(&self, key: K, previous: Option<V>) {
+        let block_hash = match BLOCK_HASH.try_with(|block_hash| block_hash.clone()) {
+            Ok(block_hash) => block_hash,
+            Err(_) => return,
+        };
+        let mut changes = self.changes.lock().unwrap();
+        changes.push((block_hash, key, previous));
+        if changes.len() > MAX_UNDO_CHANGES {
+            let extra = changes.len() - MAX_UNDO_CHANGES;
+            changes.drain(..extra);
+        }
+    }
+
+    //put back what the keys held before the block changed them. returns whether anything changed
+    pub(crate) fn undo_block(&self, block_hash: &str, map: &mut HashMap<K, V>) -> bool {
+        let mut changes = self.changes.lock().unwrap();
+        let mut undone = false;
+        //latest change first, so a key changed twice ends up with what it held before the first change
+        for (_, key, previous) in changes.iter().rev().filter(|(changed_in, _, _)| changed_in == block_hash) {
+            match previous {
+                Some(previous) => map.insert(key.clone(), previous.clone()),
+                None => map.remove(key),
+            };
+            undone = true;
+        }
+        changes.retain(|(changed_in, _, _)| changed_in != block_hash);
+        undone
+    }
+
+    //the blocks can't leave the chain anymore, their changes stay
+    pub(crate) fn forget_blocks(&self, block_hashes: &[String]) {
+        self.changes.lock().unwrap().retain(|(changed_in, _, _)| !block_hashes.contains(changed_in));
+    }
+}
+
+//the blocks the outbox let go of since it was last asked
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ChainChanges {
+    //blocks that left the chain, newest first
+    pub orphaned: Vec<String>,
+    //blocks that are final (or too old to be reverted)
+    pub settled: Vec<String>,
+}
+
+#[derive(Debug)]
+struct OutboxBlock {
+    hash: String,
+    //None while the block is still being handled
+    events: Option<Vec<IndexerEvent>>,
+    delivered: bool,
+    is_final: bool,
+}
+
+#[derive(Debug, Default)]
+struct OutboxState {
+    //height --> block, for the blocks not yet final or not yet delivered
+    blocks: BTreeMap<u64, OutboxBlock>,
+    //blocks that left the chain while they were still being handled
+    orphaned: HashSet<String>,
+    changes: ChainChanges,
+}
+
+pub(crate) struct Outbox {
+    mode: FinalityMode,
+    state: Mutex<OutboxState>,
+}
+
+impl Outbox {
+    pub(crate) fn new(mode: FinalityMode) -> Self {
+        Self { mode, state: Mutex::new(OutboxState::default()) }
+    }
+
+    //a block came off the stream. returns the events to send: Reverted ones for the blocks
+    //  it replaces, then the ones it made final or deep enough
+    pub(crate) fn block_received(&self, block: BlockInfo) -> Vec<IndexerEvent> {
+        let mut state = self.state.lock().unwrap();
+        let mut to_send = vec![];
+
+        //the new block builds on its parent, anything we saw above the parent is on another fork
+        let parent_height = state
+            .blocks
+            .iter()
+            .find(|(_, known)| known.hash == block.prev_hash)
+            .map(|(height, _)| *height);
+        let fork_height = parent_height.map_or(block.height, |parent_height| parent_height + 1);
+        let orphaned_heights: Vec<u64> = state.blocks.range(fork_height..).map(|(height, _)| *height).collect();
+        for height in orphaned_heights.into_iter().rev() {
+            let orphan = state.blocks.remove(&height).unwrap();
+            state.changes.orphaned.push(orphan.hash.clone());
+            match orphan.events {
+                Some(events) if orphan.delivered => {
+                    eprintln!("Block {} ({}) left the chain, reverting its {} events", height, orphan.hash, events.len());
+                    to_send.extend(events.into_iter().rev().map(|event| IndexerEvent::Reverted {
+                        block_height: height,
+                        block_hash: orphan.hash.clone(),
+                        event: Box::new(event),
+                    }));
+                }
+                Some(events) => {
+                    eprintln!("Block {} ({}) left the chain, dropping its {} held events", height, orphan.hash, events.len());
+                }
+                None => {
+                    eprintln!("Block {} ({}) left the chain while it was being handled", height, orphan.hash);
+                    state.orphaned.insert(orphan.hash);
+                }
+            }
+        }
+        if parent_height.is_none() && !state.blocks.is_empty() {
+            eprintln!("Parent {} of block {} is not one we know, assuming it follows on", block.prev_hash, block.height);
+        }
+
+        let final_height = state
+            .blocks
+            .iter()
+            .find(|(_, known)| known.hash == block.last_final_block)
+            .map(|(height, _)| *height);
+        if let Some(final_height) = final_height {
+            for (_, known) in state.blocks.range_mut(..=final_height) {
+                known.is_final = true;
+            }
+        }
+
+        state.blocks.insert(
+            block.height,
+            OutboxBlock {
+                hash: block.hash,
+                events: None,
+                delivered: false,
+                is_final: false,
+            },
+        );
+        to_send.extend(self.release(&mut state));
+        to_send
+    }
+
+    //a block was handled. returns the events that can be sent now
+    pub(crate) fn block_handled(&self, block_hash: &str, events: Vec<IndexerEvent>) -> Vec<IndexerEvent> {
+        let mut state = self.state.lock().unwrap();
+        if state.orphaned.remove(block_hash) {
+            eprintln!("Dropping the {} events of block {}, it is no longer on the chain", events.len(), block_hash);
+            return vec![];
+        }
+        match state.blocks.values_mut().find(|known| known.hash == block_hash) {
+            Some(known) => known.events = Some(events),
+            //blocks that weren't streamed live (e.g. replays) go straight out
+            None => return events,
+        }
+        self.release(&mut state)
+    }
+
+    //heights of the blocks whose events haven't gone out yet, they have to be handled again after a restart
+    pub(crate) fn undelivered_heights(&self) -> Vec<u64> {
+        let state = self.state.lock().unwrap();
+        state.blocks.iter().filter(|(_, known)| !known.delivered).map(|(height, _)| *height).collect()
+    }
+
+    pub(crate) fn take_chain_changes(&self) -> ChainChanges {
+        std::mem::take(&mut self.state.lock().unwrap().changes)
+    }
+
+    fn release(&self, state: &mut OutboxState) -> Vec<IndexerEvent> {
+        let tip_height = state.blocks.keys().next_back().copied().unwrap_or_default();
+        let mut to_send = vec![];
+        for (height, known) in state.blocks.iter_mut().filter(|(_, known)| !known.delivered) {
+            let deep_enough = match self.mode {
+                FinalityMode::Immediate => true,
+                FinalityMode::Depth(depth) => tip_height >= height + depth,
+                FinalityMode::Final => known.is_final,
+            };
+            let events = match &known.events {
+                Some(events) if deep_enough => events,
+                _ => break,
+            };
+            to_send.extend(events.iter().cloned());
+            known.delivered = true;
+        }
+
+        //final blocks can't be reverted, once they are out they can be forgotten
+        let settled_heights: Vec<u64> = state
+            .blocks
+            .iter()
+            .filter(|(_, known)| known.is_final && known.delivered)
+            .map(|(height, _)| *height)
+            .collect();
+        for height in settled_heights {
+            let settled = state.blocks.remove(&height).unwrap();
+            state.changes.settled.push(settled.hash);
+        }
+        while state.blocks.len() > MAX_TRACKED_BLOCKS {
+            let oldest_height = *state.blocks.keys().next().unwrap();
+            if !state.blocks[&oldest_height].delivered {
+                break;
+            }
+            let settled = state.blocks.remove(&oldest_height).unwrap();
+            state.changes.settled.push(settled.hash);
+        }
+        to_send
+    }
+}
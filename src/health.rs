@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix::Addr;
+use near_client::{ClientActor, Status};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// ------------------------------- HEALTH CHECKS ----------------------------------
+// /healthz answers as long as the indexer process is up and serving requests.
+// /readyz also looks at the node sync status, how far behind we are and how the
+//  API sink is doing, so the orchestrator can hold traffic until we're caught up.
+
+// how many of the most recent API calls are used to compute the sink error rate
+const SINK_WINDOW: usize = 100;
+
+//shared counters updated by the block handlers and read by the health server
+#[derive(Debug, Default)]
+pub(crate) struct IndexerStats {
+    last_processed_height: AtomicU64,
+    last_processed_at: Mutex<Option<Instant>>,
+    in_flight_heights: Mutex<BTreeSet<u64>>,
+    //handled blocks whose events the outbox is still holding back
+    outbox_backlog: AtomicUsize,
+    sink_results: Mutex<VecDeque<bool>>,
+    //function calls whose args could only be kept as hex (or not at all)
+    args_decode_failures: AtomicU64,
+    last_received_height: AtomicU64,
+    //shard id --> how far along handling that shard is
+    shards: Mutex<BTreeMap<u64, ShardProgress>>,
+    //event name --> the last one sent to the API
+    last_events: Mutex<BTreeMap<String, LastEvent>>,
+}
+
+//the last event of a type that was sent to the API, as shown by `status`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct LastEvent {
+    pub sent_at: String,
+    //whether the API took it
+    pub delivered: bool,
+    pub event: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ShardProgress {
+    last_processed_height: u64,
+    last_handling_time: Duration,
+}
+
+//how far behind a shard is, as reported by /readyz
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct ShardLag {
+    pub last_processed_height: u64,
+    //blocks received since the last one handled on this shard
+    pub lag_blocks: u64,
+    //how long the receipts of the shard took in the last block handled
+    pub last_handling_ms: u64,
+}
+
+impl IndexerStats {
+    //a block came off the stream and is waiting to be handled
+    pub(crate) fn block_received(&self, height: u64) {
+        self.in_flight_heights.lock().unwrap().insert(height);
+        self.last_received_height.fetch_max(height, Ordering::SeqCst);
+    }
+
+    //the receipts of one shard of a block have been handled
+    pub(crate) fn shard_processed(&self, shard_id: u64, height: u64, handling_time: Duration) {
+        let mut shards = self.shards.lock().unwrap();
+        let progress = shards.entry(shard_id).or_default();
+        //blocks are handled one at a time in height order (see MAX_IN_FLIGHT_BLOCKS), only a block
+        //  replacing one that left the chain comes with a height we already got past
+        if height >= progress.last_processed_height {
+            *progress = ShardProgress { last_processed_height: height, last_handling_time: handling_time };
+        }
+    }
+
+    pub(crate) fn shard_lags(&self) -> BTreeMap<u64, ShardLag> {
+        let last_received_height = self.last_received_height.load(Ordering::SeqCst);
+        self.shards
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(shard_id, progress)| {
+                let shard_lag = ShardLag {
+                    last_processed_height: progress.last_processed_height,
+                    lag_blocks: last_received_height.saturating_sub(progress.last_processed_height),
+                    last_handling_ms: progress.last_handling_time.as_millis() as u64,
+                };
+                (*shard_id, shard_lag)
+            })
+            .collect()
+    }
+
+    //a block has been fully handled
+    pub(crate) fn block_processed(&self, height: u64) {
+        self.in_flight_heights.lock().unwrap().remove(&height);
+        //blocks are handled one at a time in height order (see MAX_IN_FLIGHT_BLOCKS), only a block
+        //  replacing one that left the chain comes with a height we already got past
+        self.last_processed_height.fetch_max(height, Ordering::SeqCst);
+        *self.last_processed_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    //remember whether a call to the API went through
+    pub(crate) fn record_sink_result<T, E>(&self, result: &Result<T, E>) {
+        let mut sink_results = self.sink_results.lock().unwrap();
+        if sink_results.len() == SINK_WINDOW {
+            sink_results.pop_front();
+        }
+        sink_results.push_back(result.is_ok());
+    }
+
+    pub(crate) fn event_sent(&self, event_name: &str, event: serde_json::Value, delivered: bool) {
+        let last_event = LastEvent { sent_at: chrono::Utc::now().to_rfc3339(), delivered, event };
+        self.last_events.lock().unwrap().insert(event_name.to_string(), last_event);
+    }
+
+    pub(crate) fn last_events(&self) -> BTreeMap<String, LastEvent> {
+        self.last_events.lock().unwrap().clone()
+    }
+
+    pub(crate) fn last_received_height(&self) -> u64 {
+        self.last_received_height.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn args_decode_failed(&self) {
+        self.args_decode_failures.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn args_decode_failures(&self) -> u64 {
+        self.args_decode_failures.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn last_processed_height(&self) -> u64 {
+        self.last_processed_height.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn last_processed_age(&self) -> Option<Duration> {
+        self.last_processed_at.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    pub(crate) fn set_outbox_backlog(&self, blocks: usize) {
+        self.outbox_backlog.store(blocks, Ordering::SeqCst);
+    }
+
+    //blocks still being handled, or whose events haven't gone out yet
+    pub(crate) fn backlog(&self) -> usize {
+        self.in_flight_heights.lock().unwrap().len() + self.outbox_backlog.load(Ordering::SeqCst)
+    }
+
+    //heights of the blocks that are received but not fully handled yet
+    pub(crate) fn in_flight_heights(&self) -> Vec<u64> {
+        self.in_flight_heights.lock().unwrap().iter().copied().collect()
+    }
+
+    pub(crate) fn sink_error_rate(&self) -> f64 {
+        let sink_results = self.sink_results.lock().unwrap();
+        if sink_results.is_empty() {
+            return 0.0;
+        }
+        let errors = sink_results.iter().filter(|ok| !**ok).count();
+        errors as f64 / sink_results.len() as f64
+    }
+}
+
+//limits above which /readyz reports the indexer as not ready
+#[derive(Debug, Clone)]
+pub(crate) struct HealthThresholds {
+    pub max_block_age: Duration,
+    pub max_backlog: usize,
+    pub max_sink_error_rate: f64,
+}
+
+//body returned by /readyz
+#[derive(Serialize, Debug)]
+struct ReadinessReport {
+    ready: bool,
+    node_syncing: Option<bool>,
+    node_height: Option<u64>,
+    last_processed_height: u64,
+    last_processed_age_secs: Option<u64>,
+    backlog: usize,
+    sink_error_rate: f64,
+    args_decode_failures: u64,
+    shards: BTreeMap<u64, ShardLag>,
+    reasons: Vec<String>,
+}
+
+async fn readiness(
+    stats: &IndexerStats,
+    client: &Addr<ClientActor>,
+    thresholds: &HealthThresholds,
+) -> ReadinessReport {
+    let mut reasons = vec![];
+
+    //ask the node whether it is still catching up with the network
+    let (node_syncing, node_height) = match client.send(Status { is_health_check: false }).await {
+        Ok(Ok(status)) => (
+            Some(status.sync_info.syncing),
+            Some(status.sync_info.latest_block_height),
+        ),
+        Ok(Err(err)) => {
+            reasons.push(format!("node status error: {}", err));
+            (None, None)
+        }
+        Err(err) => {
+            reasons.push(format!("node status unavailable: {}", err));
+            (None, None)
+        }
+    };
+    if node_syncing == Some(true) {
+        reasons.push("node is syncing".to_string());
+    }
+
+    let last_processed_age = stats.last_processed_age();
+    match last_processed_age {
+        Some(age) if age > thresholds.max_block_age => reasons.push(format!(
+            "last block was processed {}s ago (max {}s)",
+            age.as_secs(),
+            thresholds.max_block_age.as_secs()
+        )),
+        None => reasons.push("no block processed yet".to_string()),
+        _ => {}
+    }
+
+    let backlog = stats.backlog();
+    if backlog > thresholds.max_backlog {
+        reasons.push(format!(
+            "{} blocks waiting to be handled or sent (max {})",
+            backlog, thresholds.max_backlog
+        ));
+    }
+
+    let sink_error_rate = stats.sink_error_rate();
+    if sink_error_rate > thresholds.max_sink_error_rate {
+        reasons.push(format!(
+            "API error rate is {:.2} (max {:.2})",
+            sink_error_rate, thresholds.max_sink_error_rate
+        ));
+    }
+
+    ReadinessReport {
+        ready: reasons.is_empty(),
+        node_syncing,
+        node_height,
+        last_processed_height: stats.last_processed_height(),
+        last_processed_age_secs: last_processed_age.map(|age| age.as_secs()),
+        backlog,
+        sink_error_rate,
+        args_decode_failures: stats.args_decode_failures(),
+        shards: stats.shard_lags(),
+        reasons,
+    }
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    stats: Arc<IndexerStats>,
+    client: Addr<ClientActor>,
+    thresholds: HealthThresholds,
+) -> std::io::Result<()> {
+    //we only care about the request line, e.g. "GET /readyz HTTP/1.1"
+    let mut buffer = [0u8; 1024];
+    let read = socket.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (status_line, body) = match path {
+        "/healthz" => ("200 OK", serde_json::json!({ "alive": true }).to_string()),
+        "/readyz" => {
+            let report = readiness(&stats, &client, &thresholds).await;
+            let status_line = if report.ready {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status_line, serde_json::to_string(&report).unwrap())
+        }
+        _ => ("404 Not Found", serde_json::json!({ "error": "not found" }).to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+//serve /healthz and /readyz until the process exits
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    stats: Arc<IndexerStats>,
+    client: Addr<ClientActor>,
+    thresholds: HealthThresholds,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Could not start health server on {:?} --> {:?}", addr, err);
+            return;
+        }
+    };
+    eprintln!("Health server listening on {:?}", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                let connection =
+                    handle_connection(socket, stats.clone(), client.clone(), thresholds.clone());
+                actix::spawn(async move {
+                    if let Err(err) = connection.await {
+                        eprintln!("Health server failed to answer request --> {:?}", err);
+                    }
+                });
+            }
+            Err(err) => eprintln!("Health server failed to accept connection --> {:?}", err),
+        }
+    }
+}
@@ -1,38 +1,95 @@
 #![allow(non_snake_case)]
 use actix;
-use std::env;
 
-use near_client::Query;
-use near_client::ViewClientActor;
-use near_indexer::near_primitives::types::BlockReference;
-use near_indexer::near_primitives::types::FunctionArgs;
+use near_indexer::near_primitives::types::{BlockId, BlockReference};
 use near_indexer::near_primitives::views::ExecutionStatusView;
-use near_indexer::near_primitives::views::QueryRequest;
+use near_indexer::IndexerExecutionOutcomeWithReceipt;
 use near_sdk::AccountId;
 
-use std::str::FromStr;
-
-use futures::{join, StreamExt};
+use futures::stream::FuturesUnordered;
+use futures::future::join_all;
+use futures::{join, FutureExt, StreamExt};
 
 use clap::Clap;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
 
-use actix::Addr;
-
 use configs::{init_logging, Opts, SubCommand};
 use near_indexer;
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use near_sdk::json_types::U128;
 
+use abi::WatchConfig;
+use activity::{deliver_account_activity, WatchedAccounts};
+use analytics::{
+    print_collection_stats, push_collection_stats, push_collection_stats_every, save_sale_analytics_every, SaleAnalytics,
+};
+use args::{ArgsDecoders, ArgsEncoding};
+use bids::{BidBook, BidOutcome, HighestBid};
+use charity::{aggregate_donations, charity_share, print_report, read_ledger, CharityDonation, DonationSource};
+use checkpoint::{load_checkpoint, save_checkpoint, start_at, Checkpoint, StartAt};
+use health::{HealthThresholds, IndexerStats};
+use doctor::{print_checks, run_checks, CheckStatus};
+use dry_run::DryRun;
+use events::{minted_token_from_args, IndexerEvent};
+use finality::{collect_events, BlockInfo, FinalityMode, Outbox};
+use payouts::{decode_payout, payout_role, OfferPurchase, PayoutRole, PendingPayout, PendingPayouts, PendingSale};
+use poison::{panic_message, record_poison_receipt};
+use purchases::{edition_token_id, LazyPurchase, PendingLazyPurchases};
+use recording::{read_recording, Recorder, RecordingChainViewer};
+use schema::{current_schemas, write_schemas};
+use settings::{patch_tracked_shards, settings_path, write_starter_settings, Settings};
+use shards::{check_tracked_shards, genesis_num_shards, node_tracked_shards, watched_shards};
+use sink::{deliver_event, send_events};
+use status::{indexer_status, load_status, print_status, save_status, status_report};
+use storage::{deliver_storage_balance, StorageChange};
+use viewer::{ChainViewer, NodeChainViewer};
+
+mod abi;
+mod activity;
+mod analytics;
+mod args;
+mod bids;
+mod charity;
+mod checkpoint;
 mod configs;
 mod database;
+mod doctor;
+mod dry_run;
+mod events;
+mod finality;
+mod health;
+mod payouts;
+mod poison;
+mod purchases;
+mod recording;
+mod schema;
+mod settings;
+mod shards;
+mod sink;
+mod status;
+mod storage;
+#[cfg(test)]
+mod test_support;
+#[cfg(test)]
+mod tests;
+mod viewer;
 
 pub type FungibleTokenId = AccountId;
 pub type SaleConditions = HashMap<FungibleTokenId, U128>;
 
+// how many blocks are handled at the same time. the bid book, the lazy purchases and the
+//  payouts pair up receipts from different blocks, so a block is only handled once the
+//  block before it is done
+const MAX_IN_FLIGHT_BLOCKS: usize = 1;
+
 //use this struct to store information that we want to pass to database
 #[derive(Debug, Clone)] //derive debug so that we can print
 struct ExecutionDetails {
@@ -83,450 +140,1093 @@ pub(crate) fn human(yocto: u128) -> f64 {
     foo
 }
 
-async fn handle_messages(
-    streamer_message: near_indexer::StreamerMessage,
-    view_client: Addr<ViewClientActor>,
+//configuration and shared state needed to handle the incoming blocks
+struct IndexerContext {
+    viewer: Box<dyn ChainViewer>,
     nft_contract: String,
     market_contract: String,
     admin_account: String,
     public_api_root: String,
-    private_api_root: String, 
+    private_api_root: String,
     signature_header: String,
     debug_mode: String,
+    //share of a sale (in percent) attributed to charity when there is no on-chain payout to read it from
+    charity_split_percent: Option<f64>,
+    //other contracts to index generically, from the file in WATCH_CONFIG
+    watch_config: WatchConfig,
+    //turn the args of function calls into json
+    args_decoders: ArgsDecoders,
+    //accounts whose every receipt is reported (the admin plus WATCH_ACCOUNTS)
+    watched_accounts: WatchedAccounts,
+    stats: Arc<IndexerStats>,
+    home_dir: PathBuf,
+    //highest standing bid on every token, to detect outbids and settle accepted offers
+    bids: BidBook,
+    //lazy purchase offers waiting for the nft_mint_payout that mints the edition
+    lazy_purchases: PendingLazyPurchases,
+    //payouts returned by nft_transfer_payout, waiting for the market's resolve_purchase
+    pending_payouts: PendingPayouts,
+    //set when the incoming blocks are being recorded for a later replay
+    recorder: Option<Arc<Recorder>>,
+    //holds the events of each block until it is final enough to deliver them
+    outbox: Outbox,
+    //shards hosting the contracts we handle, None to handle every shard
+    watched_shards: Option<BTreeSet<u64>>,
+    //set with --dry-run, the events are printed instead of sent to the API
+    dry_run: Option<DryRun>,
+    //floor, volume and sale prices of every collection, per hour and day
+    analytics: SaleAnalytics,
+}
+
+impl IndexerContext {
+    //read the contracts to watch and the API settings from the environment
+    fn from_env(
+        viewer: Box<dyn ChainViewer>,
+        stats: Arc<IndexerStats>,
+        home_dir: PathBuf,
+        recorder: Option<Arc<Recorder>>,
+        finality: FinalityMode,
+    ) -> Self {
+        //env variables, or the same names in fayyr-indexer/indexer.json
+        let settings = Settings::load(&home_dir);
+        let nft_contract: String =
+            settings.get("NFT").expect("NFT Contract Env Variable Not Specified");
+        let market_contract: String =
+            settings.get("MARKET").expect("Market Contract Env Variable Not Specified");
+        let admin_account: String =
+            settings.get("ADMIN").expect("Admin Account Env Variable Not Specified");
+        let private_api_root: String =
+            settings.get("PRIVATE_API").expect("Fayyr Private API Root Env Variable Not Specified");
+        let public_api_root: String =
+            settings.get("PUBLIC_API").expect("Fayyr Public API Root Env Variable Not Specified");    
+        let debug_mode: String =
+            settings.get("DEBUG").expect("Debugging Mode Env Variable Not Specified");
+        let signature_header: String =
+            settings.get("HEADER").expect("Signature Header Env Variable Not Specified");
+        let charity_split_percent: Option<f64> = settings
+            .get("CHARITY_SPLIT_PERCENT")
+            .map(|split| split.parse().expect("Charity Split Percent Env Variable Is Not A Number"));
+        let watch_config: WatchConfig = match settings.get("WATCH_CONFIG") {
+            Some(path) => WatchConfig::load(path.as_ref()).unwrap_or_else(|err| panic!("{}", err)),
+            None => WatchConfig::default(),
+        };
+        let args_decoders = ArgsDecoders::from_watch_config(&watch_config);
+        let watched_accounts =
+            WatchedAccounts::from_list(&admin_account, &settings.get("WATCH_ACCOUNTS").unwrap_or_default());
+
+        eprintln!("Starting Indexer With NFT: {:?}, Market: {:?}, and Fayyr Account: {:?} and Debugging With: {:?} with Signature Header: {:?}", nft_contract, market_contract, admin_account, debug_mode, signature_header);
+
+        let bids = BidBook::load(&home_dir);
+        let analytics = SaleAnalytics::load(&home_dir);
+
+        Self {
+            viewer,
+            nft_contract,
+            market_contract,
+            admin_account,
+            public_api_root,
+            private_api_root,
+            signature_header,
+            debug_mode,
+            charity_split_percent,
+            watch_config,
+            args_decoders,
+            watched_accounts,
+            stats,
+            home_dir,
+            bids,
+            lazy_purchases: PendingLazyPurchases::default(),
+            pending_payouts: PendingPayouts::default(),
+            recorder,
+            outbox: Outbox::new(finality),
+            watched_shards: None,
+            dry_run: None,
+            analytics,
+        }
+    }
+
+    //the contracts whose receipts go through our handlers
+    fn watched_contracts(&self) -> Vec<&str> {
+        let mut contracts = vec![self.nft_contract.as_str(), self.market_contract.as_str()];
+        contracts.extend(self.watch_config.contracts.iter().map(|contract| contract.account_id.as_str()));
+        contracts
+    }
+
+    //dry runs and replays work on the state files they were started with, without
+    //  changing what the next real run starts from
+    fn keep_state_in_memory(&mut self) {
+        self.bids.keep_in_memory();
+        self.analytics.keep_in_memory();
+    }
+
+    //undo what the blocks that left the chain did to the bids, lazy purchases and payouts,
+    //  and stop remembering what the final ones did
+    fn follow_chain_changes(&self) {
+        let changes = self.outbox.take_chain_changes();
+        for block_hash in &changes.orphaned {
+            self.bids.undo_block(block_hash);
+            self.lazy_purchases.undo_block(block_hash);
+            self.pending_payouts.undo_block(block_hash);
+        }
+        self.bids.forget_blocks(&changes.settled);
+        self.lazy_purchases.forget_blocks(&changes.settled);
+        self.pending_payouts.forget_blocks(&changes.settled);
+    }
+
+    fn watches_shard(&self, shard_id: u64) -> bool {
+        self.watched_shards.as_ref().map_or(true, |watched_shards| watched_shards.contains(&shard_id))
+    }
+}
+
+async fn handle_messages(
+    streamer_message: near_indexer::StreamerMessage,
+    context: Arc<IndexerContext>,
 ) {
-    //iterate through each shard in the incoming stream
-    for shard in streamer_message.shards {
-        //for each receipt and execution outcome pair in the shard
-        for receipt_and_execution_outcome in shard.receipt_execution_outcomes {
-            // Check if receipt is related to Fayyr
-            if is_valid_receipt(
-                &receipt_and_execution_outcome.receipt,
-                nft_contract.clone(),
-                market_contract.clone(),
-            ) {
-                //get the execution outcome from the receipt and execution outcome pair from the shard
-                let execution_outcome = receipt_and_execution_outcome.execution_outcome;
-                //only do stuff with the receipts if the outcome was successful
-                if matches!(
-                    execution_outcome.outcome.status,
-                    ExecutionStatusView::SuccessValue(_) | ExecutionStatusView::SuccessReceiptId(_)
-                ) {
-                    //declare values for the execution details that will be used for this entire loop
-                    let mut method_name_ = "".to_string(); 
-                    let mut args_ = serde_json::Value::String("".to_string()); 
-                    let signer_id_ = if let near_indexer::near_primitives::views::ReceiptEnumView::Action {
-                        ref signer_id,
-                        ..
-                    } = receipt_and_execution_outcome.receipt.receipt
-                    {
-                        signer_id.to_string()
-                    } else {
-                        "".to_string()
-                    }; 
-                    let mut deposit_ = 0; 
-                    let success_value_ = true; 
-                    let transaction_id_ = execution_outcome.id.to_string(); 
-                    let predecessor_id_ = receipt_and_execution_outcome
-                        .receipt
-                        .predecessor_id
-                        .to_string(); 
-                    let receiver_id_ = receipt_and_execution_outcome
-                        .receipt
-                        .receiver_id
-                        .to_string();
-                    
-                    //created the vector of execution details associated with this receipt. 
-                    //it will be greater than length 1 IF the receipt contains a batch txn.
-                    let mut execution_details_vector: Vec<ExecutionDetails> = vec![];
-                    
-                    //get the actions from the receipt
-                    if let near_indexer::near_primitives::views::ReceiptEnumView::Action {
-                        actions,
+    let block_height = streamer_message.block.header.height;
+    let block_timestamp = streamer_message.block.header.timestamp;
+    let block_hash = streamer_message.block.header.hash.to_string();
+    //the shards are handled side by side, the receipts of each shard one after the other
+    let shard_handlers = streamer_message.shards.into_iter().map(|shard| {
+        let context = &context;
+        async move {
+            let started_at = Instant::now();
+            let watched_shard = context.watches_shard(shard.shard_id);
+            //for each receipt and execution outcome pair in the shard
+            for receipt_and_execution_outcome in shard.receipt_execution_outcomes {
+                if watched_shard {
+                    handle_receipt_isolated(receipt_and_execution_outcome, block_height, block_timestamp, context).await;
+                } else {
+                    //none of our contracts live here, but a watched account can still sign receipts for it
+                    deliver_account_activity(&receipt_and_execution_outcome, block_height, block_timestamp, context).await;
+                }
+            }
+            context.stats.shard_processed(shard.shard_id, block_height, started_at.elapsed());
+        }
+    });
+    let events = collect_events(&block_hash, join_all(shard_handlers).map(|_| ())).await;
+    let released = context.outbox.block_handled(&block_hash, events);
+    send_events(released, &context).await;
+}
+
+//handle a receipt on its own so a bad one can't take down the rest of the block.
+//  panics and errors are recorded as poison receipts
+async fn handle_receipt_isolated(
+    receipt_and_execution_outcome: IndexerExecutionOutcomeWithReceipt,
+    block_height: u64,
+    block_timestamp: u64,
+    context: &IndexerContext,
+) {
+    let receipt_id = receipt_and_execution_outcome.receipt.receipt_id.to_string();
+
+    let handled_receipt =
+        AssertUnwindSafe(handle_receipt(receipt_and_execution_outcome, block_height, block_timestamp, context))
+            .catch_unwind()
+            .await;
+    let error = match handled_receipt {
+        Ok(Ok(())) => return,
+        Ok(Err(err)) => err,
+        Err(panic) => panic_message(panic),
+    };
+    record_poison_receipt(&context.home_dir, &receipt_id, block_height, &error);
+}
+
+async fn handle_receipt(
+    receipt_and_execution_outcome: IndexerExecutionOutcomeWithReceipt,
+    block_height: u64,
+    //nanoseconds since the unix epoch
+    block_timestamp: u64,
+    context: &IndexerContext,
+) -> Result<(), String> {
+    let IndexerContext {
+        viewer,
+        nft_contract,
+        market_contract,
+        bids,
+        lazy_purchases,
+        pending_payouts,
+        watch_config,
+        args_decoders,
+        stats,
+        ..
+    } = context;
+
+    //activity of the watched accounts is reported whatever the receipt is for
+    deliver_account_activity(&receipt_and_execution_outcome, block_height, block_timestamp, context).await;
+
+    // Check if receipt is related to Fayyr or to one of the contracts in the watch config
+    let is_fayyr_receipt = is_valid_receipt(
+        &receipt_and_execution_outcome.receipt,
+        nft_contract.clone(),
+        market_contract.clone(),
+    );
+    if !is_fayyr_receipt && !watch_config.is_watched(receipt_and_execution_outcome.receipt.receiver_id.as_ref()) {
+        return Ok(());
+    }
+
+    //view calls look at the chain as it was in the block this receipt was executed in,
+    //  not as it is today (which matters when syncing or backfilling old blocks)
+    let block_reference = BlockReference::BlockId(BlockId::Height(block_height));
+
+    //get the execution outcome from the receipt and execution outcome pair from the shard
+    let execution_outcome = receipt_and_execution_outcome.execution_outcome;
+    //receipts created by this one (e.g. the cross-contract calls the market makes), used to follow a flow across receipts
+    let produced_receipt_ids: Vec<String> = execution_outcome
+        .outcome
+        .receipt_ids
+        .iter()
+        .map(|receipt_id| receipt_id.to_string())
+        .collect();
+    //what the receipt returned, e.g. the payout returned by nft_transfer_payout
+    let return_value: Option<Vec<u8>> = match &execution_outcome.outcome.status {
+        ExecutionStatusView::SuccessValue(value) => base64::decode(value).ok(),
+        _ => None,
+    };
+    //a call and its callback are linked by data ids: the result of this receipt is sent out in its
+    //  output data ids, and the results it waited on came in through its input data ids
+    let (output_data_ids, input_data_ids): (Vec<String>, Vec<String>) =
+        if let near_indexer::near_primitives::views::ReceiptEnumView::Action {
+            ref output_data_receivers,
+            ref input_data_ids,
+            ..
+        } = receipt_and_execution_outcome.receipt.receipt
+        {
+            (
+                output_data_receivers.iter().map(|receiver| receiver.data_id.to_string()).collect(),
+                input_data_ids.iter().map(|data_id| data_id.to_string()).collect(),
+            )
+        } else {
+            (vec![], vec![])
+        };
+    //only do stuff with the receipts if the outcome was successful
+    if matches!(
+        execution_outcome.outcome.status,
+        ExecutionStatusView::SuccessValue(_) | ExecutionStatusView::SuccessReceiptId(_)
+    ) {
+        //declare values for the execution details that will be used for this entire loop
+        let mut method_name_ = "".to_string(); 
+        let mut args_ = serde_json::Value::String("".to_string()); 
+        let signer_id_ = if let near_indexer::near_primitives::views::ReceiptEnumView::Action {
+            ref signer_id,
+            ..
+        } = receipt_and_execution_outcome.receipt.receipt
+        {
+            signer_id.to_string()
+        } else {
+            "".to_string()
+        }; 
+        let mut deposit_ = 0; 
+        let success_value_ = true; 
+        let transaction_id_ = execution_outcome.id.to_string(); 
+        let predecessor_id_ = receipt_and_execution_outcome
+            .receipt
+            .predecessor_id
+            .to_string(); 
+        let receiver_id_ = receipt_and_execution_outcome
+            .receipt
+            .receiver_id
+            .to_string();
+
+        //created the vector of execution details associated with this receipt. 
+        //it will be greater than length 1 IF the receipt contains a batch txn.
+        let mut execution_details_vector: Vec<ExecutionDetails> = vec![];
+
+        //get the actions from the receipt
+        if let near_indexer::near_primitives::views::ReceiptEnumView::Action {
+            actions,
+            ..
+        } = receipt_and_execution_outcome.receipt.receipt
+        {
+            //go through each action
+            for action in actions.iter() {
+                //get the args from the action
+                match action {
+                    near_indexer::near_primitives::views::ActionView::FunctionCall {
+                        args,
+                        method_name,
+                        deposit,
                         ..
-                    } = receipt_and_execution_outcome.receipt.receipt
-                    {
-                        //go through each action
-                        for action in actions.iter() {
-                            //get the args from the action
-                            match action {
-                                near_indexer::near_primitives::views::ActionView::FunctionCall {
-                                    args,
-                                    method_name,
-                                    deposit,
-                                    ..
-                                } => {
-                                    //decode the args
-                                    if let Ok(decoded_args) = base64::decode(args) {
-                                        //turn args into json and populate execution details
-                                        if let Ok(args_json) =
-                                            serde_json::from_slice::<serde_json::Value>(&decoded_args)
-                                        {
-                                            args_ = args_json;
-                                        }
-                                    }
-                                    method_name_ = method_name.to_string();
-                                    deposit_ = *deposit;
-
-                                    //create the execution details to push into the vector
-                                    let execution_details = ExecutionDetails {
-                                        method_name: method_name_,
-                                        args: args_.clone(),
-                                        signer_id: signer_id_.clone(),
-                                        deposit: deposit_,
-                                        success_value: success_value_,
-                                        transaction_id: transaction_id_.clone(), // it's not tx id, it's Receipt id
-                                        predecessor_id: predecessor_id_.clone(), 
-                                        receiver_id: receiver_id_.clone(),
-                                    };
-
-                                    execution_details_vector.push(execution_details); 
-                                }
-                                _ => {}
+                    } => {
+                        //decode the args into json (from JSON, Borsh or as hex, see args.rs)
+                        args_ = match args_decoders.decode(&receiver_id_, method_name, args) {
+                            Ok((decoded_args, ArgsEncoding::Hex)) => {
+                                stats.args_decode_failed();
+                                eprintln!("Could not decode the args of {} in receipt {}, keeping them as hex", method_name, transaction_id_);
+                                decoded_args
                             }
-                        }
+                            Ok((decoded_args, _)) => decoded_args,
+                            Err(err) => {
+                                stats.args_decode_failed();
+                                eprintln!("Could not decode the args of {} in receipt {} --> {}", method_name, transaction_id_, err);
+                                serde_json::Value::Null
+                            }
+                        };
+                        method_name_ = method_name.to_string();
+                        deposit_ = *deposit;
+
+                        //create the execution details to push into the vector
+                        let execution_details = ExecutionDetails {
+                            method_name: method_name_,
+                            args: args_.clone(),
+                            signer_id: signer_id_.clone(),
+                            deposit: deposit_,
+                            success_value: success_value_,
+                            transaction_id: transaction_id_.clone(), // it's not tx id, it's Receipt id
+                            predecessor_id: predecessor_id_.clone(), 
+                            receiver_id: receiver_id_.clone(),
+                        };
+
+                        execution_details_vector.push(execution_details); 
                     }
+                    _ => {}
+                }
+            }
+        }
+
+        //loop through each execution detail 
+        for execution_details in execution_details_vector.iter() {
+            eprintln!("Looping through execution details vector. It's of length {}", execution_details_vector.len()); 
 
-                    //loop through each execution detail 
-                    for execution_details in execution_details_vector.iter() {
-                        eprintln!("Looping through execution details vector. It's of length {}", execution_details_vector.len()); 
-                        
-                        //different cases based on the method that was called
-                        match execution_details.method_name.as_str() {
-                            //mint function was called
-                            "nft_mint" => {
-                                eprintln!("Beginning NFT Mint");
-                                //get person who called nft_mint, the token, and contract.
-                                let signer_id = execution_details.signer_id.clone();
-                                let unclean_token_id = execution_details.args.get("token_id").unwrap();
-                                let token_id = str::replace(&unclean_token_id.to_string(), '"', ""); //cleaning up RUST strings
-                                let contract_id = execution_details.receiver_id.clone();
-
-                                //get info from metadata --> add as many fields as are relevant to you
-                                let metadata = execution_details.args.get("metadata").unwrap();
-
-                                let media_option = metadata.get("media");
-                                let artist_account_id_option = metadata.get("artist_account_id");
-                                let charity_account_id_option = metadata.get("charity_account_id");
-                                let title_option = metadata.get("title");
-                                let description_option = metadata.get("description");
-                                let copies_option = metadata.get("copies");
-                                
-                                // HANDLING MINTING LOGIC HERE --> FOR ACTUAL EXAMPLES OF MAKING API CALLS FROM THE INDEXER, REFER TO THE HANDLING OF SOME OF THE OTHER METHODS
-                                eprintln!("Handling nft_mint method here.");
+            //methods listed in the watch config are captured generically, on top of our own handlers below
+            if let Some(watched_method) = watch_config.method(&execution_details.receiver_id, &execution_details.method_name) {
+                let contract_call = IndexerEvent::ContractCall {
+                    contract_id: execution_details.receiver_id.clone(),
+                    method_name: execution_details.method_name.clone(),
+                    signer_id: execution_details.signer_id.clone(),
+                    predecessor_id: execution_details.predecessor_id.clone(),
+                    deposit: execution_details.deposit.to_string(),
+                    receipt_id: execution_details.transaction_id.clone(),
+                    block_height,
+                    fields: watched_method.extract_fields(&execution_details.args),
+                };
+                deliver_event(contract_call, context).await;
+            }
+            //our own handlers only run for our own contracts
+            if !is_fayyr_receipt {
+                continue;
+            }
+
+            //different cases based on the method that was called
+            match execution_details.method_name.as_str() {
+                //mint function was called
+                "nft_mint" => {
+                    eprintln!("Beginning NFT Mint");
+                    //get the token, its metadata and the contract it was minted on
+                    let contract_id = execution_details.receiver_id.clone();
+                    let minted_token = minted_token_from_args(
+                        &execution_details.args,
+                        &contract_id,
+                        &execution_details.signer_id,
+                    )?;
+
+                    deliver_event(IndexerEvent::TokenMinted(minted_token), context).await;
+                }
+                //nft_mint_payout was called, the market is minting an edition someone lazy purchased
+                "nft_mint_payout" => {
+                    eprintln!("Beginning NFT Mint Payout");
+                    let base_token_id = str::replace(&execution_details.args.get("base_token_id").ok_or("nft_mint_payout was called without a base_token_id")?.to_string(), '"', "");
+                    let edition_number = execution_details.args.get("edition_number").ok_or("nft_mint_payout was called without an edition_number")?;
+                    let token_id = edition_token_id(&base_token_id, edition_number)?;
+                    let contract_id = execution_details.receiver_id.clone();
+
+                    let lazy_purchase = lazy_purchases.take(&execution_details.transaction_id).filter(|purchase| {
+                        let same_series = purchase.base_token_id == base_token_id && purchase.contract_id == contract_id;
+                        if !same_series {
+                            eprintln!(
+                                "nft_mint_payout of {} on {} doesn't match the lazy purchase of {} on {} that produced it",
+                                base_token_id, contract_id, purchase.base_token_id, purchase.contract_id
+                            );
+                        }
+                        same_series
+                    });
+                    let (price, purchaser_account_id, sale_receipt_id) = match lazy_purchase {
+                        //the offer that paid for the edition was seen, the sale is reported with what the buyer paid
+                        Some(purchase) => (purchase.deposit, purchase.purchaser_account_id, purchase.offer_receipt_id),
+                        //the offer was missed (e.g. it was in a block before we started), fall back on the mint args
+                        None => {
+                            let price = str::replace(&execution_details.args.get("balance").ok_or("nft_mint_payout was called without a balance")?.to_string(), '"', "");
+                            let price: u128 = price.parse().map_err(|_| format!("invalid balance {} in nft_mint_payout", price))?;
+                            let purchaser_account_id = str::replace(&execution_details.args.get("receiver_id").ok_or("nft_mint_payout was called without a receiver_id")?.to_string(), '"', "");
+                            (price, purchaser_account_id, execution_details.transaction_id.clone())
+                        }
+                    };
+                    let token_sold = IndexerEvent::TokenSold {
+                        token_id: token_id.clone(),
+                        contract_id: contract_id.clone(),
+                        price_near: Some(format!("{:.2}", human(price)).parse().unwrap()),
+                        purchaser_account_id,
+                        receipt_id: sale_receipt_id.clone(),
+                        lazy: true,
+                        block_timestamp,
+                    };
+                    deliver_event(token_sold, context).await;
+
+                    let sale = SaleReceipt {
+                        token_id: &token_id,
+                        contract_id: &contract_id,
+                        receipt_id: &sale_receipt_id,
+                        block_height,
+                        block_timestamp,
+                    };
+                    //nft_mint_payout may return the payout of the sale
+                    deliver_sale_charity(&sale, price, return_value.as_deref().and_then(decode_payout), context).await?;
+                }
+                //the market transferred a sold token, the result says who gets paid what
+                "nft_transfer_payout" => {
+                    eprintln!("Beginning NFT Transfer Payout");
+                    let token_id = str::replace(&execution_details.args.get("token_id").ok_or("nft_transfer_payout was called without a token_id")?.to_string(), '"', "");
+                    let payout = return_value
+                        .as_deref()
+                        .and_then(decode_payout)
+                        .ok_or_else(|| format!("could not decode the payout returned by nft_transfer_payout of {}", token_id))?;
+
+                    let pending_payout = PendingPayout {
+                        token_id,
+                        contract_id: execution_details.receiver_id.clone(),
+                        payout,
+                        block_height,
+                    };
+                    pending_payouts.insert(&output_data_ids, pending_payout);
+                }
+                //the market paid out a sale, using the payout nft_transfer_payout returned
+                "resolve_purchase" => {
+                    eprintln!("Beginning Resolve Purchase");
+                    let pending_sale = pending_payouts.take_sale(&execution_details.transaction_id);
+                    let purchase = pending_sale.as_ref().and_then(|pending_sale| pending_sale.purchase.clone());
+                    let (token_id, contract_id, payout) = match (pending_payouts.take(&input_data_ids), pending_sale) {
+                        //some market versions return the payout they made, which is what was really paid
+                        (Some(pending_payout), _) => {
+                            let payout = return_value
+                                .as_deref()
+                                .and_then(decode_payout)
+                                .unwrap_or_else(|| pending_payout.payout.clone());
+                            (pending_payout.token_id, pending_payout.contract_id, Some(payout))
+                        }
+                        //the sale was seen but not its payout, the charity share comes from the configured split
+                        (None, Some(pending_sale)) => (pending_sale.token_id, pending_sale.contract_id, None),
+                        (None, None) => {
+                            eprintln!("resolve_purchase without a known sale or nft_transfer_payout, no payouts recorded");
+                            continue;
+                        }
+                    };
+                    let price = str::replace(&execution_details.args.get("price").ok_or("resolve_purchase was called without a price")?.to_string(), '"', "");
+                    let price: u128 = price
+                        .parse()
+                        .map_err(|_| format!("resolve_purchase was called with a price that is not a number: {}", price))?;
+
+                    //an offer only sold the token if the market managed to transfer it: either
+                    //  nft_transfer_payout succeeded, or the buyer owns the token now that the transfer ran
+                    if let Some(purchase) = purchase {
+                        let transferred = match &payout {
+                            Some(_) => true,
+                            None => {
+                                let output = viewer
+                                    .nft_tokens_batch(&contract_id, vec![token_id.clone()], block_reference.clone())
+                                    .await?;
+                                let token = output
+                                    .first()
+                                    .ok_or_else(|| format!("nft_tokens_batch returned no token for {}", token_id))?;
+                                token.owner_id == purchase.purchaser_account_id
                             }
-                            //nft_mint_payout was called
-                            "nft_mint_payout" => {
-                                eprintln!("Beginning NFT Mint Payout");
-                                let unclean_base_token_id = execution_details.args.get("base_token_id").unwrap();
-                                let base_token_id = str::replace(&unclean_base_token_id.to_string(), '"', "");
+                        };
+                        if !transferred {
+                            eprintln!("Signer Is Not Owner... Transaction Failed.");
+                            continue;
+                        }
 
-                                let unclean_edition_number = execution_details.args.get("edition_number").unwrap();
-                                let edition_number = str::replace(&unclean_edition_number.to_string(), '"', "");
+                        let token_sold = IndexerEvent::TokenSold {
+                            token_id: token_id.clone(),
+                            contract_id: contract_id.clone(),
+                            price_near: Some(human(purchase.price)),
+                            purchaser_account_id: purchase.purchaser_account_id,
+                            receipt_id: purchase.offer_receipt_id,
+                            lazy: false,
+                            block_timestamp,
+                        };
+                        deliver_event(token_sold, context).await;
+                    }
 
-                                let unclean_price = execution_details.args.get("balance").unwrap();
-                                let price = str::replace(&unclean_price.to_string(), '"', "");
+                    let sale = SaleReceipt {
+                        token_id: &token_id,
+                        contract_id: &contract_id,
+                        receipt_id: &execution_details.transaction_id,
+                        block_height,
+                        block_timestamp,
+                    };
+                    deliver_sale_charity(&sale, price, payout, context).await?;
+                }
+                //nft_on_approve was called
+                "nft_on_approve" => {
+                    eprintln!("Beginning NFT On Approve");
+                    let token_id_for_api = execution_details.args.get("token_id").ok_or("nft_on_approve was called without a token_id")?;
+                    let contract_id_for_api = execution_details.predecessor_id.to_string();
+                    let token_id = str::replace(&token_id_for_api.to_string(), '"', "");
 
-                                let price_for_api_string =
-                                    format!("{:.2}", human(price.parse().unwrap()));
+                    //call nft_tokens_batch in order to get access to the token metadata
+                    let output = viewer
+                        .nft_tokens_batch(&contract_id_for_api, vec![token_id.clone()], block_reference.clone())
+                        .await?;
+                    let token = output
+                        .first()
+                        .ok_or_else(|| format!("nft_tokens_batch returned no token for {}", token_id))?;
+
+                    if let Some(_media) = token.metadata.media.clone() {
+                        let msg = execution_details
+                            .args
+                            .get("msg")
+                            .and_then(|msg| msg.as_str())
+                            .ok_or("nft_on_approve was called without a msg string")?;
+                        let SaleArgs { sale_conditions } = near_sdk::serde_json::from_str(msg)
+                            .map_err(|err| format!("nft_on_approve was called with a msg that has no sale conditions --> {}", err))?;
+
+                        for (ft_token_id, price) in sale_conditions.clone() {
+                            if &ft_token_id.to_string() == "near" {
+                                let price_for_api_string = format!("{:.2}", human(price.0));
                                 let price_for_api: f64 = price_for_api_string.parse().unwrap();
 
-                                //get the transaction ID for the api
-                                let transaction_id = execution_details.transaction_id.clone();
-                                    
-                                //get the purchaser which in this case is the receiver
-                                let unclean_receiver_id = execution_details.args.get("receiver_id").unwrap();
-                                let receiver_id = str::replace(&unclean_receiver_id.to_string(), '"', "");
-
-                                let contract_id = execution_details.receiver_id.clone();
-                                let token_split_option = base_token_id.rsplit_once("_0");
-
-                                if !token_split_option.is_none() {
-                                    //get the token split
-                                    let token_split = token_split_option.unwrap(); 
-
-                                    //get the base token
-                                    let base_token_no_edition = token_split.0;
-                                    let token_id = format!("{}_{}", base_token_no_edition, edition_number);
-                                    
-                                    //we sell the token in the database because it was lazy purchased (minting without approvals process means it is not a base token)
-                                    let _sell_token_result = database::sell_token_in_database(
-                                        token_id.to_string(), 
-                                        contract_id.to_string(), 
-                                        Some(price_for_api), 
-                                        receiver_id, 
-                                        admin_account.clone(), 
-                                        transaction_id.to_string(), 
-                                        signature_header.clone(),
-                                            &private_api_root,
-                                        debug_mode.clone()).await;
-                                } else {
-                                    eprintln!("Cannot proceed with nft_mint_payout logic. the token ID was not a base token: {:?}", base_token_id.to_string());
-                                }
-                            }
-                            //nft_on_approve was called
-                            "nft_on_approve" => {
-                                eprintln!("Beginning NFT On Approve");
-                                let token_id_for_api = execution_details.args.get("token_id").unwrap();
-                                let contract_id_for_api = execution_details.predecessor_id.to_string();
-
-                                //build the function arguments to get passed into nft_tokens_batch
-                                let function_args = serde_json::json!({
-                                    "token_ids": [token_id_for_api],
-                                });
-
-                                //call nft_tokens_batch in order to get access to the current token owner
-                                let block_reference = BlockReference::latest();
-                                let request = QueryRequest::CallFunction {
-                                    //contract to call
-                                    account_id: contract_id_for_api.parse().unwrap(),
-                                    //method to view
-                                    method_name: "nft_tokens_batch".to_string(),
-                                    //passed in arguments
-                                    args: FunctionArgs::from(function_args.to_string().into_bytes()),
+                                eprintln!("Beginning API call to put up for sale.");
+                                let token_listed = IndexerEvent::TokenListed {
+                                    token_id: token_id.clone(),
+                                    contract_id: contract_id_for_api.clone(),
+                                    price_near: price_for_api,
+                                    block_timestamp,
                                 };
-                                let query = Query::new(block_reference, request);
-                                //get the response
-                                let response = view_client.send(query).await.unwrap().unwrap();
-
-                                if let near_indexer::near_primitives::views::QueryResponseKind::CallResult(call_result) = response.kind {
-                                    let result = call_result.result;
-
-                                    //set the output (of type vec<JsonToken>) equal to the result
-                                    let output: Vec<JsonToken> = serde_json::from_slice(&result).unwrap();
-                                    if let Some(media) = output[0].clone().metadata.media {
-                                        let SaleArgs { sale_conditions } = near_sdk::serde_json::from_str(execution_details.args.get("msg").unwrap().as_str().unwrap()).unwrap();
-
-                                        for (ft_token_id, price) in sale_conditions.clone() {
-                                            if &ft_token_id.to_string() == "near" {
-                                                let price_for_api_string = format!("{:.2}", human(price.0));
-                                                let price_for_api: f64 = price_for_api_string.parse().unwrap();
-
-                                                eprintln!("Beginning API call to put up for sale.");
-                                                let _put_for_sale_status = database::insert_token_forsale_in_database(
-                                                    token_id_for_api.to_string(),
-                                                    contract_id_for_api.to_string(),
-                                                    price_for_api,
-                                                    signature_header.clone(),
-                                                                &private_api_root,
-                                                    debug_mode.clone()
-                                                ).await;
-
-                                        }
-                                    } else {
-                                        eprintln!("Metadata has no media field... --> {:?}", output[0].metadata);
-                                    }
-                                }
+                                deliver_event(token_listed, context).await;
                             }
-                            //if update_price was called
-                            "update_price" => {
-                                eprintln!("Update Price Has Been Called");
-                                let token_id_for_api = execution_details.args.get("token_id").unwrap();
-                                let contract_id_for_api =
-                                    execution_details.args.get("nft_contract_id").unwrap();
-
-                                let price = execution_details.args.get("price").unwrap();
-                                let clean_price = str::replace(&price.to_string(), '"', "");
-                                let price_for_api_string =
-                                    format!("{:.2}", human(clean_price.parse().unwrap()));
-                                let price_for_api: f64 = price_for_api_string.parse().unwrap();
+                        }
+                    } else {
+                        eprintln!("Metadata has no media field... --> {:?}", token.metadata);
+                    }
 
-                                let _result = database::update_price_for_token_in_database(
-                                    token_id_for_api.to_string(),
-                                    contract_id_for_api.to_string(),
-                                    price_for_api,
-                                    signature_header.clone(),
-                                    &private_api_root,
-                                    debug_mode.clone()
-                                )
-                                .await;
-                            }
-                            //if offer was called
-                            "offer" => {
-                                eprintln!("Offer has been called");
-                                let lazy_purchase_option = execution_details.args.get("lazy_purchase");
-
-                                //only do stuff if lazy_purchase_option wasn't passed in (meaning it's a regular purchase)
-                                if lazy_purchase_option.is_none() {
-                                    //build the function arguments to get passed into nft_tokens_batch
-                                    let function_args = serde_json::json!({
-                                        "token_ids": [execution_details.args.get("token_id").unwrap()],
-                                    });
-
-                                    //call nft_tokens_batch in order to get access to the current token owner
-                                    let block_reference = BlockReference::latest();
-                                    let request = QueryRequest::CallFunction {
-                                        //contract to call
-                                        account_id:
-                                            near_indexer::near_primitives::types::AccountId::from_str(
-                                                &nft_contract,
-                                            )
-                                            .expect(
-                                                "failed to convert nft contract to account ID in view call",
-                                            ),
-                                        //method to view
-                                        method_name: "nft_tokens_batch".to_string(),
-                                        //passed in arguments
-                                        args: FunctionArgs::from(function_args.to_string().into_bytes()),
-                                    };
-                                    let query = Query::new(block_reference, request);
-                                    //get the response
-                                    let response = view_client.send(query).await.unwrap().unwrap();
-
-                                    if let near_indexer::near_primitives::views::QueryResponseKind::CallResult(call_result) = response.kind {
-                                        let result = call_result.result;
-
-                                        //set the output (of type vec<JsonToken>) equal to the result
-                                        let output: Vec<JsonToken> = serde_json::from_slice(&result).unwrap();
-
-                                        //check if the owner is the signer
-                                        if output[0].owner_id == execution_details.signer_id {
-                                            let price: f64 = human(execution_details.deposit);
-                                            let purchaser_account_id = execution_details.signer_id.clone();
-                                            let transaction_id = execution_details.transaction_id.clone();
-
-                                            let token_id_for_api = execution_details.args.get("token_id").unwrap();
-                                            let contract_id_for_api = execution_details.args.get("nft_contract_id").unwrap();
-
-                                            let _sell_token_result = database::sell_token_in_database(
-                                                token_id_for_api.to_string(), 
-                                                contract_id_for_api.to_string(), 
-                                                Some(price), purchaser_account_id, 
-                                                admin_account.clone(), 
-                                                transaction_id.to_string(), 
-                                                signature_header.clone(),
-                                                &private_api_root,
-                                                debug_mode.clone()).await;
-
-                                        } else {
-                                            eprintln!("Signer Is Not Owner... Transaction Failed.");
-                                        }
-                                    }
-                                }
-                            }
-                            //if remove_sale was called
-                            "remove_sale" => {
-                                eprintln!("Beginning API Call to remove sale");
-
-                                let token_id_for_api = execution_details.args.get("token_id").unwrap();
-                                let contract_id_for_api =
-                                    execution_details.args.get("nft_contract_id").unwrap();
-
-                                let _result = database::remove_token_forsale_in_database(
-                                    token_id_for_api.to_string(),
-                                    contract_id_for_api.to_string(),
-                                    signature_header.clone(),
-                                    &private_api_root,
-                                    debug_mode.clone()
-                                )
-                                .await;
-                            }
-                            //if place was called
-                            "place_bid" => {
-                                eprintln!("Place Bid Was Called ---> {:#?}", execution_details.clone());
+                    //the listing used up some of the owner's storage on the market. failing to read it
+                    //  back shouldn't hold up the listing itself, so it is only logged
+                    let owner_id = execution_details
+                        .args
+                        .get("owner_id")
+                        .map(|owner_id| str::replace(&owner_id.to_string(), '"', ""))
+                        .unwrap_or_else(|| execution_details.signer_id.clone());
+                    if let Err(err) = deliver_storage_balance(
+                        &owner_id,
+                        StorageChange::Listing,
+                        None,
+                        &execution_details.transaction_id,
+                        block_height,
+                        block_timestamp,
+                        context,
+                    )
+                    .await
+                    {
+                        eprintln!("Could not read the market storage of {} after listing {} --> {}", owner_id, token_id, err);
+                    }
+                }
+                //if update_price was called
+                "update_price" => {
+                    eprintln!("Update Price Has Been Called");
+                    let token_id_for_api = execution_details.args.get("token_id").ok_or("update_price was called without a token_id")?;
+                    let contract_id_for_api =
+                        execution_details.args.get("nft_contract_id").ok_or("update_price was called without a nft_contract_id")?;
 
-                                // handle place_bid method here
-                            }
-                            //accept offer was called
-                            "accept_offer" => {
-                                eprintln!(
-                                    "Accept Offer Was Called ---> {:#?}",
-                                    execution_details.clone()
-                                );
-                            }
-                            //accept offer was called
-                            "nft_revoke" => {
-                                eprintln!("nft_revoke was called");
-
-                                let token_id_for_api = execution_details.args.get("token_id").unwrap();
-                                let account_being_revoked =
-                                    execution_details.args.get("account_id").unwrap();
-
-                                let contract_id_for_api = execution_details.predecessor_id.clone();
-
-                                if account_being_revoked.to_string() == market_contract {
-                                    eprintln!("nft_revoke was called on OUR market account...");
-                                    // TODO: the result is not used, do next call rely on this one?
-                                    let _result = database::remove_token_forsale_in_database(
-                                        token_id_for_api.to_string(),
-                                        contract_id_for_api.to_string(),
-                                        signature_header.clone(),
-                                        &private_api_root,
-                                        debug_mode.clone(),
-                                    )
-                                    .await;
-                                }
-                            }
-                            //accept offer was called
-                            "nft_revoke_all" => {
-                                eprintln!(
-                                    "Beginning API Call to remove sale since nft_revoke_all was called"
-                                );
-                                let token_id_for_api = execution_details.args.get("token_id").unwrap();
-                                let contract_id_for_api = execution_details.predecessor_id.clone();
-                                // TODO: the result is not used, do next call rely on this one?
-                                let _result = database::remove_token_forsale_in_database(
-                                    token_id_for_api.to_string(),
-                                    contract_id_for_api.to_string(),
-                                    signature_header.clone(),
-                                    &private_api_root,
-                                    debug_mode.clone(),
-                                )
-                                .await;
-                            }
-                            //some other transaction was called
-                            _ => {
-                                //print the entire execution outcome for the current receipt
-                                eprintln!(
-                                    "Other TXN Called ---> {:?} By {:?}",
-                                    execution_details.method_name.as_str(),
-                                    execution_details.signer_id.as_str()
-                                );
+                    let price = execution_details.args.get("price").ok_or("update_price was called without a price")?;
+                    let clean_price = str::replace(&price.to_string(), '"', "");
+                    let clean_price: u128 = clean_price
+                        .parse()
+                        .map_err(|_| format!("update_price was called with a price that is not a number: {}", clean_price))?;
+                    let price_for_api_string =
+                        format!("{:.2}", human(clean_price));
+                    let price_for_api: f64 = price_for_api_string.parse().unwrap();
+
+                    let price_updated = IndexerEvent::PriceUpdated {
+                        token_id: str::replace(&token_id_for_api.to_string(), '"', ""),
+                        contract_id: str::replace(&contract_id_for_api.to_string(), '"', ""),
+                        price_near: price_for_api,
+                        block_timestamp,
+                    };
+                    deliver_event(price_updated, context).await;
+                }
+                //if offer was called
+                "offer" => {
+                    eprintln!("Offer has been called");
+                    let lazy_purchase_option = execution_details.args.get("lazy_purchase");
+
+                    //a lazy purchase is reported once the market mints the edition (see nft_mint_payout)
+                    if lazy_purchase_option.is_some() {
+                        let base_token_id = str::replace(&execution_details.args.get("token_id").ok_or("offer was called without a token_id")?.to_string(), '"', "");
+                        let contract_id = execution_details
+                            .args
+                            .get("nft_contract_id")
+                            .map(|contract_id| str::replace(&contract_id.to_string(), '"', ""))
+                            .unwrap_or_else(|| nft_contract.clone());
+                        let lazy_purchase = LazyPurchase {
+                            offer_receipt_id: execution_details.transaction_id.clone(),
+                            contract_id,
+                            base_token_id,
+                            purchaser_account_id: execution_details.signer_id.clone(),
+                            deposit: execution_details.deposit,
+                            block_height,
+                        };
+                        lazy_purchases.insert(&produced_receipt_ids, lazy_purchase);
+                    } else {
+                        let token_id = str::replace(&execution_details.args.get("token_id").ok_or("offer was called without a token_id")?.to_string(), '"', "");
+                        let contract_id = str::replace(&execution_details.args.get("nft_contract_id").ok_or("offer was called without a nft_contract_id")?.to_string(), '"', "");
+
+                        //the market only transfers the token in a later receipt, so at this height it still
+                        //  belongs to the seller. the sale is reported once the market resolves the purchase
+                        let pending_sale = PendingSale {
+                            token_id,
+                            contract_id,
+                            block_height,
+                            purchase: Some(OfferPurchase {
+                                purchaser_account_id: execution_details.signer_id.clone(),
+                                price: execution_details.deposit,
+                                offer_receipt_id: execution_details.transaction_id.clone(),
+                            }),
+                        };
+                        pending_payouts.insert_sale(&produced_receipt_ids, pending_sale);
+                    }
+                }
+                //if remove_sale was called
+                "remove_sale" => {
+                    eprintln!("Beginning API Call to remove sale");
+
+                    let token_id_for_api = execution_details.args.get("token_id").ok_or("remove_sale was called without a token_id")?;
+                    let contract_id_for_api =
+                        execution_details.args.get("nft_contract_id").ok_or("remove_sale was called without a nft_contract_id")?;
+
+                    let listing_removed = IndexerEvent::ListingRemoved {
+                        token_id: str::replace(&token_id_for_api.to_string(), '"', ""),
+                        contract_id: str::replace(&contract_id_for_api.to_string(), '"', ""),
+                    };
+                    deliver_event(listing_removed, context).await;
+                }
+                //a bid was placed on a token
+                "place_bid" => {
+                    eprintln!("Place Bid Was Called");
+                    let token_id = str::replace(&execution_details.args.get("token_id").ok_or("place_bid was called without a token_id")?.to_string(), '"', "");
+                    let contract_id = execution_details
+                        .args
+                        .get("nft_contract_id")
+                        .map(|contract_id| str::replace(&contract_id.to_string(), '"', ""))
+                        .unwrap_or_else(|| nft_contract.clone());
+                    let ft_token_id = execution_details
+                        .args
+                        .get("ft_token_id")
+                        .map(|ft_token_id| str::replace(&ft_token_id.to_string(), '"', ""))
+                        .unwrap_or_else(|| "near".to_string());
+                    //near bids are paid with the attached deposit, FT bids say how much they are for
+                    let amount = match execution_details.args.get("amount") {
+                        Some(amount) => str::replace(&amount.to_string(), '"', ""),
+                        None => execution_details.deposit.to_string(),
+                    };
+
+                    let bid = HighestBid {
+                        bidder_account_id: execution_details.signer_id.clone(),
+                        ft_token_id: ft_token_id.clone(),
+                        amount: amount.clone(),
+                        receipt_id: execution_details.transaction_id.clone(),
+                    };
+                    match bids.place_bid(&contract_id, &token_id, bid) {
+                        BidOutcome::Outbid(previous) => {
+                            let bid_outbid = IndexerEvent::BidOutbid {
+                                token_id: token_id.clone(),
+                                contract_id: contract_id.clone(),
+                                bidder_account_id: previous.bidder_account_id,
+                                ft_token_id: previous.ft_token_id,
+                                amount: previous.amount,
+                                outbid_by_account_id: execution_details.signer_id.clone(),
+                            };
+                            deliver_event(bid_outbid, context).await;
+                        }
+                        BidOutcome::NotHighest(standing) => {
+                            eprintln!(
+                                "Bid of {} on {} is not above the standing bid of {} by {}",
+                                amount, token_id, standing.amount, standing.bidder_account_id
+                            );
+                        }
+                        BidOutcome::First => {}
+                    }
+
+                    let bid_placed = IndexerEvent::BidPlaced {
+                        token_id,
+                        contract_id,
+                        bidder_account_id: execution_details.signer_id.clone(),
+                        ft_token_id,
+                        amount,
+                        receipt_id: execution_details.transaction_id.clone(),
+                    };
+                    deliver_event(bid_placed, context).await;
+                }
+                //the owner accepted the highest bid on their token
+                "accept_offer" => {
+                    eprintln!("Accept Offer Was Called");
+                    let token_id = str::replace(&execution_details.args.get("token_id").ok_or("accept_offer was called without a token_id")?.to_string(), '"', "");
+                    let contract_id = execution_details
+                        .args
+                        .get("nft_contract_id")
+                        .map(|contract_id| str::replace(&contract_id.to_string(), '"', ""))
+                        .unwrap_or_else(|| nft_contract.clone());
+
+                    //the winning bid is the one we have been tracking. if we started indexing after it
+                    //  was placed, ask the market what the sale looked like just before this block
+                    let winning_bid = match bids.settle(&contract_id, &token_id) {
+                        Some(winning_bid) => winning_bid,
+                        None => {
+                            let block_before = BlockReference::BlockId(BlockId::Height(block_height.saturating_sub(1)));
+                            let sale = viewer
+                                .get_sale(&market_contract, &contract_id, &token_id, block_before)
+                                .await?
+                                .ok_or_else(|| format!("accept_offer on {} but there is no sale for it", token_id))?;
+                            let ft_token_id = execution_details
+                                .args
+                                .get("ft_token_id")
+                                .map(|ft_token_id| str::replace(&ft_token_id.to_string(), '"', ""))
+                                .unwrap_or_else(|| "near".to_string());
+                            //the market keeps the bids of each FT in the order they were placed, highest last
+                            let bid = sale
+                                .bids
+                                .iter()
+                                .find(|(bid_ft_token_id, _)| bid_ft_token_id.to_string() == ft_token_id)
+                                .and_then(|(_, bids)| bids.last())
+                                .ok_or_else(|| format!("accept_offer on {} but there is no {} bid on it", token_id, ft_token_id))?;
+                            HighestBid {
+                                bidder_account_id: bid.owner_id.clone(),
+                                ft_token_id,
+                                amount: bid.price.0.to_string(),
+                                receipt_id: String::new(),
                             }
+                        }
+                    };
+
+                    let price_near = if winning_bid.ft_token_id == "near" {
+                        Some(format!("{:.2}", human(winning_bid.amount())).parse().unwrap())
+                    } else {
+                        None
+                    };
+                    //the charity share is recorded once the market resolves the purchase, the split
+                    //  only makes sense for a price in near
+                    if winning_bid.ft_token_id == "near" {
+                        let pending_sale = PendingSale {
+                            token_id: token_id.clone(),
+                            contract_id: contract_id.clone(),
+                            block_height,
+                            purchase: None,
+                        };
+                        pending_payouts.insert_sale(&produced_receipt_ids, pending_sale);
                     }
-                    
+
+                    let offer_accepted = IndexerEvent::OfferAccepted {
+                        token_id,
+                        contract_id,
+                        buyer_account_id: winning_bid.bidder_account_id,
+                        ft_token_id: winning_bid.ft_token_id,
+                        amount: winning_bid.amount,
+                        price_near,
+                        receipt_id: execution_details.transaction_id.clone(),
+                        block_timestamp,
+                    };
+                    deliver_event(offer_accepted, context).await;
+                }
+                //storage was paid for on the market, for the signer or for the account_id they passed
+                "storage_deposit" if execution_details.receiver_id == *market_contract => {
+                    eprintln!("Storage Deposit Was Called");
+                    let account_id = execution_details
+                        .args
+                        .get("account_id")
+                        .filter(|account_id| !account_id.is_null())
+                        .map(|account_id| str::replace(&account_id.to_string(), '"', ""))
+                        .unwrap_or_else(|| execution_details.signer_id.clone());
+                    deliver_storage_balance(
+                        &account_id,
+                        StorageChange::Deposit,
+                        Some(execution_details.deposit),
+                        &execution_details.transaction_id,
+                        block_height,
+                        block_timestamp,
+                        context,
+                    )
+                    .await?;
+                }
+                //the storage not used by listings was taken back
+                "storage_withdraw" if execution_details.receiver_id == *market_contract => {
+                    eprintln!("Storage Withdraw Was Called");
+                    deliver_storage_balance(
+                        &execution_details.predecessor_id,
+                        StorageChange::Withdraw,
+                        None,
+                        &execution_details.transaction_id,
+                        block_height,
+                        block_timestamp,
+                        context,
+                    )
+                    .await?;
+                }
+                //accept offer was called
+                "nft_revoke" => {
+                    eprintln!("nft_revoke was called");
+
+                    let token_id_for_api = execution_details.args.get("token_id").ok_or("nft_revoke was called without a token_id")?;
+                    let account_being_revoked =
+                        execution_details.args.get("account_id").ok_or("nft_revoke was called without an account_id")?;
+
+                    let contract_id_for_api = execution_details.predecessor_id.clone();
+
+                    if str::replace(&account_being_revoked.to_string(), '"', "") == *market_contract {
+                        eprintln!("nft_revoke was called on OUR market account...");
+                        let listing_removed = IndexerEvent::ListingRemoved {
+                            token_id: str::replace(&token_id_for_api.to_string(), '"', ""),
+                            contract_id: str::replace(&contract_id_for_api.to_string(), '"', ""),
+                        };
+                        deliver_event(listing_removed, context).await;
                     }
-                } else {
-                    //print execution details (FAILED)
+                }
+                //accept offer was called
+                "nft_revoke_all" => {
                     eprintln!(
-                        "Unsuccessful Execution Details related to Fayyr --> {:#?}",
-                        execution_outcome
+                        "Beginning API Call to remove sale since nft_revoke_all was called"
                     );
+                    let token_id_for_api = execution_details.args.get("token_id").ok_or("nft_revoke_all was called without a token_id")?;
+                    let contract_id_for_api = execution_details.predecessor_id.clone();
+                    let listing_removed = IndexerEvent::ListingRemoved {
+                        token_id: str::replace(&token_id_for_api.to_string(), '"', ""),
+                        contract_id: str::replace(&contract_id_for_api.to_string(), '"', ""),
+                    };
+                    deliver_event(listing_removed, context).await;
                 }
-            }
+                //some other transaction was called
+                _ => {
+                    //print the entire execution outcome for the current receipt
+                    eprintln!(
+                        "Other TXN Called ---> {:?} By {:?}",
+                        execution_details.method_name.as_str(),
+                        execution_details.signer_id.as_str()
+                    );
+                }
+        }
+
+        }
+    } else {
+        //print execution details (FAILED)
+        eprintln!(
+            "Unsuccessful Execution Details related to Fayyr --> {:#?}",
+            execution_outcome
+        );
+    }
+    Ok(())
+}
+
+//the sale a payout or a donation belongs to
+struct SaleReceipt<'a> {
+    token_id: &'a str,
+    contract_id: &'a str,
+    receipt_id: &'a str,
+    block_height: u64,
+    block_timestamp: u64,
+}
+
+impl SaleReceipt<'_> {
+    fn donation(&self, charity_account_id: &str, amount: u128, source: DonationSource) -> IndexerEvent {
+        IndexerEvent::CharityDonated(CharityDonation {
+            charity_account_id: charity_account_id.to_string(),
+            token_id: self.token_id.to_string(),
+            contract_id: self.contract_id.to_string(),
+            amount: amount.to_string(),
+            amount_near: human(amount),
+            source,
+            receipt_id: self.receipt_id.to_string(),
+            block_height: self.block_height,
+            block_timestamp: self.block_timestamp,
+        })
+    }
+
+    //metadata of the sold token, which names its artist and charity
+    async fn token_metadata(&self, context: &IndexerContext) -> Result<TokenMetadata, String> {
+        let block_reference = BlockReference::BlockId(BlockId::Height(self.block_height));
+        let output = context
+            .viewer
+            .nft_tokens_batch(self.contract_id, vec![self.token_id.to_string()], block_reference)
+            .await?;
+        let token = output
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("nft_tokens_batch returned no token for {}", self.token_id))?;
+        Ok(token.metadata)
+    }
+}
+
+//report every recipient of the payout of a sale, and the share of the charity as a donation
+async fn deliver_payout(
+    sale: &SaleReceipt<'_>,
+    payout: HashMap<String, u128>,
+    context: &IndexerContext,
+) -> Result<(), String> {
+    let metadata = sale.token_metadata(context).await?;
+
+    let mut payout: Vec<(String, u128)> = payout.into_iter().collect();
+    payout.sort();
+    for (recipient_account_id, amount) in payout {
+        let role = payout_role(&recipient_account_id, &metadata);
+        let payout_recorded = IndexerEvent::PayoutRecorded {
+            token_id: sale.token_id.to_string(),
+            contract_id: sale.contract_id.to_string(),
+            recipient_account_id: recipient_account_id.clone(),
+            role,
+            amount: amount.to_string(),
+            amount_near: human(amount),
+            receipt_id: sale.receipt_id.to_string(),
+        };
+        deliver_event(payout_recorded, context).await;
+
+        if role == PayoutRole::Charity {
+            deliver_event(sale.donation(&recipient_account_id, amount, DonationSource::Payout), context).await;
         }
     }
+    Ok(())
+}
+
+//the charity share of a sale: from its payout when there is one, otherwise from the configured split
+async fn deliver_sale_charity(
+    sale: &SaleReceipt<'_>,
+    price: u128,
+    payout: Option<HashMap<String, u128>>,
+    context: &IndexerContext,
+) -> Result<(), String> {
+    match (payout, context.charity_split_percent) {
+        (Some(payout), _) => deliver_payout(sale, payout, context).await,
+        (None, Some(split_percent)) => deliver_charity_split(sale, price, split_percent, context).await,
+        (None, None) => Ok(()),
+    }
+}
+
+//attribute the configured split of the sale price to the charity of the token
+async fn deliver_charity_split(
+    sale: &SaleReceipt<'_>,
+    price: u128,
+    split_percent: f64,
+    context: &IndexerContext,
+) -> Result<(), String> {
+    let metadata = sale.token_metadata(context).await?;
+    let donation = sale.donation(&metadata.charity_account_id, charity_share(price, split_percent), DonationSource::Split);
+    deliver_event(donation, context).await;
+    Ok(())
 }
 
 async fn listen_blocks(
-    stream: mpsc::Receiver<near_indexer::StreamerMessage>,
-    view_client: Addr<ViewClientActor>,
-    nft_contract: String,
-    market_contract: String,
-    admin_account_id: String,
-    public_api_root: String,
-    private_api_root: String, 
-    signature_header: String,
-    debug_mode: String,
+    mut stream: mpsc::Receiver<near_indexer::StreamerMessage>,
+    context: Arc<IndexerContext>,
+    shutdown_timeout: Duration,
 ) {
-    //listen for streams
-    let mut handle_messages = tokio_stream::wrappers::ReceiverStream::new(stream)
-        .map(|streamer_message| {
-            eprintln!("Block Height {}", &streamer_message.block.header.height);
-            handle_messages(
-                streamer_message,
-                view_client.clone(),
-                nft_contract.clone(),
-                market_contract.clone(),
-                admin_account_id.clone(),
-                public_api_root.clone(),
-                private_api_root.clone(), 
-                signature_header.clone(),
-                debug_mode.clone(),
-            )
-        })
-        .buffer_unordered(100); //1 is concurrency as per Bohdan's suggestion
+    let stats = &context.stats;
+    //blocks that are currently being handled
+    let mut in_flight = FuturesUnordered::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    //listen for streams until we are asked to stop
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                eprintln!("Shutting down, no longer reading new blocks from the stream");
+                break;
+            }
+            streamer_message = stream.recv(), if in_flight.len() < MAX_IN_FLIGHT_BLOCKS => {
+                let streamer_message = match streamer_message {
+                    Some(streamer_message) => streamer_message,
+                    None => {
+                        eprintln!("The block stream was closed");
+                        break;
+                    }
+                };
+                let block_height = streamer_message.block.header.height;
+                eprintln!("Block Height {}", block_height);
+                if let Some(recorder) = &context.recorder {
+                    recorder.record_block(&streamer_message);
+                }
+                stats.block_received(block_height);
+                //the new block can make earlier ones final, or replace them
+                let released = context.outbox.block_received(BlockInfo::from(&streamer_message.block.header));
+                context.follow_chain_changes();
+                let handled_message = handle_messages(streamer_message, context.clone());
+                let stats = stats.clone();
+                let context = context.clone();
+                in_flight.push(async move {
+                    send_events(released, &context).await;
+                    handled_message.await;
+                    stats.set_outbox_backlog(context.outbox.undelivered_heights().len());
+                    stats.block_processed(block_height);
+                    save_progress(&context, true);
+                });
+            }
+            Some(_handled_message) = in_flight.next(), if !in_flight.is_empty() => {}
+        }
+    }
+    drop(stream);
+
+    //give the blocks that are still in flight a chance to finish their API calls
+    eprintln!("Waiting up to {:?} for {} in-flight blocks", shutdown_timeout, in_flight.len());
+    let drain = async { while let Some(_handled_message) = in_flight.next().await {} };
+    if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+        eprintln!(
+            "Timed out waiting for blocks {:?}, they will be handled again on the next run",
+            stats.in_flight_heights()
+        );
+    }
 
-    while let Some(_handled_message) = handle_messages.next().await {}
+    if context.dry_run.is_none() {
+        push_collection_stats(&context).await;
+        context.analytics.save_if_changed();
+    }
+    //remember where we stopped so the next run can pick up the unfinished blocks
+    save_progress(&context, false);
+    eprintln!("Stopped at {:?}", current_checkpoint(&context));
+
+    actix::System::current().stop();
+}
+
+//the blocks the next run has to start from, including the handled ones whose events
+//  were still held back
+fn current_checkpoint(context: &IndexerContext) -> Checkpoint {
+    let mut unfinished_heights = context.stats.in_flight_heights();
+    unfinished_heights.extend(context.outbox.undelivered_heights());
+    unfinished_heights.sort_unstable();
+    unfinished_heights.dedup();
+    Checkpoint {
+        last_processed_height: context.stats.last_processed_height(),
+        unfinished_heights,
+    }
+}
+
+//rewrite the status and the checkpoint together, so the checkpoint is never older than the status
+fn save_progress(context: &IndexerContext, running: bool) {
+    //blocks of a dry run never reached the API, the next real run mustn't start after them
+    if context.dry_run.is_some() {
+        return;
+    }
+    if let Err(err) = save_status(&context.home_dir, &indexer_status(context, running)) {
+        eprintln!("Failed to save the indexer status --> {:?}", err);
+    }
+    let checkpoint = current_checkpoint(context);
+    if let Err(err) = save_checkpoint(&context.home_dir, &checkpoint) {
+        eprintln!("Failed to save checkpoint {:?} --> {:?}", checkpoint, err);
+    }
+}
+
+//feed recorded blocks through the handlers one after the other
+async fn replay_blocks(blocks: Vec<near_indexer::StreamerMessage>, context: Arc<IndexerContext>) {
+    for streamer_message in blocks {
+        let block_height = streamer_message.block.header.height;
+        eprintln!("Replaying Block Height {}", block_height);
+        context.stats.block_received(block_height);
+        handle_messages(streamer_message, context.clone()).await;
+        context.stats.block_processed(block_height);
+    }
+    eprintln!("Replay finished at block {}", context.stats.last_processed_height());
+}
+
+//resolves once the process receives SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => eprintln!("Received SIGINT"),
+        _ = terminate.recv() => eprintln!("Received SIGTERM"),
+    }
 }
 
 // Checks if the receipt is for our target nft and market contracts, and that
@@ -553,56 +1253,213 @@ fn main() {
 
     match opts.subcmd {
         //if we run cargo run -- run
-        SubCommand::Run => {
-            let nft_contract: String =
-                env::var("NFT").expect("NFT Contract Env Variable Not Specified");
-            let market_contract: String =
-                env::var("MARKET").expect("Market Contract Env Variable Not Specified");
-            let admin_account: String =
-                env::var("ADMIN").expect("Admin Account Env Variable Not Specified");
-            let private_api_root: String =
-                env::var("PRIVATE_API").expect("Fayyr Private API Root Env Variable Not Specified");
-            let public_api_root: String =
-                env::var("PUBLIC_API").expect("Fayyr Public API Root Env Variable Not Specified");    
-            let debug_mode: String =
-                env::var("DEBUG").expect("Debugging Mode Env Variable Not Specified");
-            let signature_header: String =
-                env::var("HEADER").expect("Signature Header Env Variable Not Specified");
-
-
-            eprintln!("Starting Indexer With NFT: {:?}, Market: {:?}, and Fayyr Account: {:?} and Debugging With: {:?} with Signature Header: {:?}", nft_contract, market_contract, admin_account, debug_mode, signature_header);
+        SubCommand::Run(args) => {
+            let start_from = args.start_from().unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            });
+            //by default resume the last run, from the first block it left unfinished if there are any
+            let last_handled_height = load_status(&home_dir).map(|status| status.last_processed_height);
+            let sync_mode = match start_at(start_from, load_checkpoint(&home_dir).as_ref(), last_handled_height) {
+                StartAt::Latest => {
+                    eprintln!("Starting from the latest block");
+                    near_indexer::SyncModeEnum::LatestSynced
+                }
+                StartAt::Height(height) => {
+                    eprintln!("Starting from block {}", height);
+                    near_indexer::SyncModeEnum::BlockHeight(height)
+                }
+            };
+            let await_for_node_synced = if args.wait_for_sync {
+                //wait until the entire syncing process is finished before streaming starts.
+                near_indexer::AwaitForNodeSyncedEnum::WaitForFullSync
+            } else {
+                near_indexer::AwaitForNodeSyncedEnum::StreamWhileSyncing
+            };
 
             //get the indexer config from the home directory
             let indexer_config = near_indexer::IndexerConfig {
-                home_dir,
-                //Starts syncing from the block by which the indexer was interupted the las time it was run
-                sync_mode: near_indexer::SyncModeEnum::FromInterruption,
-                //wait until the entire syncing process is finished before streaming starts.
-                await_for_node_synced: near_indexer::AwaitForNodeSyncedEnum::StreamWhileSyncing,
+                home_dir: home_dir.clone(),
+                sync_mode,
+                await_for_node_synced,
             };
 
             let sys = actix::System::new();
             sys.block_on(async move {
                 let indexer = near_indexer::Indexer::new(indexer_config);
                 //use view client to make view calls to the blockchain
-                let view_client = indexer.client_actors().0; //returns tuple, second is another client actor - we only care about first value
+                //returns tuple, the view client is used for view calls and the client for the node sync status
+                let (view_client, client) = indexer.client_actors();
+
+                //shards the node keeps, which have to include the shards of the contracts we handle
+                let near_config = indexer.near_config();
+                let num_shards = near_config.genesis.config.num_block_producer_seats_per_shard.len() as u64;
+                let tracked_accounts: Vec<String> =
+                    near_config.client_config.tracked_accounts.iter().map(|account_id| account_id.to_string()).collect();
+                let tracked_shards =
+                    node_tracked_shards(&near_config.client_config.tracked_shards, &tracked_accounts, num_shards);
+
                 let stream = indexer.streamer();
+
+                let stats = Arc::new(IndexerStats::default());
+                let thresholds = HealthThresholds {
+                    max_block_age: Duration::from_secs(args.max_block_age_secs),
+                    max_backlog: args.max_backlog,
+                    max_sink_error_rate: args.max_sink_error_rate,
+                };
+                actix::spawn(health::serve(args.health_addr, stats.clone(), client, thresholds));
+
+                let mut viewer: Box<dyn ChainViewer> = Box::new(NodeChainViewer::new(
+                    view_client,
+                    Duration::from_millis(args.view_timeout_ms),
+                    args.view_cache_size,
+                    args.view_fallback,
+                ));
+
+                //when recording, write every block and view call answer to the recording file
+                let recorder = args.record.as_ref().map(|record_path| {
+                    let recorder = Recorder::create(record_path)
+                        .unwrap_or_else(|err| panic!("Could not create recording {:?}: {:?}", record_path, err));
+                    eprintln!("Recording blocks to {:?}", record_path);
+                    Arc::new(recorder)
+                });
+                if let Some(recorder) = &recorder {
+                    viewer = Box::new(RecordingChainViewer { inner: viewer, recorder: recorder.clone() });
+                }
+
+                let mut context = IndexerContext::from_env(viewer, stats, home_dir, recorder, args.finality);
+                let contract_shards = watched_shards(context.watched_contracts(), num_shards);
+                if let Err(err) = check_tracked_shards(&contract_shards, &tracked_shards) {
+                    eprintln!("Can't start the indexer: {}", err);
+                    std::process::exit(1);
+                }
+                eprintln!("Handling the receipts of shards {:?} out of {}", contract_shards, num_shards);
+                context.watched_shards = Some(contract_shards.keys().copied().collect());
+                context.dry_run = args.dry_run.open(&context.home_dir);
+                if context.dry_run.is_some() {
+                    context.keep_state_in_memory();
+                }
+                let context = Arc::new(context);
+                if context.dry_run.is_none() {
+                    actix::spawn(push_collection_stats_every(
+                        context.clone(),
+                        Duration::from_secs(args.stats_interval_secs),
+                    ));
+                    actix::spawn(save_sale_analytics_every(context.clone()));
+                }
                 actix::spawn(listen_blocks(
                     stream,
-                    view_client,
-                    nft_contract,
-                    market_contract,
-                    admin_account,
-                    public_api_root,
-                    private_api_root, 
-                    signature_header,
-                    debug_mode,
+                    context,
+                    Duration::from_secs(args.shutdown_timeout_secs),
                 ));
             });
             sys.run().unwrap();
         }
+        //if we run cargo run -- replay --file blocks.jsonl
+        SubCommand::Replay(args) => {
+            let (blocks, viewer) = read_recording(&args.file).unwrap_or_else(|err| panic!("{}", err));
+            eprintln!("Replaying {} blocks from {:?}", blocks.len(), args.file);
+
+            let mut context = IndexerContext::from_env(
+                Box::new(viewer),
+                Arc::new(IndexerStats::default()),
+                home_dir,
+                None,
+                //recorded blocks are replayed as they were streamed, there is nothing to wait for
+                FinalityMode::Immediate,
+            );
+            context.dry_run = args.dry_run.open(&context.home_dir);
+            context.keep_state_in_memory();
+            let context = Arc::new(context);
+
+            let sys = actix::System::new();
+            sys.block_on(replay_blocks(blocks, context));
+        }
+        //if we run cargo run -- charity-report
+        SubCommand::CharityReport(args) => {
+            let donations: Vec<CharityDonation> = read_ledger(&home_dir)
+                .into_iter()
+                .filter(|donation| args.charity.as_ref().map_or(true, |charity| donation.charity_account_id == *charity))
+                .collect();
+            let totals = aggregate_donations(&donations, args.period);
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&totals).unwrap());
+            } else {
+                print_report(&totals);
+            }
+        }
+        //if we run cargo run -- sale-stats
+        SubCommand::SaleStats(args) => {
+            let collection_stats = SaleAnalytics::load(&home_dir).collection_stats(args.contract.as_deref(), args.period);
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&collection_stats).unwrap());
+            } else {
+                print_collection_stats(&collection_stats);
+            }
+        }
+        //if we run cargo run -- status
+        SubCommand::Status(args) => {
+            let sys = actix::System::new();
+            let report = sys.block_on(status_report(&home_dir, args.rpc_url));
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            } else {
+                print_status(&report);
+            }
+        }
+        //if we run cargo run -- schema
+        SubCommand::Schema(args) => match args.out_dir {
+            Some(out_dir) => match write_schemas(&out_dir) {
+                Ok(written) => eprintln!("Wrote {} schemas to {:?}", written.len(), out_dir),
+                Err(err) => {
+                    eprintln!("Could not write the schemas to {:?} --> {}", out_dir, err);
+                    std::process::exit(1);
+                }
+            },
+            None => {
+                let schemas: serde_json::Map<String, serde_json::Value> =
+                    current_schemas().into_iter().map(|(endpoint, schema)| (endpoint.to_string(), schema)).collect();
+                println!("{}", serde_json::to_string_pretty(&schemas).unwrap());
+            }
+        },
+        //if we run cargo run -- doctor
+        SubCommand::Doctor(args) => {
+            let sys = actix::System::new();
+            let checks = sys.block_on(run_checks(&home_dir, args.rpc_url, args.min_free_disk_gb));
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&checks).unwrap());
+            } else {
+                print_checks(&checks);
+            }
+            if checks.iter().any(|check| check.status == CheckStatus::Failed) {
+                std::process::exit(1);
+            }
+        }
         //if we run cargo run -- init
         //initialize configs in the home directory (~./near)
-        SubCommand::Init(config) => near_indexer::indexer_init_configs(&home_dir, config.into()),
+        SubCommand::Init(mut config) => {
+            if config.localnet_market && config.chain_id.is_none() {
+                config.chain_id = Some("localnet".to_string());
+            }
+            let starter_settings = config.starter_settings();
+            let requested_num_shards = config.num_shards;
+            near_indexer::indexer_init_configs(&home_dir, config.into());
+
+            //a downloaded genesis (testnet, mainnet) has its own number of shards, --num-shards only shapes a new one
+            let num_shards = genesis_num_shards(&home_dir).unwrap_or_else(|err| {
+                eprintln!("Could not read the number of shards from the genesis, using {} --> {}", requested_num_shards, err);
+                requested_num_shards
+            });
+
+            match patch_tracked_shards(&home_dir, num_shards) {
+                Ok(tracked_shards) => eprintln!("Set tracked_shards to {:?} in the near config", tracked_shards),
+                Err(err) => eprintln!("Could not set tracked_shards, set it by hand (see Troubleshooting in the README) --> {}", err),
+            }
+            match write_starter_settings(&home_dir, starter_settings) {
+                Ok(true) => eprintln!("Wrote the indexer settings to {:?}, fill in the empty ones", settings_path(&home_dir)),
+                Ok(false) => eprintln!("Keeping the existing indexer settings in {:?}", settings_path(&home_dir)),
+                Err(err) => eprintln!("Could not write the indexer settings to {:?} --> {}", settings_path(&home_dir), err),
+            }
+        }
     }
 }
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use near_sdk::json_types::U128;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::finality::UndoLog;
+use crate::TokenMetadata;
+
+// ------------------------------- PAYOUTS ----------------------------------
+// when a token is bought the market calls `nft_transfer_payout` on the nft contract,
+//  which transfers the token and returns who gets paid how much (royalties included).
+//  that result is handed to the market's `resolve_purchase` callback, which pays
+//  everyone out (or pays the whole price to the owner if the payout was invalid).
+// the payout is read from the nft_transfer_payout result and reported once
+//  resolve_purchase ran. the two receipts are matched through the data id the
+//  result travels in. the offer or accept_offer that made the sale also produced the
+//  resolve_purchase receipt, so the sale is remembered under that receipt's id: when
+//  no payout shows up the charity share is worked out from the configured split.
+// resolve_purchase runs in a later block than nft_transfer_payout, so this only works
+//  because the stream hands us one block at a time, in height order (see
+//  MAX_IN_FLIGHT_BLOCKS).
+
+//how many blocks a payout waits for its resolve_purchase before it is forgotten
+const MAX_PENDING_BLOCKS: u64 = 1000;
+
+//return type of nft_transfer_payout (NEP-199)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Payout {
+    pub payout: HashMap<String, U128>,
+}
+
+//decode the payout returned by a receipt, in the NEP-199 shape or as a bare map of account --> amount
+pub(crate) fn decode_payout(return_value: &[u8]) -> Option<HashMap<String, u128>> {
+    if let Ok(Payout { payout }) = serde_json::from_slice(return_value) {
+        return Some(payout.into_iter().map(|(account_id, amount)| (account_id, amount.0)).collect());
+    }
+    let payout: HashMap<String, U128> = serde_json::from_slice(return_value).ok()?;
+    Some(payout.into_iter().map(|(account_id, amount)| (account_id, amount.0)).collect())
+}
+
+//who a payout goes to, as far as our reporting is concerned
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutRole {
+    Artist,
+    Charity,
+    //the seller, or anyone else the contract decided to pay
+    Owner,
+}
+
+pub(crate) fn payout_role(account_id: &str, metadata: &TokenMetadata) -> PayoutRole {
+    if metadata.artist_account_id.as_deref() == Some(account_id) {
+        PayoutRole::Artist
+    } else if metadata.charity_account_id == account_id {
+        PayoutRole::Charity
+    } else {
+        PayoutRole::Owner
+    }
+}
+
+//a transfer whose payout is waiting for the market to resolve the purchase
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PendingPayout {
+    pub token_id: String,
+    pub contract_id: String,
+    pub payout: HashMap<String, u128>,
+    pub block_height: u64,
+}
+
+//a sale waiting for the market to resolve the purchase
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PendingSale {
+    pub token_id: String,
+    pub contract_id: String,
+    pub block_height: u64,
+    //set for offers, which are only reported as sold once the token was transferred
+    pub purchase: Option<OfferPurchase>,
+}
+
+//who bought the token with an offer, and for how much
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OfferPurchase {
+    pub purchaser_account_id: String,
+    pub price: u128,
+    pub offer_receipt_id: String,
+}
+
+#[derive(Default)]
+pub(crate) struct PendingPayouts {
+    //data id the nft_transfer_payout result is sent in --> the payout
+    pending: Mutex<HashMap<String, PendingPayout>>,
+    changes: UndoLog<String, PendingPayout>,
+    //receipt produced by the offer or accept_offer --> the sale
+    sales: Mutex<HashMap<String, PendingSale>>,
+    sale_changes: UndoLog<String, PendingSale>,
+}
+
+impl PendingPayouts {
+    pub(crate) fn insert(&self, data_ids: &[String], payout: PendingPayout) {
+        let mut pending = self.pending.lock().unwrap();
+        let oldest_height = payout.block_height.saturating_sub(MAX_PENDING_BLOCKS);
+        let expired: Vec<String> = pending
+            .iter()
+            .filter(|(_, pending_payout)| pending_payout.block_height < oldest_height)
+            .map(|(data_id, _)| data_id.clone())
+            .collect();
+        for data_id in expired {
+            self.changes.remove(&mut pending, &data_id);
+        }
+        for data_id in data_ids {
+            self.changes.insert(&mut pending, data_id.clone(), payout.clone());
+        }
+    }
+
+    //the payout sent in any of the data ids a receipt waited on
+    pub(crate) fn take(&self, input_data_ids: &[String]) -> Option<PendingPayout> {
+        let mut pending = self.pending.lock().unwrap();
+        input_data_ids.iter().find_map(|data_id| self.changes.remove(&mut pending, data_id))
+    }
+
+    //remember a sale until one of the receipts it produced resolves the purchase
+    pub(crate) fn insert_sale(&self, produced_receipt_ids: &[String], sale: PendingSale) {
+        let mut sales = self.sales.lock().unwrap();
+        let oldest_height = sale.block_height.saturating_sub(MAX_PENDING_BLOCKS);
+        let expired: Vec<String> = sales
+            .iter()
+            .filter(|(_, pending_sale)| pending_sale.block_height < oldest_height)
+            .map(|(receipt_id, _)| receipt_id.clone())
+            .collect();
+        for receipt_id in expired {
+            self.sale_changes.remove(&mut sales, &receipt_id);
+        }
+        for receipt_id in produced_receipt_ids {
+            self.sale_changes.insert(&mut sales, receipt_id.clone(), sale.clone());
+        }
+    }
+
+    //the sale whose purchase this receipt resolves, forgetting it along with its other receipts
+    pub(crate) fn take_sale(&self, receipt_id: &str) -> Option<PendingSale> {
+        let mut sales = self.sales.lock().unwrap();
+        let sale = self.sale_changes.remove(&mut sales, &receipt_id.to_string())?;
+        let other_receipts: Vec<String> = sales
+            .iter()
+            .filter(|(_, pending_sale)| **pending_sale == sale)
+            .map(|(receipt_id, _)| receipt_id.clone())
+            .collect();
+        for receipt_id in other_receipts {
+            self.sale_changes.remove(&mut sales, &receipt_id);
+        }
+        Some(sale)
+    }
+
+    //the block left the chain, the sales, transfers and purchases it resolved never happened
+    pub(crate) fn undo_block(&self, block_hash: &str) {
+        self.changes.undo_block(block_hash, &mut self.pending.lock().unwrap());
+        self.sale_changes.undo_block(block_hash, &mut self.sales.lock().unwrap());
+    }
+
+    pub(crate) fn forget_blocks(&self, block_hashes: &[String]) {
+        self.changes.forget_blocks(block_hashes);
+        self.sale_changes.forget_blocks(block_hashes);
+    }
+}
@@ -0,0 +1,61 @@
+use std::any::Any;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::checkpoint::state_dir;
+
+// ------------------------------- POISON RECEIPTS ----------------------------------
+// receipts whose handler panicked or returned an error are appended to a
+//  newline-delimited JSON file so they can be looked at (and replayed) later
+//  without stopping the rest of the block from being indexed.
+
+pub(crate) fn poison_receipts_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("poison_receipts.jsonl")
+}
+
+#[derive(Serialize, Debug)]
+struct PoisonReceipt<'a> {
+    receipt_id: &'a str,
+    block_height: u64,
+    error: &'a str,
+    recorded_at: String,
+}
+
+//log the failed receipt and append it to the poison receipts file
+pub(crate) fn record_poison_receipt(home_dir: &Path, receipt_id: &str, block_height: u64, error: &str) {
+    eprintln!(
+        "Failed to handle receipt {} in block {} --> {}",
+        receipt_id, block_height, error
+    );
+
+    let poison_receipt = PoisonReceipt {
+        receipt_id,
+        block_height,
+        error,
+        recorded_at: chrono::Utc::now().to_rfc3339(),
+    };
+    let appended = std::fs::create_dir_all(state_dir(home_dir)).and_then(|_| {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(poison_receipts_path(home_dir))?;
+        writeln!(file, "{}", serde_json::to_string(&poison_receipt)?)
+    });
+    if let Err(err) = appended {
+        eprintln!("Could not write poison receipt {} --> {:?}", receipt_id, err);
+    }
+}
+
+//turn the payload of a caught panic into something we can log
+pub(crate) fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked with a non-string payload".to_string()
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::BASE_TOKEN_SUFFIX;
+use crate::finality::UndoLog;
+
+// ------------------------------- LAZY PURCHASES ----------------------------------
+// a lazy purchase buys an edition of a series that hasn't been minted yet. the buyer
+//  calls `offer` with `lazy_purchase` on the market, and the market then calls
+//  `nft_mint_payout` on the nft contract to mint the edition to the buyer.
+// the offer tells us what was paid, the mint tells us which edition was bought, so the
+//  offer is remembered under the ids of the receipts it produced until the mint shows up.
+// the mint runs in a later block than the offer, so this only works because the stream
+//  hands us one block at a time, in height order (see MAX_IN_FLIGHT_BLOCKS).
+
+//how many blocks a lazy purchase waits for its nft_mint_payout before it is forgotten
+const MAX_PENDING_BLOCKS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LazyPurchase {
+    pub offer_receipt_id: String,
+    pub contract_id: String,
+    pub base_token_id: String,
+    pub purchaser_account_id: String,
+    //attached to the offer, in yocto
+    pub deposit: u128,
+    pub block_height: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct PendingLazyPurchases {
+    //receipt produced by the offer --> the purchase it belongs to
+    pending: Mutex<HashMap<String, LazyPurchase>>,
+    changes: UndoLog<String, LazyPurchase>,
+}
+
+impl PendingLazyPurchases {
+    //remember the purchase until one of the receipts the offer produced is handled
+    pub(crate) fn insert(&self, produced_receipt_ids: &[String], purchase: LazyPurchase) {
+        let mut pending = self.pending.lock().unwrap();
+        let oldest_height = purchase.block_height.saturating_sub(MAX_PENDING_BLOCKS);
+        let expired: Vec<String> = pending
+            .iter()
+            .filter(|(_, pending_purchase)| pending_purchase.block_height < oldest_height)
+            .map(|(receipt_id, _)| receipt_id.clone())
+            .collect();
+        for receipt_id in expired {
+            self.changes.remove(&mut pending, &receipt_id);
+        }
+        for receipt_id in produced_receipt_ids {
+            self.changes.insert(&mut pending, receipt_id.clone(), purchase.clone());
+        }
+    }
+
+    //the purchase that produced this receipt, forgetting it along with its other receipts
+    pub(crate) fn take(&self, receipt_id: &str) -> Option<LazyPurchase> {
+        let mut pending = self.pending.lock().unwrap();
+        let purchase = self.changes.remove(&mut pending, &receipt_id.to_string())?;
+        let other_receipts: Vec<String> = pending
+            .iter()
+            .filter(|(_, pending_purchase)| pending_purchase.offer_receipt_id == purchase.offer_receipt_id)
+            .map(|(receipt_id, _)| receipt_id.clone())
+            .collect();
+        for receipt_id in other_receipts {
+            self.changes.remove(&mut pending, &receipt_id);
+        }
+        Some(purchase)
+    }
+
+    //the block left the chain, the offers it made and the mints it saw never happened
+    pub(crate) fn undo_block(&self, block_hash: &str) {
+        self.changes.undo_block(block_hash, &mut self.pending.lock().unwrap());
+    }
+
+    pub(crate) fn forget_blocks(&self, block_hashes: &[String]) {
+        self.changes.forget_blocks(block_hashes);
+    }
+}
+
+//the id of an edition, from the id of the base token of its series and the edition number
+//  e.g. ("sunset_0", "3") --> "sunset_3"
+pub(crate) fn edition_token_id(base_token_id: &str, edition_number: &serde_json::Value) -> Result<String, String> {
+    let series = base_token_id
+        .strip_suffix(BASE_TOKEN_SUFFIX)
+        .filter(|series| !series.is_empty())
+        .ok_or_else(|| format!("{} is not a base token (it should end with {})", base_token_id, BASE_TOKEN_SUFFIX))?;
+    //the edition number may be sent as a JSON number or as a string
+    let edition_number = edition_number
+        .as_u64()
+        .or_else(|| edition_number.as_str().and_then(|edition_number| edition_number.parse().ok()))
+        .ok_or_else(|| format!("invalid edition number {} for {}", edition_number, base_token_id))?;
+    if edition_number == 0 {
+        return Err(format!("edition 0 of {} is the base token, it can't be lazy purchased", base_token_id));
+    }
+    Ok(format!("{}_{}", series, edition_number))
+}
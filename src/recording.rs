@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use near_indexer::near_primitives::types::BlockReference;
+use near_indexer::StreamerMessage;
+use serde::{Deserialize, Serialize};
+
+use crate::viewer::{ChainViewer, ViewError};
+
+// ------------------------------- RECORD / REPLAY ----------------------------------
+// `run --record blocks.jsonl` appends every block coming from the node to a
+//  newline-delimited JSON file, along with the answers to the view calls made while
+//  handling it. `replay --file blocks.jsonl` feeds that file through the same
+//  handlers without a node, answering the view calls from the recording.
+
+//a view call and the raw bytes the node answered it with (base64 encoded)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct RecordedViewCall {
+    pub account_id: String,
+    pub method_name: String,
+    pub args: serde_json::Value,
+    pub block_reference: BlockReference,
+    pub result: String,
+}
+
+//one line of a recording
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RecordedLine {
+    Block(StreamerMessage),
+    ViewCall(RecordedViewCall),
+}
+
+//same as RecordedLine but borrows the block, so recording doesn't need to clone it
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RecordedLineRef<'a> {
+    Block(&'a StreamerMessage),
+    ViewCall(&'a RecordedViewCall),
+}
+
+//appends blocks and view calls to the recording file
+pub(crate) struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    fn write_line(&self, line: &RecordedLineRef) {
+        let written = serde_json::to_string(line)
+            .map_err(std::io::Error::from)
+            .and_then(|json| writeln!(self.file.lock().unwrap(), "{}", json));
+        if let Err(err) = written {
+            eprintln!("Failed to write to the recording --> {:?}", err);
+        }
+    }
+
+    pub(crate) fn record_block(&self, streamer_message: &StreamerMessage) {
+        self.write_line(&RecordedLineRef::Block(streamer_message));
+    }
+
+    fn record_view_call(&self, view_call: &RecordedViewCall) {
+        self.write_line(&RecordedLineRef::ViewCall(view_call));
+    }
+}
+
+//key used to look a view call up in a recording
+fn view_call_key(
+    account_id: &str,
+    method_name: &str,
+    args: &serde_json::Value,
+    block_reference: &BlockReference,
+) -> String {
+    format!(
+        "{}|{}|{}|{}",
+        account_id,
+        method_name,
+        args,
+        serde_json::to_string(block_reference).unwrap_or_default()
+    )
+}
+
+//forwards view calls to another viewer and records every answer
+pub(crate) struct RecordingChainViewer {
+    pub inner: Box<dyn ChainViewer>,
+    pub recorder: Arc<Recorder>,
+}
+
+#[async_trait]
+impl ChainViewer for RecordingChainViewer {
+    async fn call_function(
+        &self,
+        account_id: &str,
+        method_name: &str,
+        args: serde_json::Value,
+        block_reference: BlockReference,
+    ) -> Result<Vec<u8>, ViewError> {
+        let result = self
+            .inner
+            .call_function(account_id, method_name, args.clone(), block_reference.clone())
+            .await?;
+        self.recorder.record_view_call(&RecordedViewCall {
+            account_id: account_id.to_string(),
+            method_name: method_name.to_string(),
+            args,
+            block_reference,
+            result: base64::encode(&result),
+        });
+        Ok(result)
+    }
+}
+
+//answers view calls from the ones found in a recording
+#[derive(Default)]
+pub(crate) struct ReplayChainViewer {
+    view_calls: HashMap<String, Vec<u8>>,
+}
+
+#[async_trait]
+impl ChainViewer for ReplayChainViewer {
+    async fn call_function(
+        &self,
+        account_id: &str,
+        method_name: &str,
+        args: serde_json::Value,
+        block_reference: BlockReference,
+    ) -> Result<Vec<u8>, ViewError> {
+        let key = view_call_key(account_id, method_name, &args, &block_reference);
+        self.view_calls.get(&key).cloned().ok_or_else(|| ViewError::Query {
+            method_name: method_name.to_string(),
+            error: format!("no recorded answer for {} on {} with {}", method_name, account_id, args),
+        })
+    }
+}
+
+//read a recording back into its blocks (in order) and a viewer answering its view calls
+pub(crate) fn read_recording(
+    path: &Path,
+) -> Result<(Vec<StreamerMessage>, ReplayChainViewer), String> {
+    let file = File::open(path).map_err(|err| format!("Could not open {:?} --> {:?}", path, err))?;
+
+    let mut blocks = vec![];
+    let mut viewer = ReplayChainViewer::default();
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("Could not read {:?} --> {:?}", path, err))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let recorded_line: RecordedLine = serde_json::from_str(&line)
+            .map_err(|err| format!("Invalid line {} in {:?} --> {:?}", line_number + 1, path, err))?;
+        match recorded_line {
+            RecordedLine::Block(streamer_message) => blocks.push(streamer_message),
+            RecordedLine::ViewCall(view_call) => {
+                let result = base64::decode(&view_call.result).map_err(|err| {
+                    format!("Invalid view call result on line {} --> {:?}", line_number + 1, err)
+                })?;
+                let key = view_call_key(
+                    &view_call.account_id,
+                    &view_call.method_name,
+                    &view_call.args,
+                    &view_call.block_reference,
+                );
+                viewer.view_calls.insert(key, result);
+            }
+        }
+    }
+    Ok((blocks, viewer))
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::database::{endpoint_schemas, SCHEMA_VERSION};
+
+// ------------------------------- EVENT SCHEMAS ----------------------------------
+// `schema` dumps the JSON Schema of the body POSTed to every endpoint. the schemas of
+//  each released version are kept in schemas/v<N>/ at the root of the repo, and the
+//  tests compare them with the current bodies so a change that would break an API
+//  consumer can't go out under the same schema_version.
+
+//schemas/v1/tokens.forsale.insert.json for "tokens/forsale/insert"
+pub(crate) fn schema_file_name(endpoint: &str) -> String {
+    format!("{}.json", endpoint.replace('/', "."))
+}
+
+//directory holding the released schemas of a version
+pub(crate) fn released_schemas_dir(version: u32) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas").join(format!("v{}", version))
+}
+
+//endpoint --> schema of its body
+pub(crate) fn current_schemas() -> Vec<(&'static str, Value)> {
+    endpoint_schemas()
+        .into_iter()
+        .map(|(endpoint, schema)| (endpoint, serde_json::to_value(schema).unwrap()))
+        .collect()
+}
+
+//write one file per endpoint into the directory, returns the files written
+pub(crate) fn write_schemas(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = vec![];
+    for (endpoint, schema) in current_schemas() {
+        let path = dir.join(schema_file_name(endpoint));
+        std::fs::write(&path, serde_json::to_vec_pretty(&schema)?)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+//the ways `new` breaks consumers written against `old`: fields that were removed, are
+//  no longer always sent or changed type, and enum values that went away
+pub(crate) fn breaking_changes(old: &Value, new: &Value) -> Vec<String> {
+    let mut changes = vec![];
+    let new_required = string_set(&new["required"]);
+    for required in string_set(&old["required"]) {
+        if new["properties"].get(&required).is_some() && !new_required.contains(&required) {
+            changes.push(format!("field {:?} is no longer always sent", required));
+        }
+    }
+
+    for (field, old_field) in sorted_entries(&old["properties"]) {
+        let new_field = match new["properties"].get(field.as_str()) {
+            Some(new_field) => new_field,
+            None => {
+                changes.push(format!("field {:?} was removed", field));
+                continue;
+            }
+        };
+        let (old_types, new_types) = (string_set(&old_field["type"]), string_set(&new_field["type"]));
+        if !old_types.is_empty() && old_types != new_types {
+            changes.push(format!("field {:?} changed type from {:?} to {:?}", field, old_types, new_types));
+        }
+        if old_field.get("$ref") != new_field.get("$ref") {
+            changes.push(format!("field {:?} changed type from {} to {}", field, old_field["$ref"], new_field["$ref"]));
+        }
+    }
+
+    for (name, old_definition) in sorted_entries(&old["definitions"]) {
+        let new_values = string_set(&new["definitions"][name.as_str()]["enum"]);
+        for value in string_set(&old_definition["enum"]) {
+            if !new_values.contains(&value) {
+                changes.push(format!("{} no longer has the value {:?}", name, value));
+            }
+        }
+    }
+    changes
+}
+
+//the entries of an object by name, whatever order the map keeps them in
+fn sorted_entries(value: &Value) -> BTreeMap<&String, &Value> {
+    value.as_object().map(|object| object.iter().collect()).unwrap_or_default()
+}
+
+//a string or an array of strings, as a sorted list
+fn string_set(value: &Value) -> Vec<String> {
+    let mut strings: Vec<String> = match value {
+        Value::String(string) => vec![string.clone()],
+        Value::Array(values) => values.iter().filter_map(|value| value.as_str().map(String::from)).collect(),
+        _ => vec![],
+    };
+    strings.sort();
+    strings
+}
+
+//compare the current bodies with the released schemas of SCHEMA_VERSION
+pub(crate) fn check_released_schemas() -> Vec<String> {
+    let dir = released_schemas_dir(SCHEMA_VERSION);
+    let mut problems = vec![];
+    for (endpoint, schema) in current_schemas() {
+        let path = dir.join(schema_file_name(endpoint));
+        let released: Value = match std::fs::read_to_string(&path).map(|contents| serde_json::from_str(&contents)) {
+            Ok(Ok(released)) => released,
+            _ => {
+                problems.push(format!("{}: no released schema in {:?}", endpoint, path));
+                continue;
+            }
+        };
+        problems.extend(breaking_changes(&released, &schema).into_iter().map(|change| format!("{}: {}", endpoint, change)));
+    }
+    problems
+}
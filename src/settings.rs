@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::checkpoint::state_dir;
+
+// ------------------------------- INDEXER SETTINGS ----------------------------------
+// the indexer is configured through env variables (NFT, MARKET, PRIVATE_API, ...).
+//  each of them can also be set in fayyr-indexer/indexer.json inside the near home dir,
+//  which `init` writes a starter version of. an env variable always wins over the file.
+
+//the settings the indexer can't run without
+pub(crate) const REQUIRED_SETTINGS: [&str; 7] = ["NFT", "MARKET", "ADMIN", "PRIVATE_API", "PUBLIC_API", "HEADER", "DEBUG"];
+
+//every env variable the indexer reads
+const SETTING_NAMES: [&str; 10] = [
+    "NFT",
+    "MARKET",
+    "ADMIN",
+    "PRIVATE_API",
+    "PUBLIC_API",
+    "HEADER",
+    "DEBUG",
+    "CHARITY_SPLIT_PERCENT",
+    "WATCH_CONFIG",
+    "WATCH_ACCOUNTS",
+];
+
+pub(crate) fn settings_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("indexer.json")
+}
+
+//the settings from the env, falling back on indexer.json
+pub(crate) struct Settings {
+    file: BTreeMap<String, String>,
+}
+
+impl Settings {
+    pub(crate) fn load(home_dir: &Path) -> Self {
+        Self::try_load(home_dir).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub(crate) fn try_load(home_dir: &Path) -> Result<Self, String> {
+        let path = settings_path(home_dir);
+        let file = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|err| format!("Invalid indexer settings {:?}: {}", path, err))?,
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Self { file })
+    }
+
+    //empty values count as not set, the starter file leaves the ones it can't guess empty
+    pub(crate) fn get(&self, name: &str) -> Option<String> {
+        env::var(name)
+            .ok()
+            .or_else(|| self.file.get(name).cloned())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+//the settings matching the local testing guide in the README
+pub(crate) fn localnet_market_settings() -> BTreeMap<String, String> {
+    let mut settings = BTreeMap::new();
+    settings.insert("NFT".to_string(), "test.near".to_string());
+    settings.insert("MARKET".to_string(), "market.test.near".to_string());
+    settings.insert("ADMIN".to_string(), "test.near".to_string());
+    settings.insert("PRIVATE_API".to_string(), "http://localhost:3000/api".to_string());
+    settings.insert("PUBLIC_API".to_string(), "http://localhost:3000/api".to_string());
+    settings.insert("DEBUG".to_string(), "TRUE".to_string());
+    settings
+}
+
+//write a starter indexer.json, every setting not given is left empty for the user to fill in.
+//  an existing file is kept as it is
+pub(crate) fn write_starter_settings(home_dir: &Path, given: BTreeMap<String, String>) -> std::io::Result<bool> {
+    let path = settings_path(home_dir);
+    if path.exists() {
+        return Ok(false);
+    }
+    let mut settings: BTreeMap<String, String> = SETTING_NAMES.iter().map(|name| (name.to_string(), String::new())).collect();
+    settings.insert("DEBUG".to_string(), "FALSE".to_string());
+    settings.extend(given);
+
+    std::fs::create_dir_all(state_dir(home_dir))?;
+    std::fs::write(&path, serde_json::to_vec_pretty(&settings)?)?;
+    Ok(true)
+}
+
+//make the node keep the state of every shard, otherwise it streams blocks without our receipts.
+//  returns the shards it now tracks
+pub(crate) fn patch_tracked_shards(home_dir: &Path, num_shards: u64) -> Result<Vec<u64>, String> {
+    let path = home_dir.join("config.json");
+    let contents = std::fs::read_to_string(&path).map_err(|err| format!("could not read {:?}: {}", path, err))?;
+    let mut config: serde_json::Value =
+        serde_json::from_str(&contents).map_err(|err| format!("invalid near config {:?}: {}", path, err))?;
+
+    let tracked_shards: Vec<u64> = (0..num_shards.max(1)).collect();
+    config
+        .as_object_mut()
+        .ok_or_else(|| format!("near config {:?} is not a JSON object", path))?
+        .insert("tracked_shards".to_string(), serde_json::json!(tracked_shards));
+
+    let patched = serde_json::to_vec_pretty(&config).map_err(|err| err.to_string())?;
+    std::fs::write(&path, patched).map_err(|err| format!("could not write {:?}: {}", path, err))?;
+    Ok(tracked_shards)
+}
@@ -0,0 +1,80 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use near_indexer::near_primitives::hash::hash;
+use serde::Deserialize;
+
+// ------------------------------- SHARDS ----------------------------------
+// a receipt is executed on the shard of the account receiving it, so the receipts
+//  for our contracts only ever show up on the shards hosting them. at startup we work
+//  out which shards those are and refuse to run if the node doesn't track them (it would
+//  stream empty shards and we would silently miss everything). the other shards are
+//  only looked at for the activity of the watched accounts.
+
+//the part of genesis.json we need, the rest (which can be huge) is skipped while reading
+#[derive(Deserialize)]
+struct GenesisShards {
+    num_block_producer_seats_per_shard: Vec<u64>,
+}
+
+//how many shards the chain of the home dir has, from its genesis
+pub(crate) fn genesis_num_shards(home_dir: &Path) -> Result<u64, String> {
+    let path = home_dir.join("genesis.json");
+    let file = File::open(&path).map_err(|err| format!("could not open {:?}: {}", path, err))?;
+    let genesis: GenesisShards =
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| format!("invalid genesis {:?}: {}", path, err))?;
+    Ok(genesis.num_block_producer_seats_per_shard.len() as u64)
+}
+
+//shard an account lives on, the same way nearcore assigns them: the first 8 bytes of the
+//  sha256 of the account id, as a little endian number, modulo the number of shards
+pub(crate) fn account_shard(account_id: &str, num_shards: u64) -> u64 {
+    let account_hash = hash(account_id.as_bytes());
+    let first_bytes: [u8; 8] = account_hash.as_ref()[..8].try_into().unwrap();
+    u64::from_le_bytes(first_bytes) % num_shards.max(1)
+}
+
+//shard --> the accounts we care about on it
+pub(crate) fn watched_shards<'a, I: IntoIterator<Item = &'a str>>(accounts: I, num_shards: u64) -> BTreeMap<u64, Vec<String>> {
+    let mut shards: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for account_id in accounts {
+        let accounts_on_shard = shards.entry(account_shard(account_id, num_shards)).or_default();
+        if !accounts_on_shard.iter().any(|known| known == account_id) {
+            accounts_on_shard.push(account_id.to_string());
+        }
+    }
+    shards
+}
+
+//the shards the node keeps the state of: the ones in tracked_shards plus those of the tracked_accounts
+pub(crate) fn node_tracked_shards(tracked_shards: &[u64], tracked_accounts: &[String], num_shards: u64) -> BTreeSet<u64> {
+    tracked_shards
+        .iter()
+        .copied()
+        .chain(tracked_accounts.iter().map(|account_id| account_shard(account_id, num_shards)))
+        .collect()
+}
+
+//fail with the shards the node is missing, and what should be added to its config
+pub(crate) fn check_tracked_shards(
+    watched_shards: &BTreeMap<u64, Vec<String>>,
+    tracked_shards: &BTreeSet<u64>,
+) -> Result<(), String> {
+    let missing: Vec<String> = watched_shards
+        .iter()
+        .filter(|(shard_id, _)| !tracked_shards.contains(shard_id))
+        .map(|(shard_id, accounts)| format!("shard {} (hosting {})", shard_id, accounts.join(", ")))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let needed: BTreeSet<&u64> = watched_shards.keys().chain(tracked_shards.iter()).collect();
+    let needed: Vec<String> = needed.iter().map(|shard_id| shard_id.to_string()).collect();
+    Err(format!(
+        "the node doesn't track {}. set \"tracked_shards\": [{}] in the config.json of the near home dir",
+        missing.join(" and "),
+        needed.join(", ")
+    ))
+}
@@ -0,0 +1,276 @@
+use crate::charity::{record_donation, record_reverted_donation};
+use crate::database;
+use crate::events::IndexerEvent;
+use crate::finality::hold_event;
+use crate::IndexerContext;
+
+// ------------------------------- SINK ----------------------------------
+// delivers the events produced by the handlers to the API. every delivery is
+//  counted in the stats so /readyz can report the API error rate.
+//  with --dry-run they are printed instead (see dry_run.rs).
+
+//hand an event over for delivery. while a block is being handled its events are held
+//  back, the outbox (finality.rs) decides when they go out
+pub(crate) async fn deliver_event(event: IndexerEvent, context: &IndexerContext) {
+    if let Some(event) = hold_event(event) {
+        send_event(event, context).await;
+    }
+}
+
+//send events the outbox released, in order
+pub(crate) async fn send_events(events: Vec<IndexerEvent>, context: &IndexerContext) {
+    for event in events {
+        send_event(event, context).await;
+    }
+}
+
+async fn send_event(event: IndexerEvent, context: &IndexerContext) {
+    let IndexerContext {
+        admin_account,
+        private_api_root,
+        signature_header,
+        debug_mode,
+        stats,
+        home_dir,
+        dry_run,
+        ..
+    } = context;
+
+    let event_name = event.name();
+    let event_json = serde_json::to_value(&event).unwrap_or(serde_json::Value::Null);
+    //a dry run has no side effects past printing, not even the charity ledger
+    if let Some(dry_run) = dry_run {
+        dry_run.record(event_name, &event_json);
+        stats.event_sent(event_name, event_json, true);
+        return;
+    }
+    //the sale stats follow what was delivered, whether or not the API took it
+    context.analytics.record(&event);
+    let result = match event {
+        IndexerEvent::TokenMinted(minted_token) => {
+            database::insert_minted_token_in_database(
+                minted_token,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::TokenListed { token_id, contract_id, price_near, .. } => {
+            database::insert_token_forsale_in_database(
+                token_id,
+                contract_id,
+                price_near,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::PriceUpdated { token_id, contract_id, price_near, .. } => {
+            database::update_price_for_token_in_database(
+                token_id,
+                contract_id,
+                price_near,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::ListingRemoved { token_id, contract_id } => {
+            database::remove_token_forsale_in_database(
+                token_id,
+                contract_id,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::TokenSold { token_id, contract_id, price_near, purchaser_account_id, receipt_id, lazy, .. } => {
+            database::sell_token_in_database(
+                token_id,
+                contract_id,
+                price_near,
+                purchaser_account_id,
+                admin_account.clone(),
+                receipt_id,
+                lazy,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::BidPlaced { token_id, contract_id, bidder_account_id, ft_token_id, amount, receipt_id } => {
+            database::place_bid_in_database(
+                token_id,
+                contract_id,
+                bidder_account_id,
+                ft_token_id,
+                amount,
+                receipt_id,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::BidOutbid { token_id, contract_id, bidder_account_id, ft_token_id, amount, outbid_by_account_id } => {
+            database::outbid_in_database(
+                token_id,
+                contract_id,
+                bidder_account_id,
+                ft_token_id,
+                amount,
+                outbid_by_account_id,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        //an accepted offer is a sale, the API records it the same way
+        IndexerEvent::OfferAccepted { token_id, contract_id, buyer_account_id, price_near, receipt_id, .. } => {
+            database::sell_token_in_database(
+                token_id,
+                contract_id,
+                price_near,
+                buyer_account_id,
+                admin_account.clone(),
+                receipt_id,
+                false,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::PayoutRecorded { token_id, contract_id, recipient_account_id, role, amount, amount_near, receipt_id } => {
+            database::record_payout_in_database(
+                token_id,
+                contract_id,
+                recipient_account_id,
+                role,
+                amount,
+                amount_near,
+                receipt_id,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        //donations are also kept in the local ledger, whether or not the API took them
+        IndexerEvent::CharityDonated(donation) => {
+            record_donation(home_dir, &donation);
+            database::record_charity_donation_in_database(
+                donation,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::ContractCall { contract_id, method_name, signer_id, predecessor_id, deposit, receipt_id, block_height, fields } => {
+            database::record_contract_call_in_database(
+                contract_id,
+                method_name,
+                signer_id,
+                predecessor_id,
+                deposit,
+                receipt_id,
+                block_height,
+                fields,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::AccountActivity {
+            account_id,
+            role,
+            receipt_id,
+            block_height,
+            block_timestamp,
+            signer_id,
+            predecessor_id,
+            receiver_id,
+            action,
+            success,
+        } => {
+            database::record_account_activity_in_database(
+                account_id,
+                role,
+                receipt_id,
+                block_height,
+                block_timestamp,
+                signer_id,
+                predecessor_id,
+                receiver_id,
+                action,
+                success,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::StorageBalanceChanged {
+            account_id,
+            change,
+            amount,
+            balance,
+            balance_near,
+            listings,
+            listings_affordable,
+            listing_would_fail,
+            receipt_id,
+            block_height,
+            block_timestamp,
+        } => {
+            if listing_would_fail {
+                eprintln!("{} has no storage left on the market, their next listing would fail", account_id);
+            }
+            database::record_storage_balance_in_database(
+                account_id,
+                change,
+                amount,
+                balance,
+                balance_near,
+                listings,
+                listings_affordable,
+                listing_would_fail,
+                receipt_id,
+                block_height,
+                block_timestamp,
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+        IndexerEvent::Reverted { block_height, block_hash, event } => {
+            if let IndexerEvent::CharityDonated(donation) = event.as_ref() {
+                record_reverted_donation(home_dir, donation);
+            }
+            database::revert_event_in_database(
+                block_height,
+                block_hash,
+                serde_json::to_value(&event).unwrap_or(serde_json::Value::Null),
+                signature_header.clone(),
+                private_api_root,
+                debug_mode.clone(),
+            )
+            .await
+        }
+    };
+
+    stats.record_sink_result(&result);
+    stats.event_sent(event_name, event_json, result.is_ok());
+    if let Err(err) = result {
+        eprintln!("Failed to deliver {} event to the API --> {:?}", event_name, err);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::{load_checkpoint, state_dir, Checkpoint};
+use crate::doctor::{configured_contracts, node_rpc_url};
+use crate::health::LastEvent;
+use crate::settings::Settings;
+use crate::IndexerContext;
+
+// ------------------------------- STATUS ----------------------------------
+// while it runs, the indexer keeps fayyr-indexer/status.json up to date after every
+//  block. `status` reads it back along with the checkpoint, so the progress of a running
+//  or stopped indexer can be looked up from the home dir alone.
+// the head of the chain is asked over RPC while a node (the indexer itself, or the one
+//  behind --rpc-url) is running. when none answers, it is read from the database the node
+//  left in the data dir, opened read only.
+
+pub(crate) fn status_path(home_dir: &Path) -> PathBuf {
+    state_dir(home_dir).join("status.json")
+}
+
+//what the indexer last wrote about itself
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct IndexerStatus {
+    pub updated_at: String,
+    pub running: bool,
+    pub last_received_height: u64,
+    pub last_processed_height: u64,
+    //blocks whose events the outbox still holds back
+    pub outbox_backlog: Vec<u64>,
+    //event name --> the last one sent
+    pub last_events: BTreeMap<String, LastEvent>,
+}
+
+pub(crate) fn indexer_status(context: &IndexerContext, running: bool) -> IndexerStatus {
+    IndexerStatus {
+        updated_at: chrono::Utc::now().to_rfc3339(),
+        running,
+        last_received_height: context.stats.last_received_height(),
+        last_processed_height: context.stats.last_processed_height(),
+        outbox_backlog: context.outbox.undelivered_heights(),
+        last_events: context.stats.last_events(),
+    }
+}
+
+pub(crate) fn save_status(home_dir: &Path, status: &IndexerStatus) -> std::io::Result<()> {
+    std::fs::create_dir_all(state_dir(home_dir))?;
+    let path = status_path(home_dir);
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(status)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+pub(crate) fn load_status(home_dir: &Path) -> Option<IndexerStatus> {
+    let contents = std::fs::read_to_string(status_path(home_dir)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+//the head of the node, asked through its RPC
+async fn node_head_height(rpc_url: &str) -> Option<u64> {
+    let request = serde_json::json!({ "jsonrpc": "2.0", "id": "status", "method": "status", "params": [] });
+    let response: serde_json::Value =
+        reqwest::Client::new().post(rpc_url).json(&request).send().await.ok()?.json().await.ok()?;
+    response["result"]["sync_info"]["latest_block_height"].as_u64()
+}
+
+//the node keeps its head under HEAD in the block misc column (col1), as a borsh Tip
+//  that starts with the height
+const BLOCK_MISC_COLUMN: &str = "col1";
+const HEAD_KEY: &[u8] = b"HEAD";
+
+//the head a stopped node left in its database
+fn stored_head_height(home_dir: &Path) -> Option<u64> {
+    let data_dir = home_dir.join("data");
+    if !data_dir.exists() {
+        return None;
+    }
+    let db = match rocksdb::DB::open_cf_for_read_only(&rocksdb::Options::default(), &data_dir, vec![BLOCK_MISC_COLUMN], false) {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("Could not open the node database in {} --> {}", data_dir.display(), err);
+            return None;
+        }
+    };
+    let tip = db.get_cf(db.cf_handle(BLOCK_MISC_COLUMN)?, HEAD_KEY).ok()??;
+    let height: [u8; 8] = tip.get(..8)?.try_into().ok()?;
+    Some(u64::from_le_bytes(height))
+}
+
+//where the head of the node was read from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NodeHeadSource {
+    Rpc,
+    //the node isn't running, this is where it stopped
+    Database,
+}
+
+//what `status` prints
+#[derive(Serialize, Debug)]
+pub(crate) struct StatusReport {
+    pub checkpoint: Option<Checkpoint>,
+    //None when no node answers and the data dir has no database
+    pub node_head_height: Option<u64>,
+    pub node_head_source: Option<NodeHeadSource>,
+    pub status: Option<IndexerStatus>,
+    pub watched_contracts: Vec<String>,
+}
+
+pub(crate) async fn status_report(home_dir: &Path, rpc_url: Option<String>) -> StatusReport {
+    let rpc_head_height = match rpc_url.or_else(|| node_rpc_url(home_dir)) {
+        Some(rpc_url) => node_head_height(&rpc_url).await,
+        None => None,
+    };
+    let (node_head_height, node_head_source) = match rpc_head_height {
+        Some(height) => (Some(height), Some(NodeHeadSource::Rpc)),
+        None => match stored_head_height(home_dir) {
+            Some(height) => (Some(height), Some(NodeHeadSource::Database)),
+            None => (None, None),
+        },
+    };
+    let watched_contracts = match Settings::try_load(home_dir) {
+        Ok(settings) => configured_contracts(&settings),
+        Err(err) => {
+            eprintln!("{}", err);
+            vec![]
+        }
+    };
+    StatusReport {
+        checkpoint: load_checkpoint(home_dir),
+        node_head_height,
+        node_head_source,
+        status: load_status(home_dir),
+        watched_contracts,
+    }
+}
+
+pub(crate) fn print_status(report: &StatusReport) {
+    match &report.status {
+        Some(status) => {
+            let state = if status.running { "running" } else { "stopped" };
+            println!("Indexer:             {} (as of {})", state, status.updated_at);
+            println!("Last received block: {}", status.last_received_height);
+            println!("Last handled block:  {}", status.last_processed_height);
+            println!("Outbox backlog:      {} blocks {:?}", status.outbox_backlog.len(), status.outbox_backlog);
+        }
+        None => println!("Indexer:             never ran with this home dir"),
+    }
+    match &report.checkpoint {
+        Some(checkpoint) => println!(
+            "Checkpoint:          block {}, unfinished {:?}",
+            checkpoint.last_processed_height, checkpoint.unfinished_heights
+        ),
+        None => println!("Checkpoint:          none"),
+    }
+    match report.node_head_height {
+        Some(node_head_height) => {
+            print!("Node head:           {}", node_head_height);
+            if let Some(status) = &report.status {
+                print!(" ({} blocks ahead)", node_head_height.saturating_sub(status.last_processed_height));
+            }
+            match report.node_head_source {
+                Some(NodeHeadSource::Database) => println!(", where the stopped node left off"),
+                _ => println!(),
+            }
+        }
+        None => println!("Node head:           unknown, no node is answering RPC calls and the data dir has no database"),
+    }
+    println!("Watched contracts:   {}", report.watched_contracts.join(", "));
+
+    if let Some(status) = &report.status {
+        println!();
+        println!("Last events:");
+        if status.last_events.is_empty() {
+            println!("  none yet");
+        }
+        for (event_name, last_event) in &status.last_events {
+            let delivered = if last_event.delivered { "" } else { " (the API refused it)" };
+            println!("  {:<22} {}{} {}", event_name, last_event.sent_at, delivered, last_event.event);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use near_indexer::near_primitives::types::{BlockId, BlockReference};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::events::IndexerEvent;
+use crate::sink::deliver_event;
+use crate::{human, IndexerContext};
+
+// ------------------------------- MARKET STORAGE ----------------------------------
+// every listing on the market has to be paid for with storage the seller deposited
+//  through storage_deposit (and can take back with storage_withdraw). after each
+//  deposit, withdrawal and new listing the account's storage balance is read back from
+//  the market, so the frontend can warn before a listing would fail for lack of storage.
+
+//what changed the storage balance
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageChange {
+    Deposit,
+    Withdraw,
+    //a new listing used up some of the balance
+    Listing,
+}
+
+//how many more listings a storage balance pays for
+pub(crate) fn listings_affordable(balance: u128, storage_per_listing: u128, listings: u64) -> u64 {
+    if storage_per_listing == 0 {
+        return u64::MAX;
+    }
+    let paid_for = (balance / storage_per_listing).min(u64::MAX as u128) as u64;
+    paid_for.saturating_sub(listings)
+}
+
+//read the storage balance of an account on the market as it was after this block, and report it
+pub(crate) async fn deliver_storage_balance(
+    account_id: &str,
+    change: StorageChange,
+    //what was deposited, when we know it
+    amount: Option<u128>,
+    receipt_id: &str,
+    block_height: u64,
+    block_timestamp: u64,
+    context: &IndexerContext,
+) -> Result<(), String> {
+    let IndexerContext { viewer, market_contract, .. } = context;
+    let block_reference = BlockReference::BlockId(BlockId::Height(block_height));
+
+    let balance = viewer
+        .storage_balance_of(market_contract, account_id, block_reference.clone())
+        .await?
+        .0;
+    let storage_per_listing = viewer
+        .storage_minimum_balance(market_contract, block_reference.clone())
+        .await?
+        .0;
+    let listings = viewer
+        .get_supply_by_owner_id(market_contract, account_id, block_reference)
+        .await?
+        .0;
+    let listings_affordable = listings_affordable(balance, storage_per_listing, listings);
+
+    let storage_balance_changed = IndexerEvent::StorageBalanceChanged {
+        account_id: account_id.to_string(),
+        change,
+        amount: amount.map(|amount| amount.to_string()),
+        balance: balance.to_string(),
+        balance_near: human(balance),
+        listings,
+        listings_affordable,
+        //the next listing would be refused by the market
+        listing_would_fail: listings_affordable == 0,
+        receipt_id: receipt_id.to_string(),
+        block_height,
+        block_timestamp,
+    };
+    deliver_event(storage_balance_changed, context).await;
+    Ok(())
+}
@@ -0,0 +1,365 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use near_indexer::near_primitives::hash::hash;
+use near_indexer::near_primitives::views::{ExecutionOutcomeWithIdView, ReceiptView};
+use near_indexer::IndexerExecutionOutcomeWithReceipt;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::abi::WatchConfig;
+use crate::activity::WatchedAccounts;
+use crate::analytics::SaleAnalytics;
+use crate::args::ArgsDecoders;
+use crate::bids::BidBook;
+use crate::database::SCHEMA_VERSION;
+use crate::finality::{FinalityMode, Outbox};
+use crate::health::IndexerStats;
+use crate::payouts::PendingPayouts;
+use crate::purchases::PendingLazyPurchases;
+use crate::viewer::MockChainViewer;
+use crate::IndexerContext;
+
+// ------------------------------- TEST SUPPORT ----------------------------------
+// builders for synthetic receipts and execution outcomes, plus a fake API server
+//  that records the requests made by the database module, so every handler can be
+//  tested end-to-end without a node.
+// the views are built from the same JSON the node RPC returns, which keeps the
+//  builders independent of fields the tests don't care about.
+
+pub(crate) const NFT_CONTRACT: &str = "nft.test.near";
+pub(crate) const MARKET_CONTRACT: &str = "market.test.near";
+pub(crate) const ADMIN_ACCOUNT: &str = "admin.test.near";
+pub(crate) const SIGNATURE_HEADER: &str = "test-signature";
+
+pub(crate) const PUBLIC_KEY: &str = "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp";
+
+//a fake hash derived from a name, so tests can refer to receipts by name
+pub(crate) fn test_hash(name: &str) -> String {
+    hash(name.as_bytes()).to_string()
+}
+
+//builds a ReceiptView carrying function call actions
+pub(crate) struct ReceiptBuilder {
+    receipt_id: String,
+    predecessor_id: String,
+    receiver_id: String,
+    signer_id: String,
+    actions: Vec<serde_json::Value>,
+    output_data_receivers: Vec<serde_json::Value>,
+    input_data_ids: Vec<String>,
+}
+
+impl ReceiptBuilder {
+    pub(crate) fn new(receipt_id: &str, predecessor_id: &str, receiver_id: &str) -> Self {
+        Self {
+            receipt_id: test_hash(receipt_id),
+            predecessor_id: predecessor_id.to_string(),
+            receiver_id: receiver_id.to_string(),
+            signer_id: predecessor_id.to_string(),
+            actions: vec![],
+            output_data_receivers: vec![],
+            input_data_ids: vec![],
+        }
+    }
+
+    pub(crate) fn signer(mut self, signer_id: &str) -> Self {
+        self.signer_id = signer_id.to_string();
+        self
+    }
+
+    //the result of the receipt is sent to a callback on receiver_id, in a data id built from data_name
+    pub(crate) fn sends_result_to(mut self, data_name: &str, receiver_id: &str) -> Self {
+        self.output_data_receivers
+            .push(serde_json::json!({ "data_id": test_hash(data_name), "receiver_id": receiver_id }));
+        self
+    }
+
+    //the receipt is a callback waiting for the result sent in data_name
+    pub(crate) fn waits_for(mut self, data_name: &str) -> Self {
+        self.input_data_ids.push(test_hash(data_name));
+        self
+    }
+
+    pub(crate) fn function_call(self, method_name: &str, args: serde_json::Value, deposit: u128) -> Self {
+        self.function_call_bytes(method_name, args.to_string().as_bytes(), deposit)
+    }
+
+    //a function call whose args are not JSON
+    pub(crate) fn function_call_bytes(mut self, method_name: &str, args: &[u8], deposit: u128) -> Self {
+        self.actions.push(serde_json::json!({
+            "FunctionCall": {
+                "method_name": method_name,
+                "args": base64::encode(args),
+                "gas": 100_000_000_000_000u64,
+                "deposit": deposit.to_string(),
+            }
+        }));
+        self
+    }
+
+    pub(crate) fn transfer(mut self, deposit: u128) -> Self {
+        self.actions.push(serde_json::json!({ "Transfer": { "deposit": deposit.to_string() } }));
+        self
+    }
+
+    pub(crate) fn add_full_access_key(mut self) -> Self {
+        self.actions.push(serde_json::json!({
+            "AddKey": { "public_key": PUBLIC_KEY, "access_key": { "nonce": 0, "permission": "FullAccess" } }
+        }));
+        self
+    }
+
+    pub(crate) fn build(self) -> ReceiptView {
+        serde_json::from_value(serde_json::json!({
+            "predecessor_id": self.predecessor_id,
+            "receiver_id": self.receiver_id,
+            "receipt_id": self.receipt_id,
+            "receipt": {
+                "Action": {
+                    "signer_id": self.signer_id,
+                    "signer_public_key": PUBLIC_KEY,
+                    "gas_price": "100000000",
+                    "output_data_receivers": self.output_data_receivers,
+                    "input_data_ids": self.input_data_ids,
+                    "actions": self.actions,
+                }
+            }
+        }))
+        .expect("invalid receipt built in test")
+    }
+}
+
+//builds the ExecutionOutcomeWithIdView of a receipt
+pub(crate) struct OutcomeBuilder {
+    id: String,
+    executor_id: String,
+    status: serde_json::Value,
+    receipt_ids: Vec<String>,
+}
+
+impl OutcomeBuilder {
+    pub(crate) fn new(receipt: &ReceiptView) -> Self {
+        Self {
+            id: receipt.receipt_id.to_string(),
+            executor_id: receipt.receiver_id.to_string(),
+            status: serde_json::json!({ "SuccessValue": "" }),
+            receipt_ids: vec![],
+        }
+    }
+
+    //the receipt returned a JSON value
+    pub(crate) fn returns(mut self, value: serde_json::Value) -> Self {
+        self.status = serde_json::json!({ "SuccessValue": base64::encode(value.to_string()) });
+        self
+    }
+
+    //the receipt created another receipt (built with the same name)
+    pub(crate) fn produces(mut self, receipt_id: &str) -> Self {
+        self.receipt_ids.push(test_hash(receipt_id));
+        self
+    }
+
+    pub(crate) fn failure(mut self) -> Self {
+        self.status = serde_json::json!({
+            "Failure": { "ActionError": { "index": 0, "kind": { "FunctionCallError": { "HostError": { "GuestPanic": { "panic_msg": "test failure" } } } } } }
+        });
+        self
+    }
+
+    pub(crate) fn build(self) -> ExecutionOutcomeWithIdView {
+        serde_json::from_value(serde_json::json!({
+            "proof": [],
+            "block_hash": test_hash("block"),
+            "id": self.id,
+            "outcome": {
+                "logs": [],
+                "receipt_ids": self.receipt_ids,
+                "gas_burnt": 2_428_000_000_000u64,
+                "tokens_burnt": "0",
+                "executor_id": self.executor_id,
+                "status": self.status,
+                "metadata": { "version": 1, "gas_profile": null },
+            }
+        }))
+        .expect("invalid execution outcome built in test")
+    }
+}
+
+//a successful receipt together with its outcome, ready to be handled
+pub(crate) fn successful_receipt(receipt: ReceiptView) -> IndexerExecutionOutcomeWithReceipt {
+    let execution_outcome = OutcomeBuilder::new(&receipt).build();
+    IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt }
+}
+
+//a request received by the fake API server
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub signature: Option<String>,
+    pub body: serde_json::Value,
+}
+
+//in-process HTTP server that answers 200 to everything and records what it was sent
+pub(crate) struct FakeApiServer {
+    pub root: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl FakeApiServer {
+    pub(crate) async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                answer_request(socket, &recorded).await;
+            }
+        });
+
+        Self { root, requests }
+    }
+
+    pub(crate) fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    //the bodies POSTed to an endpoint path (without the API root). every body has to
+    //  carry the current schema_version, which is taken out so tests only compare the fields
+    pub(crate) fn posts_to(&self, path: &str) -> Vec<serde_json::Value> {
+        let path = format!("/{}", path);
+        self.requests()
+            .into_iter()
+            .filter(|request| request.method == "POST" && request.path == path)
+            .map(|mut request| {
+                let schema_version = request.body.as_object_mut().and_then(|body| body.remove("schema_version"));
+                assert_eq!(schema_version, Some(serde_json::json!(SCHEMA_VERSION)), "unversioned body POSTed to {}", path);
+                request.body
+            })
+            .collect()
+    }
+}
+
+//record the request before answering it, so it is visible as soon as the client gets its response
+async fn answer_request(mut socket: TcpStream, recorded: &Mutex<Vec<RecordedRequest>>) -> Option<()> {
+    //read until the end of the headers, then read the body they announce
+    let mut buffer = vec![];
+    let mut chunk = [0u8; 4096];
+    let headers_end = loop {
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..headers_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let mut content_length = 0;
+    let mut signature = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "signature" => signature = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    while buffer.len() < headers_end + content_length {
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = serde_json::from_slice(&buffer[headers_end..]).unwrap_or(serde_json::Value::Null);
+    recorded.lock().unwrap().push(RecordedRequest { method, path, signature, body });
+
+    let response = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}";
+    socket.write_all(response.as_bytes()).await.ok()
+}
+
+//a fresh directory to use as the near home dir of a test, removed when it is dropped
+pub(crate) fn test_home_dir() -> TempDir {
+    tempfile::tempdir().unwrap()
+}
+
+//an IndexerContext that keeps its home dir around for as long as the test uses it
+pub(crate) struct TestContext {
+    context: IndexerContext,
+    _home_dir: TempDir,
+}
+
+impl Deref for TestContext {
+    type Target = IndexerContext;
+
+    fn deref(&self) -> &IndexerContext {
+        &self.context
+    }
+}
+
+impl DerefMut for TestContext {
+    fn deref_mut(&mut self) -> &mut IndexerContext {
+        &mut self.context
+    }
+}
+
+//an IndexerContext watching the test contracts and talking to the fake API server
+pub(crate) fn test_context(viewer: MockChainViewer, api: &FakeApiServer) -> TestContext {
+    let temp_home_dir = test_home_dir();
+    let home_dir = temp_home_dir.path().to_path_buf();
+    let context = IndexerContext {
+        viewer: Box::new(viewer),
+        nft_contract: NFT_CONTRACT.to_string(),
+        market_contract: MARKET_CONTRACT.to_string(),
+        admin_account: ADMIN_ACCOUNT.to_string(),
+        public_api_root: api.root.clone(),
+        private_api_root: api.root.clone(),
+        signature_header: SIGNATURE_HEADER.to_string(),
+        debug_mode: "FALSE".to_string(),
+        charity_split_percent: None,
+        watch_config: WatchConfig::default(),
+        args_decoders: ArgsDecoders::from_watch_config(&WatchConfig::default()),
+        watched_accounts: WatchedAccounts::from_list(ADMIN_ACCOUNT, ""),
+        stats: Arc::new(IndexerStats::default()),
+        bids: BidBook::load(&home_dir),
+        analytics: SaleAnalytics::load(&home_dir),
+        home_dir,
+        lazy_purchases: PendingLazyPurchases::default(),
+        pending_payouts: PendingPayouts::default(),
+        recorder: None,
+        outbox: Outbox::new(FinalityMode::Immediate),
+        watched_shards: None,
+        dry_run: None,
+    };
+    TestContext { context, _home_dir: temp_home_dir }
+}
+
+//what nft_tokens_batch returns for a single token
+pub(crate) fn nft_tokens_batch_response(owner_id: &str, media: Option<&str>) -> serde_json::Value {
+    serde_json::json!([{
+        "token_id": "1",
+        "owner_id": owner_id,
+        "metadata": {
+            "title": "Test Token",
+            "description": "A token minted in a test",
+            "media": media,
+            "charity_account_id": "charity.test.near",
+            "artist_account_id": "artist.test.near",
+            "copies": 10,
+        }
+    }])
+}
@@ -0,0 +1,1570 @@
+use crate::abi::WatchConfig;
+use crate::analytics::{push_collection_stats, SaleAnalytics, StatsPeriod};
+use crate::args::ArgsDecoders;
+use crate::charity::{aggregate_donations, read_ledger, CharityDonation, DonationSource, ReportPeriod};
+use crate::checkpoint::{start_at, Checkpoint, StartAt, StartFrom};
+use crate::database::{
+    ACCOUNT_ACTIVITY_ENDPOINT, CHARITY_DONATION_ENDPOINT, COLLECTION_STATS_ENDPOINT, CONTRACT_CALL_ENDPOINT, INSERT_FOR_SALE_ENDPOINT, MINT_TOKEN_ENDPOINT, OUTBID_ENDPOINT, PAYOUT_ENDPOINT, PLACE_BID_ENDPOINT,
+    REMOVE_FOR_SALE_ENDPOINT, SELL_TOKEN_ENDPOINT, STORAGE_BALANCE_ENDPOINT, UPDATE_PRICE_ENDPOINT,
+};
+use crate::doctor::{check_api, check_home_dir, check_tracked_shards_setting, parse_df_available, CheckStatus};
+use crate::dry_run::{dry_run_path, DryRun, DryRunFormat};
+use crate::events::IndexerEvent;
+use crate::finality::{collect_events, BlockInfo, FinalityMode, Outbox};
+use crate::health::{IndexerStats, ShardLag};
+use crate::poison::poison_receipts_path;
+use crate::schema::{breaking_changes, check_released_schemas};
+use crate::settings::{localnet_market_settings, patch_tracked_shards, settings_path, write_starter_settings, Settings};
+use crate::shards::{account_shard, check_tracked_shards, genesis_num_shards, node_tracked_shards, watched_shards};
+use crate::sink::{deliver_event, send_events};
+use crate::status::{indexer_status, save_status, status_report, NodeHeadSource};
+use crate::storage::listings_affordable;
+use crate::test_support::*;
+use crate::viewer::MockChainViewer;
+use crate::{handle_receipt_isolated, IndexerContext};
+
+use near_indexer::IndexerExecutionOutcomeWithReceipt;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+const BLOCK_HEIGHT: u64 = 42;
+//2021-06-15T00:00:00Z
+const BLOCK_TIMESTAMP: u64 = 1_623_715_200_000_000_000;
+
+async fn handle(receipt: IndexerExecutionOutcomeWithReceipt, context: &IndexerContext) {
+    handle_receipt_isolated(receipt, BLOCK_HEIGHT, BLOCK_TIMESTAMP, context).await;
+}
+
+#[tokio::test]
+async fn nft_mint_delivers_the_minted_token() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let receipt = ReceiptBuilder::new("mint", "artist.test.near", NFT_CONTRACT)
+        .function_call(
+            "nft_mint",
+            serde_json::json!({
+                "token_id": "sunset_0",
+                "receiver_id": "artist.test.near",
+                "metadata": {
+                    "title": "Sunset",
+                    "description": "Five editions of a sunset",
+                    "media": "https://media/sunset.png",
+                    "artist_account_id": "artist.test.near",
+                    "charity_account_id": "charity.test.near",
+                    "copies": 5,
+                    "extra": serde_json::json!({ "tags": ["nature", "sky"], "aspect_ratio": 1.5 }).to_string(),
+                },
+            }),
+            ONE_NEAR / 10,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert_eq!(
+        api.posts_to(MINT_TOKEN_ENDPOINT),
+        vec![serde_json::json!({
+            "token_id": "sunset_0",
+            "contract_id": NFT_CONTRACT,
+            "tags": ["nature", "sky"],
+            "artwork_url": "https://media/sunset.png",
+            "owner_account_id": "artist.test.near",
+            "artist_account_id": "artist.test.near",
+            "charity_account_id": "charity.test.near",
+            "aspect_ratio": 1.5,
+            "copies": 5,
+            "title": "Sunset",
+            "description": "Five editions of a sunset",
+        })]
+    );
+}
+
+#[tokio::test]
+async fn nft_mint_payout_sells_the_edition_token() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let receipt = ReceiptBuilder::new("mint_payout", MARKET_CONTRACT, NFT_CONTRACT)
+        .function_call(
+            "nft_mint_payout",
+            serde_json::json!({
+                "base_token_id": "sunset_0",
+                "edition_number": "3",
+                "balance": (2 * ONE_NEAR).to_string(),
+                "receiver_id": "bob.test.near",
+            }),
+            0,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    let requests = api.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].signature.as_deref(), Some(SIGNATURE_HEADER));
+    assert_eq!(
+        api.posts_to(SELL_TOKEN_ENDPOINT),
+        vec![serde_json::json!({
+            "token_id": "sunset_3",
+            "contract_id": NFT_CONTRACT,
+            "purchaser_account_id": "bob.test.near",
+            "price_near": 2.0,
+            "admin_account_id": ADMIN_ACCOUNT,
+            "receipt_id": test_hash("mint_payout"),
+            "lazy": true,
+        })]
+    );
+}
+
+#[tokio::test]
+async fn lazy_purchase_is_sold_once_the_edition_is_minted() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let offer = ReceiptBuilder::new("lazy_offer", "bob.test.near", MARKET_CONTRACT)
+        .function_call(
+            "offer",
+            serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "sunset_0", "lazy_purchase": true }),
+            3 * ONE_NEAR,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&offer).produces("lazy_mint").build();
+    handle(IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: offer }, &context).await;
+    assert!(api.requests().is_empty());
+
+    //the market keeps its fee, so the balance passed on is less than what the buyer paid
+    let mint = ReceiptBuilder::new("lazy_mint", MARKET_CONTRACT, NFT_CONTRACT)
+        .signer("bob.test.near")
+        .function_call(
+            "nft_mint_payout",
+            serde_json::json!({
+                "base_token_id": "sunset_0",
+                "edition_number": 12,
+                "balance": (27 * ONE_NEAR / 10).to_string(),
+                "receiver_id": "bob.test.near",
+            }),
+            0,
+        )
+        .build();
+    handle(successful_receipt(mint), &context).await;
+
+    assert_eq!(
+        api.posts_to(SELL_TOKEN_ENDPOINT),
+        vec![serde_json::json!({
+            "token_id": "sunset_12",
+            "contract_id": NFT_CONTRACT,
+            "purchaser_account_id": "bob.test.near",
+            "price_near": 3.0,
+            "admin_account_id": ADMIN_ACCOUNT,
+            "receipt_id": test_hash("lazy_offer"),
+            "lazy": true,
+        })]
+    );
+}
+
+#[tokio::test]
+async fn nft_mint_payout_of_a_token_that_is_not_a_base_token_is_poison() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    //"art_01" used to be split on its "_0" and sold as "art_5"
+    let receipt = ReceiptBuilder::new("bad_mint_payout", MARKET_CONTRACT, NFT_CONTRACT)
+        .function_call(
+            "nft_mint_payout",
+            serde_json::json!({
+                "base_token_id": "art_01",
+                "edition_number": "5",
+                "balance": ONE_NEAR.to_string(),
+                "receiver_id": "bob.test.near",
+            }),
+            0,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert!(api.requests().is_empty());
+    let poison_receipts = std::fs::read_to_string(poison_receipts_path(&context.home_dir)).unwrap();
+    assert!(poison_receipts.contains("art_01 is not a base token"));
+}
+
+#[tokio::test]
+async fn nft_on_approve_lists_the_token_for_sale() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("alice.test.near", Some("https://media/1.png")),
+    );
+    let context = test_context(viewer, &api);
+
+    let receipt = ReceiptBuilder::new("approve", NFT_CONTRACT, MARKET_CONTRACT)
+        .signer("alice.test.near")
+        .function_call(
+            "nft_on_approve",
+            serde_json::json!({
+                "token_id": "1",
+                "owner_id": "alice.test.near",
+                "approval_id": 0,
+                "msg": serde_json::json!({ "sale_conditions": { "near": (5 * ONE_NEAR).to_string() } }).to_string(),
+            }),
+            0,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert_eq!(
+        api.posts_to(INSERT_FOR_SALE_ENDPOINT),
+        vec![serde_json::json!({ "token_id": "1", "contract_id": NFT_CONTRACT, "price_near": 5.0 })]
+    );
+}
+
+#[tokio::test]
+async fn nft_on_approve_skips_tokens_without_media() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("alice.test.near", None),
+    );
+    let context = test_context(viewer, &api);
+
+    let receipt = ReceiptBuilder::new("approve", NFT_CONTRACT, MARKET_CONTRACT)
+        .function_call(
+            "nft_on_approve",
+            serde_json::json!({
+                "token_id": "1",
+                "msg": serde_json::json!({ "sale_conditions": { "near": ONE_NEAR.to_string() } }).to_string(),
+            }),
+            0,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert!(api.requests().is_empty());
+}
+
+#[tokio::test]
+async fn storage_deposit_reports_how_many_listings_are_left() {
+    let api = FakeApiServer::start().await;
+    let storage_per_listing = ONE_NEAR / 4;
+    //enough storage for 4 listings, 3 of which are used
+    let viewer = MockChainViewer::default()
+        .with_response(MARKET_CONTRACT, "storage_balance_of", serde_json::json!(ONE_NEAR.to_string()))
+        .with_response(MARKET_CONTRACT, "storage_minimum_balance", serde_json::json!(storage_per_listing.to_string()))
+        .with_response(MARKET_CONTRACT, "get_supply_by_owner_id", serde_json::json!("3"));
+    let context = test_context(viewer, &api);
+
+    let deposit = ReceiptBuilder::new("deposit", "alice.test.near", MARKET_CONTRACT)
+        .function_call("storage_deposit", serde_json::json!({ "account_id": "bob.test.near" }), storage_per_listing)
+        .build();
+    handle(successful_receipt(deposit), &context).await;
+    //the market answers the same after the withdrawal, nothing beyond the listings was left to take back
+    let withdraw = ReceiptBuilder::new("withdraw", "bob.test.near", MARKET_CONTRACT)
+        .function_call("storage_withdraw", serde_json::json!({}), 1)
+        .build();
+    handle(successful_receipt(withdraw), &context).await;
+
+    assert_eq!(
+        api.posts_to(STORAGE_BALANCE_ENDPOINT),
+        vec![
+            serde_json::json!({
+                "account_id": "bob.test.near",
+                "change": "deposit",
+                "amount": storage_per_listing.to_string(),
+                "balance": ONE_NEAR.to_string(),
+                "balance_near": 1.0,
+                "listings": 3,
+                "listings_affordable": 1,
+                "listing_would_fail": false,
+                "receipt_id": test_hash("deposit"),
+                "block_height": BLOCK_HEIGHT,
+                "block_timestamp": BLOCK_TIMESTAMP,
+            }),
+            serde_json::json!({
+                "account_id": "bob.test.near",
+                "change": "withdraw",
+                "amount": null,
+                "balance": ONE_NEAR.to_string(),
+                "balance_near": 1.0,
+                "listings": 3,
+                "listings_affordable": 1,
+                "listing_would_fail": false,
+                "receipt_id": test_hash("withdraw"),
+                "block_height": BLOCK_HEIGHT,
+                "block_timestamp": BLOCK_TIMESTAMP,
+            }),
+        ]
+    );
+}
+
+#[test]
+fn listing_fails_once_the_storage_is_used_up() {
+    let storage_per_listing = ONE_NEAR / 100;
+    assert_eq!(listings_affordable(5 * storage_per_listing, storage_per_listing, 3), 2);
+    //a balance that doesn't cover a whole listing doesn't count
+    assert_eq!(listings_affordable(3 * storage_per_listing - 1, storage_per_listing, 2), 0);
+    //listings from before the market raised its price can exceed what the balance pays for
+    assert_eq!(listings_affordable(storage_per_listing, storage_per_listing, 4), 0);
+}
+
+#[tokio::test]
+async fn update_price_updates_the_listing() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let receipt = ReceiptBuilder::new("update_price", "alice.test.near", MARKET_CONTRACT)
+        .function_call(
+            "update_price",
+            serde_json::json!({
+                "nft_contract_id": NFT_CONTRACT,
+                "token_id": "1",
+                "ft_token_id": "near",
+                "price": (7 * ONE_NEAR / 2).to_string(),
+            }),
+            1,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert_eq!(
+        api.posts_to(UPDATE_PRICE_ENDPOINT),
+        vec![serde_json::json!({ "token_id": "1", "contract_id": NFT_CONTRACT, "price_near": 3.5 })]
+    );
+}
+
+fn offer(deposit: u128) -> IndexerExecutionOutcomeWithReceipt {
+    let receipt = ReceiptBuilder::new("offer", "bob.test.near", MARKET_CONTRACT)
+        .function_call(
+            "offer",
+            serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" }),
+            deposit,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&receipt).produces("transfer_payout").produces("resolve_purchase").build();
+    IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt }
+}
+
+fn resolve_purchase(price: u128) -> IndexerExecutionOutcomeWithReceipt {
+    let receipt = ReceiptBuilder::new("resolve_purchase", MARKET_CONTRACT, MARKET_CONTRACT)
+        .signer("bob.test.near")
+        .function_call(
+            "resolve_purchase",
+            serde_json::json!({ "buyer_id": "bob.test.near", "price": price.to_string() }),
+            0,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&receipt).returns(serde_json::json!(price.to_string())).build();
+    IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt }
+}
+
+#[tokio::test]
+async fn offer_from_the_new_owner_sells_the_token() {
+    let api = FakeApiServer::start().await;
+    //the market transfers the token in the block after the offer
+    let viewer = MockChainViewer::default()
+        .with_response(
+            NFT_CONTRACT,
+            "nft_tokens_batch",
+            nft_tokens_batch_response("alice.test.near", Some("https://media/1.png")),
+        )
+        .with_response_from(
+            BLOCK_HEIGHT + 1,
+            NFT_CONTRACT,
+            "nft_tokens_batch",
+            nft_tokens_batch_response("bob.test.near", Some("https://media/1.png")),
+        );
+    let context = test_context(viewer, &api);
+
+    handle(offer(5 * ONE_NEAR), &context).await;
+    assert!(api.requests().is_empty());
+
+    handle_receipt_isolated(resolve_purchase(5 * ONE_NEAR), BLOCK_HEIGHT + 1, BLOCK_TIMESTAMP, &context).await;
+
+    assert_eq!(
+        api.posts_to(SELL_TOKEN_ENDPOINT),
+        vec![serde_json::json!({
+            "token_id": "1",
+            "contract_id": NFT_CONTRACT,
+            "purchaser_account_id": "bob.test.near",
+            "price_near": 5.0,
+            "admin_account_id": ADMIN_ACCOUNT,
+            "receipt_id": test_hash("offer"),
+            "lazy": false,
+        })]
+    );
+}
+
+#[tokio::test]
+async fn offer_is_sold_once_nft_transfer_payout_succeeded() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("alice.test.near", Some("https://media/1.png")),
+    );
+    let context = test_context(viewer, &api);
+
+    handle(offer(5 * ONE_NEAR), &context).await;
+
+    let transfer = ReceiptBuilder::new("transfer_payout", MARKET_CONTRACT, NFT_CONTRACT)
+        .signer("bob.test.near")
+        .sends_result_to("payout", MARKET_CONTRACT)
+        .function_call(
+            "nft_transfer_payout",
+            serde_json::json!({
+                "receiver_id": "bob.test.near",
+                "token_id": "1",
+                "approval_id": 0,
+                "balance": (5 * ONE_NEAR).to_string(),
+                "max_len_payout": 10,
+            }),
+            1,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&transfer)
+        .returns(serde_json::json!({ "payout": { "alice.test.near": (5 * ONE_NEAR).to_string() } }))
+        .build();
+    let transfer = IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: transfer };
+    handle_receipt_isolated(transfer, BLOCK_HEIGHT + 1, BLOCK_TIMESTAMP, &context).await;
+
+    let resolve = ReceiptBuilder::new("resolve_purchase", MARKET_CONTRACT, MARKET_CONTRACT)
+        .signer("bob.test.near")
+        .waits_for("payout")
+        .function_call(
+            "resolve_purchase",
+            serde_json::json!({ "buyer_id": "bob.test.near", "price": (5 * ONE_NEAR).to_string() }),
+            0,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&resolve).returns(serde_json::json!((5 * ONE_NEAR).to_string())).build();
+    let resolve = IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: resolve };
+    handle_receipt_isolated(resolve, BLOCK_HEIGHT + 2, BLOCK_TIMESTAMP, &context).await;
+
+    //the transfer outcome decides the sale, the owner isn't looked up
+    let sold = api.posts_to(SELL_TOKEN_ENDPOINT);
+    assert_eq!(sold.len(), 1);
+    assert_eq!(sold[0]["purchaser_account_id"], "bob.test.near");
+    assert_eq!(sold[0]["receipt_id"], test_hash("offer"));
+}
+
+#[tokio::test]
+async fn offer_that_did_not_transfer_the_token_is_not_a_sale() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("alice.test.near", Some("https://media/1.png")),
+    );
+    let mut context = test_context(viewer, &api);
+    context.charity_split_percent = Some(10.0);
+
+    handle(offer(ONE_NEAR), &context).await;
+    //the transfer failed, so the market refunded the buyer
+    handle_receipt_isolated(resolve_purchase(ONE_NEAR), BLOCK_HEIGHT + 1, BLOCK_TIMESTAMP, &context).await;
+
+    assert!(api.requests().is_empty());
+}
+
+#[tokio::test]
+async fn remove_sale_removes_the_listing() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let receipt = ReceiptBuilder::new("remove_sale", "alice.test.near", MARKET_CONTRACT)
+        .function_call(
+            "remove_sale",
+            serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" }),
+            1,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert_eq!(
+        api.posts_to(REMOVE_FOR_SALE_ENDPOINT),
+        vec![serde_json::json!({ "token_id": "1", "contract_id": NFT_CONTRACT })]
+    );
+}
+
+#[tokio::test]
+async fn nft_revoke_of_the_market_removes_the_listing() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let revoke_market = ReceiptBuilder::new("revoke_market", NFT_CONTRACT, NFT_CONTRACT)
+        .function_call(
+            "nft_revoke",
+            serde_json::json!({ "token_id": "1", "account_id": MARKET_CONTRACT }),
+            1,
+        )
+        .build();
+    let revoke_other = ReceiptBuilder::new("revoke_other", NFT_CONTRACT, NFT_CONTRACT)
+        .function_call(
+            "nft_revoke",
+            serde_json::json!({ "token_id": "2", "account_id": "other-market.test.near" }),
+            1,
+        )
+        .build();
+    handle(successful_receipt(revoke_market), &context).await;
+    handle(successful_receipt(revoke_other), &context).await;
+
+    assert_eq!(
+        api.posts_to(REMOVE_FOR_SALE_ENDPOINT),
+        vec![serde_json::json!({ "token_id": "1", "contract_id": NFT_CONTRACT })]
+    );
+}
+
+#[tokio::test]
+async fn nft_revoke_all_removes_the_listing() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let receipt = ReceiptBuilder::new("revoke_all", NFT_CONTRACT, NFT_CONTRACT)
+        .function_call("nft_revoke_all", serde_json::json!({ "token_id": "1" }), 1)
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert_eq!(
+        api.posts_to(REMOVE_FOR_SALE_ENDPOINT),
+        vec![serde_json::json!({ "token_id": "1", "contract_id": NFT_CONTRACT })]
+    );
+}
+
+fn place_bid(name: &str, bidder: &str, deposit: u128) -> IndexerExecutionOutcomeWithReceipt {
+    let receipt = ReceiptBuilder::new(name, bidder, MARKET_CONTRACT)
+        .function_call(
+            "place_bid",
+            serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" }),
+            deposit,
+        )
+        .build();
+    successful_receipt(receipt)
+}
+
+fn accept_offer() -> IndexerExecutionOutcomeWithReceipt {
+    let receipt = ReceiptBuilder::new("accept_offer", "alice.test.near", MARKET_CONTRACT)
+        .function_call(
+            "accept_offer",
+            serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1", "ft_token_id": "near" }),
+            1,
+        )
+        .build();
+    successful_receipt(receipt)
+}
+
+#[tokio::test]
+async fn place_bid_reports_the_bid_and_who_was_outbid() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    handle(place_bid("bob_bid", "bob.test.near", ONE_NEAR), &context).await;
+    handle(place_bid("carol_bid", "carol.test.near", 2 * ONE_NEAR), &context).await;
+
+    assert_eq!(
+        api.posts_to(PLACE_BID_ENDPOINT),
+        vec![
+            serde_json::json!({
+                "token_id": "1",
+                "contract_id": NFT_CONTRACT,
+                "bidder_account_id": "bob.test.near",
+                "ft_token_id": "near",
+                "amount": ONE_NEAR.to_string(),
+                "receipt_id": test_hash("bob_bid"),
+                "outbid_by_account_id": null,
+            }),
+            serde_json::json!({
+                "token_id": "1",
+                "contract_id": NFT_CONTRACT,
+                "bidder_account_id": "carol.test.near",
+                "ft_token_id": "near",
+                "amount": (2 * ONE_NEAR).to_string(),
+                "receipt_id": test_hash("carol_bid"),
+                "outbid_by_account_id": null,
+            }),
+        ]
+    );
+    assert_eq!(
+        api.posts_to(OUTBID_ENDPOINT),
+        vec![serde_json::json!({
+            "token_id": "1",
+            "contract_id": NFT_CONTRACT,
+            "bidder_account_id": "bob.test.near",
+            "ft_token_id": "near",
+            "amount": ONE_NEAR.to_string(),
+            "receipt_id": null,
+            "outbid_by_account_id": "carol.test.near",
+        })]
+    );
+}
+
+#[tokio::test]
+async fn accept_offer_sells_to_the_highest_bidder() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    handle(place_bid("carol_bid", "carol.test.near", 3 * ONE_NEAR), &context).await;
+    handle(place_bid("bob_bid", "bob.test.near", ONE_NEAR), &context).await;
+    handle(accept_offer(), &context).await;
+
+    assert_eq!(
+        api.posts_to(SELL_TOKEN_ENDPOINT),
+        vec![serde_json::json!({
+            "token_id": "1",
+            "contract_id": NFT_CONTRACT,
+            "purchaser_account_id": "carol.test.near",
+            "price_near": 3.0,
+            "admin_account_id": ADMIN_ACCOUNT,
+            "receipt_id": test_hash("accept_offer"),
+            "lazy": false,
+        })]
+    );
+    assert!(context.bids.highest_bid(NFT_CONTRACT, "1").is_none());
+}
+
+#[tokio::test]
+async fn accept_offer_of_an_untracked_bid_asks_the_market() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        MARKET_CONTRACT,
+        "get_sale",
+        serde_json::json!({
+            "owner_id": "alice.test.near",
+            "approval_id": "0",
+            "nft_contract_id": NFT_CONTRACT,
+            "token_id": "1",
+            "sale_conditions": {},
+            "bids": { "near": [
+                { "owner_id": "bob.test.near", "price": ONE_NEAR.to_string() },
+                { "owner_id": "dave.test.near", "price": (4 * ONE_NEAR).to_string() },
+            ] },
+            "is_auction": true,
+        }),
+    );
+    let context = test_context(viewer, &api);
+
+    handle(accept_offer(), &context).await;
+
+    assert_eq!(api.posts_to(SELL_TOKEN_ENDPOINT).len(), 1);
+    assert_eq!(api.posts_to(SELL_TOKEN_ENDPOINT)[0]["purchaser_account_id"], "dave.test.near");
+    assert_eq!(api.posts_to(SELL_TOKEN_ENDPOINT)[0]["price_near"], 4.0);
+}
+
+#[tokio::test]
+async fn resolve_purchase_records_the_payout_of_nft_transfer_payout() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("bob.test.near", Some("https://media/1.png")),
+    );
+    let context = test_context(viewer, &api);
+
+    let transfer = ReceiptBuilder::new("transfer_payout", MARKET_CONTRACT, NFT_CONTRACT)
+        .signer("bob.test.near")
+        .sends_result_to("payout", MARKET_CONTRACT)
+        .function_call(
+            "nft_transfer_payout",
+            serde_json::json!({
+                "receiver_id": "bob.test.near",
+                "token_id": "1",
+                "approval_id": 0,
+                "balance": (5 * ONE_NEAR).to_string(),
+                "max_len_payout": 10,
+            }),
+            1,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&transfer)
+        .returns(serde_json::json!({ "payout": {
+            "alice.test.near": (7 * ONE_NEAR / 2).to_string(),
+            "artist.test.near": (ONE_NEAR / 2).to_string(),
+            "charity.test.near": ONE_NEAR.to_string(),
+        } }))
+        .build();
+    handle(IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: transfer }, &context).await;
+    assert!(api.requests().is_empty());
+
+    //resolve_purchase returns the price, not the payout
+    let resolve = ReceiptBuilder::new("resolve_purchase", MARKET_CONTRACT, MARKET_CONTRACT)
+        .signer("bob.test.near")
+        .waits_for("payout")
+        .function_call(
+            "resolve_purchase",
+            serde_json::json!({ "buyer_id": "bob.test.near", "price": (5 * ONE_NEAR).to_string() }),
+            0,
+        )
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&resolve).returns(serde_json::json!((5 * ONE_NEAR).to_string())).build();
+    handle(IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: resolve }, &context).await;
+
+    let payout = |recipient: &str, role: &str, amount: u128| {
+        serde_json::json!({
+            "token_id": "1",
+            "contract_id": NFT_CONTRACT,
+            "recipient_account_id": recipient,
+            "role": role,
+            "amount": amount.to_string(),
+            "amount_near": amount as f64 / ONE_NEAR as f64,
+            "receipt_id": test_hash("resolve_purchase"),
+        })
+    };
+    assert_eq!(
+        api.posts_to(PAYOUT_ENDPOINT),
+        vec![
+            payout("alice.test.near", "owner", 7 * ONE_NEAR / 2),
+            payout("artist.test.near", "artist", ONE_NEAR / 2),
+            payout("charity.test.near", "charity", ONE_NEAR),
+        ]
+    );
+
+    //the charity's share is also a donation in the charity ledger
+    let donation = CharityDonation {
+        charity_account_id: "charity.test.near".to_string(),
+        token_id: "1".to_string(),
+        contract_id: NFT_CONTRACT.to_string(),
+        amount: ONE_NEAR.to_string(),
+        amount_near: 1.0,
+        source: DonationSource::Payout,
+        receipt_id: test_hash("resolve_purchase"),
+        block_height: BLOCK_HEIGHT,
+        block_timestamp: BLOCK_TIMESTAMP,
+    };
+    assert_eq!(api.posts_to(CHARITY_DONATION_ENDPOINT), vec![serde_json::to_value(&donation).unwrap()]);
+    assert_eq!(read_ledger(&context.home_dir), vec![donation]);
+}
+
+#[tokio::test]
+async fn lazy_purchase_without_payout_donates_the_configured_split() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("bob.test.near", Some("https://media/1.png")),
+    );
+    let mut context = test_context(viewer, &api);
+    context.charity_split_percent = Some(10.0);
+
+    let receipt = ReceiptBuilder::new("mint_payout", MARKET_CONTRACT, NFT_CONTRACT)
+        .function_call(
+            "nft_mint_payout",
+            serde_json::json!({
+                "base_token_id": "sunset_0",
+                "edition_number": "3",
+                "balance": (2 * ONE_NEAR).to_string(),
+                "receiver_id": "bob.test.near",
+            }),
+            0,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    let donations = api.posts_to(CHARITY_DONATION_ENDPOINT);
+    assert_eq!(donations.len(), 1);
+    assert_eq!(donations[0]["charity_account_id"], "charity.test.near");
+    assert_eq!(donations[0]["token_id"], "sunset_3");
+    assert_eq!(donations[0]["amount"], (ONE_NEAR / 5).to_string());
+    assert_eq!(donations[0]["source"], "split");
+}
+
+#[tokio::test]
+async fn offer_sale_without_payout_donates_the_configured_split_once_resolved() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("bob.test.near", Some("https://media/1.png")),
+    );
+    let mut context = test_context(viewer, &api);
+    context.charity_split_percent = Some(10.0);
+
+    handle(offer(5 * ONE_NEAR), &context).await;
+    assert!(api.posts_to(CHARITY_DONATION_ENDPOINT).is_empty());
+
+    //the market transferred the token without nft_transfer_payout
+    handle(resolve_purchase(5 * ONE_NEAR), &context).await;
+
+    let donations = api.posts_to(CHARITY_DONATION_ENDPOINT);
+    assert_eq!(donations.len(), 1);
+    assert_eq!(donations[0]["token_id"], "1");
+    assert_eq!(donations[0]["amount"], (ONE_NEAR / 2).to_string());
+    assert_eq!(donations[0]["source"], "split");
+    assert_eq!(donations[0]["receipt_id"], test_hash("resolve_purchase"));
+}
+
+#[tokio::test]
+async fn resolve_purchase_without_a_price_is_poison() {
+    let api = FakeApiServer::start().await;
+    let viewer = MockChainViewer::default().with_response(
+        NFT_CONTRACT,
+        "nft_tokens_batch",
+        nft_tokens_batch_response("bob.test.near", Some("https://media/1.png")),
+    );
+    let mut context = test_context(viewer, &api);
+    context.charity_split_percent = Some(10.0);
+
+    handle(offer(5 * ONE_NEAR), &context).await;
+    let resolve = ReceiptBuilder::new("resolve_purchase", MARKET_CONTRACT, MARKET_CONTRACT)
+        .signer("bob.test.near")
+        .function_call("resolve_purchase", serde_json::json!({ "buyer_id": "bob.test.near" }), 0)
+        .build();
+    handle(successful_receipt(resolve), &context).await;
+
+    //no donation is made up from a price of 0
+    assert!(api.posts_to(CHARITY_DONATION_ENDPOINT).is_empty());
+    let poison_receipts = std::fs::read_to_string(poison_receipts_path(&context.home_dir)).unwrap();
+    let poison_receipt: serde_json::Value = serde_json::from_str(poison_receipts.lines().next().unwrap()).unwrap();
+    assert_eq!(poison_receipt["error"], "resolve_purchase was called without a price");
+}
+
+#[test]
+fn charity_report_totals_donations_per_charity_and_month() {
+    let donation = |charity: &str, amount: u128, block_timestamp: u64| CharityDonation {
+        charity_account_id: charity.to_string(),
+        token_id: "1".to_string(),
+        contract_id: NFT_CONTRACT.to_string(),
+        amount: amount.to_string(),
+        amount_near: amount as f64 / ONE_NEAR as f64,
+        source: DonationSource::Payout,
+        receipt_id: format!("{}-{}", charity, block_timestamp),
+        block_height: BLOCK_HEIGHT,
+        block_timestamp,
+    };
+    //2021-07-01T00:00:00Z
+    let july = 1_625_097_600_000_000_000;
+    let donations = vec![
+        donation("b.test.near", ONE_NEAR, BLOCK_TIMESTAMP),
+        donation("a.test.near", ONE_NEAR, BLOCK_TIMESTAMP),
+        donation("a.test.near", 2 * ONE_NEAR, BLOCK_TIMESTAMP + 1),
+        donation("a.test.near", ONE_NEAR / 2, july),
+    ];
+
+    let totals: Vec<(String, String, String, usize)> = aggregate_donations(&donations, ReportPeriod::Month)
+        .into_iter()
+        .map(|total| (total.charity_account_id, total.period, total.amount, total.donations))
+        .collect();
+    assert_eq!(
+        totals,
+        vec![
+            ("a.test.near".to_string(), "2021-06".to_string(), (3 * ONE_NEAR).to_string(), 2),
+            ("a.test.near".to_string(), "2021-07".to_string(), (ONE_NEAR / 2).to_string(), 1),
+            ("b.test.near".to_string(), "2021-06".to_string(), ONE_NEAR.to_string(), 1),
+        ]
+    );
+    assert_eq!(aggregate_donations(&donations, ReportPeriod::All).len(), 2);
+}
+
+#[tokio::test]
+async fn charity_report_leaves_out_donations_of_blocks_that_left_the_chain() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+    let donated = |receipt_id: &str, amount: u128| {
+        IndexerEvent::CharityDonated(CharityDonation {
+            charity_account_id: "charity.test.near".to_string(),
+            token_id: "1".to_string(),
+            contract_id: NFT_CONTRACT.to_string(),
+            amount: amount.to_string(),
+            amount_near: amount as f64 / ONE_NEAR as f64,
+            source: DonationSource::Split,
+            receipt_id: test_hash(receipt_id),
+            block_height: BLOCK_HEIGHT,
+            block_timestamp: BLOCK_TIMESTAMP,
+        })
+    };
+    let send_block = |block_info: BlockInfo, events: Vec<IndexerEvent>| {
+        let context = &context;
+        async move {
+            send_events(context.outbox.block_received(block_info.clone()), context).await;
+            send_events(context.outbox.block_handled(&block_info.hash, events), context).await;
+        }
+    };
+
+    send_block(block(1, "a", "genesis", "genesis"), vec![donated("first_sale", ONE_NEAR)]).await;
+    send_block(block(2, "b", "a", "genesis"), vec![donated("second_sale", 2 * ONE_NEAR)]).await;
+    assert_eq!(read_ledger(&context.home_dir).len(), 2);
+
+    //another block 2 builds on block 1, the second sale never happened
+    send_block(block(2, "b2", "a", "genesis"), vec![]).await;
+
+    let totals = aggregate_donations(&read_ledger(&context.home_dir), ReportPeriod::All);
+    assert_eq!(totals.len(), 1);
+    assert_eq!(totals[0].amount, ONE_NEAR.to_string());
+    assert_eq!(totals[0].donations, 1);
+}
+
+#[tokio::test]
+async fn watched_contract_calls_are_captured_with_their_fields() {
+    let api = FakeApiServer::start().await;
+    let mut context = test_context(MockChainViewer::default(), &api);
+    context.watch_config = serde_json::from_value(serde_json::json!({ "contracts": [{
+        "account_id": "other-nft.test.near",
+        "methods": [{ "name": "nft_mint", "fields": {
+            "token_id": "/token_id",
+            "title": "/metadata/title",
+            "missing": "/metadata/extra/colour",
+        } }],
+    }] }))
+    .unwrap();
+
+    let receipt = ReceiptBuilder::new("other_mint", "alice.test.near", "other-nft.test.near")
+        .function_call(
+            "nft_mint",
+            serde_json::json!({ "token_id": "7", "metadata": { "title": "Elsewhere" } }),
+            ONE_NEAR / 10,
+        )
+        .function_call("nft_burn", serde_json::json!({ "token_id": "7" }), 0)
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert_eq!(
+        api.posts_to(CONTRACT_CALL_ENDPOINT),
+        vec![serde_json::json!({
+            "contract_id": "other-nft.test.near",
+            "method_name": "nft_mint",
+            "signer_id": "alice.test.near",
+            "predecessor_id": "alice.test.near",
+            "deposit": (ONE_NEAR / 10).to_string(),
+            "receipt_id": test_hash("other_mint"),
+            "block_height": BLOCK_HEIGHT,
+            "fields": { "token_id": "7", "title": "Elsewhere", "missing": null },
+        })]
+    );
+    //the watched contract is not ours, so our own nft_mint handler doesn't run
+    assert!(api.posts_to(MINT_TOKEN_ENDPOINT).is_empty());
+}
+
+#[tokio::test]
+async fn borsh_args_are_decoded_with_the_registered_schema() {
+    let api = FakeApiServer::start().await;
+    let mut context = test_context(MockChainViewer::default(), &api);
+    context.watch_config = serde_json::from_value(serde_json::json!({ "contracts": [{
+        "account_id": "borsh.test.near",
+        "methods": [
+            {
+                "name": "transfer",
+                "fields": { "receiver": "/receiver_id", "amount": "/amount", "memo": "/memo" },
+                "borsh_schema": { "struct": [
+                    ["receiver_id", "string"],
+                    ["amount", "u128"],
+                    ["memo", { "option": "string" }],
+                ] },
+            },
+            { "name": "raw", "fields": { "bytes": "/hex" } },
+        ],
+    }] }))
+    .unwrap();
+    context.args_decoders = ArgsDecoders::from_watch_config(&context.watch_config);
+
+    //borsh: a u32 length then the bytes for a string, little endian numbers, a 0/1 tag for options
+    let mut borsh_args = vec![];
+    borsh_args.extend_from_slice(&13u32.to_le_bytes());
+    borsh_args.extend_from_slice(b"bob.test.near");
+    borsh_args.extend_from_slice(&(5 * ONE_NEAR).to_le_bytes());
+    borsh_args.push(0);
+    let receipt = ReceiptBuilder::new("borsh", "alice.test.near", "borsh.test.near")
+        .function_call_bytes("transfer", &borsh_args, 0)
+        .function_call_bytes("raw", &[0xde, 0xad, 0xbe, 0xef], 0)
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    let calls = api.posts_to(CONTRACT_CALL_ENDPOINT);
+    assert_eq!(calls.len(), 2);
+    assert_eq!(
+        calls[0]["fields"],
+        serde_json::json!({ "receiver": "bob.test.near", "amount": (5 * ONE_NEAR).to_string(), "memo": null })
+    );
+    //args nothing could decode are kept as hex and counted
+    assert_eq!(calls[1]["fields"], serde_json::json!({ "bytes": "deadbeef" }));
+    assert_eq!(context.stats.args_decode_failures(), 1);
+}
+
+#[test]
+fn watch_config_with_an_invalid_pointer_is_refused() {
+    let home_dir = test_home_dir();
+    let path = home_dir.path().join("watch.json");
+    std::fs::write(
+        &path,
+        r#"{ "contracts": [{ "account_id": "a.test.near", "methods": [{ "name": "m", "fields": { "id": "token_id" } }] }] }"#,
+    )
+    .unwrap();
+
+    let err = WatchConfig::load(&path).unwrap_err();
+    assert!(err.contains("invalid JSON pointer"), "{}", err);
+}
+
+#[tokio::test]
+async fn every_action_of_a_watched_account_is_reported() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    //the admin pays someone and adds a key to their own account, on contracts we don't index
+    let payment = ReceiptBuilder::new("payment", ADMIN_ACCOUNT, "bob.test.near").transfer(ONE_NEAR).build();
+    let add_key = ReceiptBuilder::new("add_key", ADMIN_ACCOUNT, ADMIN_ACCOUNT).add_full_access_key().build();
+    let failed_call = ReceiptBuilder::new("failed_call", "carol.test.near", ADMIN_ACCOUNT)
+        .function_call("ping", serde_json::json!({}), 0)
+        .build();
+    handle(successful_receipt(payment), &context).await;
+    handle(successful_receipt(add_key), &context).await;
+    let execution_outcome = OutcomeBuilder::new(&failed_call).failure().build();
+    handle(IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: failed_call }, &context).await;
+    //receipts that don't involve a watched account are not reported
+    let other = ReceiptBuilder::new("other", "carol.test.near", "bob.test.near").transfer(ONE_NEAR).build();
+    handle(successful_receipt(other), &context).await;
+
+    let activity: Vec<(String, String, serde_json::Value, bool)> = api
+        .posts_to(ACCOUNT_ACTIVITY_ENDPOINT)
+        .into_iter()
+        .map(|activity| {
+            (
+                activity["receipt_id"].as_str().unwrap().to_string(),
+                activity["role"].as_str().unwrap().to_string(),
+                activity["action"].clone(),
+                activity["success"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    let add_key_action = serde_json::json!({ "kind": "add_key", "public_key": PUBLIC_KEY, "permission": "FullAccess" });
+    assert_eq!(
+        activity,
+        vec![
+            (test_hash("payment"), "signer".to_string(), serde_json::json!({ "kind": "transfer", "deposit": ONE_NEAR.to_string() }), true),
+            (test_hash("add_key"), "signer".to_string(), add_key_action.clone(), true),
+            (test_hash("add_key"), "receiver".to_string(), add_key_action, true),
+            (test_hash("failed_call"), "receiver".to_string(), serde_json::json!({ "kind": "function_call", "method_name": "ping", "deposit": "0" }), false),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failed_and_unrelated_receipts_are_ignored() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let remove_sale = serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" });
+    let failed = ReceiptBuilder::new("failed", "alice.test.near", MARKET_CONTRACT)
+        .function_call("remove_sale", remove_sale.clone(), 1)
+        .build();
+    let execution_outcome = OutcomeBuilder::new(&failed).failure().build();
+    handle(IndexerExecutionOutcomeWithReceipt { execution_outcome, receipt: failed }, &context).await;
+
+    let unrelated = ReceiptBuilder::new("unrelated", "alice.test.near", "other.test.near")
+        .function_call("remove_sale", remove_sale, 1)
+        .build();
+    handle(successful_receipt(unrelated), &context).await;
+
+    assert!(api.requests().is_empty());
+}
+
+#[tokio::test]
+async fn failing_receipt_is_recorded_as_poison() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    //update_price without a price can't be handled
+    let broken = ReceiptBuilder::new("broken", "alice.test.near", MARKET_CONTRACT)
+        .function_call("update_price", serde_json::json!({ "token_id": "1" }), 1)
+        .build();
+    handle(successful_receipt(broken), &context).await;
+
+    //the next receipt is still handled
+    let receipt = ReceiptBuilder::new("remove_sale", "alice.test.near", MARKET_CONTRACT)
+        .function_call(
+            "remove_sale",
+            serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" }),
+            1,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    let poison_receipts = std::fs::read_to_string(poison_receipts_path(&context.home_dir)).unwrap();
+    let poison_receipts: Vec<serde_json::Value> =
+        poison_receipts.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(poison_receipts.len(), 1);
+    assert_eq!(poison_receipts[0]["receipt_id"], test_hash("broken"));
+    assert_eq!(poison_receipts[0]["block_height"], BLOCK_HEIGHT);
+    assert_eq!(poison_receipts[0]["error"], "update_price was called without a price");
+    assert_eq!(api.posts_to(REMOVE_FOR_SALE_ENDPOINT).len(), 1);
+}
+
+fn block(height: u64, hash: &str, prev_hash: &str, last_final_block: &str) -> BlockInfo {
+    BlockInfo {
+        height,
+        hash: hash.to_string(),
+        prev_hash: prev_hash.to_string(),
+        last_final_block: last_final_block.to_string(),
+    }
+}
+
+fn listing_removed(token_id: &str) -> IndexerEvent {
+    IndexerEvent::ListingRemoved { token_id: token_id.to_string(), contract_id: NFT_CONTRACT.to_string() }
+}
+
+fn event_names(events: &[IndexerEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|event| match event {
+            IndexerEvent::ListingRemoved { token_id, .. } => format!("removed {}", token_id),
+            IndexerEvent::Reverted { event, .. } => format!("reverted {}", event_names(&[*event.clone()])[0]),
+            other => other.name().to_string(),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn events_are_held_while_their_block_is_handled() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    let receipt = ReceiptBuilder::new("remove_sale", "alice.test.near", MARKET_CONTRACT)
+        .function_call("remove_sale", serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" }), 1)
+        .build();
+    let events = collect_events("a", handle(successful_receipt(receipt), &context)).await;
+
+    assert_eq!(event_names(&events), vec!["removed 1"]);
+    assert!(api.requests().is_empty());
+}
+
+#[test]
+fn events_go_out_once_their_block_is_deep_enough() {
+    let outbox = Outbox::new(FinalityMode::Depth(2));
+
+    assert!(outbox.block_received(block(1, "a", "genesis", "genesis")).is_empty());
+    assert!(outbox.block_handled("a", vec![listing_removed("1")]).is_empty());
+    assert!(outbox.block_received(block(2, "b", "a", "genesis")).is_empty());
+    assert!(outbox.block_handled("b", vec![listing_removed("2")]).is_empty());
+    assert_eq!(outbox.undelivered_heights(), vec![1, 2]);
+
+    //block 1 is now 2 blocks deep
+    assert_eq!(event_names(&outbox.block_received(block(3, "c", "b", "genesis"))), vec!["removed 1"]);
+    assert_eq!(outbox.undelivered_heights(), vec![2, 3]);
+}
+
+#[test]
+fn final_blocks_go_out_in_height_order() {
+    let outbox = Outbox::new(FinalityMode::Final);
+
+    outbox.block_received(block(1, "a", "genesis", "genesis"));
+    outbox.block_received(block(2, "b", "a", "genesis"));
+    assert!(outbox.block_handled("b", vec![listing_removed("2")]).is_empty());
+    //block 3 makes both final, but block 1 is still being handled and holds block 2 back
+    assert!(outbox.block_received(block(3, "c", "b", "b")).is_empty());
+    assert_eq!(event_names(&outbox.block_handled("a", vec![listing_removed("1")])), vec!["removed 1", "removed 2"]);
+    assert_eq!(outbox.undelivered_heights(), vec![3]);
+}
+
+#[test]
+fn events_of_a_block_that_left_the_chain_are_reverted() {
+    let outbox = Outbox::new(FinalityMode::Immediate);
+
+    outbox.block_received(block(1, "a", "genesis", "genesis"));
+    assert_eq!(event_names(&outbox.block_handled("a", vec![listing_removed("1")])), vec!["removed 1"]);
+    outbox.block_received(block(2, "b", "a", "genesis"));
+    assert_eq!(event_names(&outbox.block_handled("b", vec![listing_removed("2")])), vec!["removed 2"]);
+    outbox.block_received(block(3, "c", "b", "genesis"));
+
+    //another block 2 builds on block 1, the old block 2 and block 3 are gone
+    let reverted = outbox.block_received(block(2, "b2", "a", "genesis"));
+    assert_eq!(event_names(&reverted), vec!["reverted removed 2"]);
+    //block 3 was still being handled, its events are dropped
+    assert!(outbox.block_handled("c", vec![listing_removed("3")]).is_empty());
+    assert_eq!(event_names(&outbox.block_handled("b2", vec![listing_removed("4")])), vec!["removed 4"]);
+}
+
+#[tokio::test]
+async fn bids_of_a_block_that_left_the_chain_are_undone() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+    let handle_block = |block_info: BlockInfo, receipt: IndexerExecutionOutcomeWithReceipt| {
+        let context = &context;
+        async move {
+            context.outbox.block_received(block_info.clone());
+            context.follow_chain_changes();
+            let events = collect_events(&block_info.hash, handle(receipt, context)).await;
+            context.outbox.block_handled(&block_info.hash, events);
+        }
+    };
+
+    handle_block(block(1, "a", "genesis", "genesis"), place_bid("bob_bid", "bob.test.near", ONE_NEAR)).await;
+    handle_block(block(2, "b", "a", "genesis"), place_bid("carol_bid", "carol.test.near", 2 * ONE_NEAR)).await;
+    handle_block(block(3, "c", "b", "genesis"), accept_offer()).await;
+    assert_eq!(context.bids.highest_bid(NFT_CONTRACT, "1"), None);
+
+    //another block 2 builds on block 1, carol's bid and the sale never happened
+    context.outbox.block_received(block(2, "b2", "a", "genesis"));
+    context.follow_chain_changes();
+    assert_eq!(context.bids.highest_bid(NFT_CONTRACT, "1").unwrap().bidder_account_id, "bob.test.near");
+}
+
+#[test]
+fn contracts_on_untracked_shards_are_refused() {
+    //with a single shard everything lives on shard 0
+    assert_eq!(account_shard(MARKET_CONTRACT, 1), 0);
+
+    let contracts = [NFT_CONTRACT, MARKET_CONTRACT, NFT_CONTRACT];
+    let contract_shards = watched_shards(contracts.iter().copied(), 4);
+    let market_shard = account_shard(MARKET_CONTRACT, 4);
+    assert!(market_shard < 4);
+    assert!(contract_shards[&market_shard].contains(&MARKET_CONTRACT.to_string()));
+    assert_eq!(contract_shards.values().map(|accounts| accounts.len()).sum::<usize>(), 2);
+
+    let all_shards = node_tracked_shards(&[0, 1, 2, 3], &[], 4);
+    assert!(check_tracked_shards(&contract_shards, &all_shards).is_ok());
+    //tracking the market account is enough to track its shard
+    let market_only = node_tracked_shards(&[], &[MARKET_CONTRACT.to_string()], 4);
+    assert_eq!(market_only.into_iter().collect::<Vec<_>>(), vec![market_shard]);
+
+    let err = check_tracked_shards(&watched_shards(vec![MARKET_CONTRACT], 1), &BTreeSet::new()).unwrap_err();
+    assert!(err.contains("shard 0 (hosting market.test.near)"), "{}", err);
+    assert!(err.contains("\"tracked_shards\": [0]"), "{}", err);
+}
+
+#[test]
+fn shard_lag_is_counted_from_the_latest_block_received() {
+    let stats = IndexerStats::default();
+    stats.block_received(10);
+    stats.block_received(11);
+    stats.shard_processed(0, 11, Duration::from_millis(5));
+    stats.shard_processed(1, 10, Duration::from_millis(7));
+    //a late finishing older block doesn't move the shard back
+    stats.shard_processed(0, 10, Duration::from_millis(9));
+
+    let shard_lags = stats.shard_lags();
+    assert_eq!(shard_lags[&0], ShardLag { last_processed_height: 11, lag_blocks: 0, last_handling_ms: 5 });
+    assert_eq!(shard_lags[&1], ShardLag { last_processed_height: 10, lag_blocks: 1, last_handling_ms: 7 });
+}
+
+#[test]
+fn backlog_counts_the_blocks_held_back_in_the_outbox() {
+    let stats = IndexerStats::default();
+    stats.block_received(10);
+    //more blocks than are ever handled at once, waiting for finality
+    stats.set_outbox_backlog(150);
+    assert_eq!(stats.backlog(), 151);
+}
+
+#[test]
+fn init_tracks_every_shard_and_writes_starter_settings() {
+    let temp_home_dir = test_home_dir();
+    let home_dir = temp_home_dir.path();
+    std::fs::write(home_dir.join("config.json"), r#"{ "tracked_shards": [], "archive": false }"#).unwrap();
+    std::fs::write(home_dir.join("genesis.json"), r#"{ "num_block_producer_seats_per_shard": [100, 100], "records": [] }"#).unwrap();
+
+    //the shard count comes from the genesis, not from --num-shards
+    let num_shards = genesis_num_shards(home_dir).unwrap();
+    assert_eq!(patch_tracked_shards(&home_dir, num_shards).unwrap(), vec![0, 1]);
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(home_dir.join("config.json")).unwrap()).unwrap();
+    assert_eq!(config, serde_json::json!({ "tracked_shards": [0, 1], "archive": false }));
+
+    let mut given = localnet_market_settings();
+    given.insert("HEADER".to_string(), "local-signature".to_string());
+    assert!(write_starter_settings(&home_dir, given).unwrap());
+    let settings: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(settings_path(&home_dir)).unwrap()).unwrap();
+    assert_eq!(settings["MARKET"], "market.test.near");
+    assert_eq!(settings["HEADER"], "local-signature");
+    //settings that can't be guessed are there to be filled in
+    assert_eq!(settings["WATCH_CONFIG"], "");
+
+    //running init again keeps what the user filled in
+    assert!(!write_starter_settings(&home_dir, Default::default()).unwrap());
+    assert_eq!(std::fs::read_to_string(settings_path(&home_dir)).unwrap(), serde_json::to_string_pretty(&settings).unwrap());
+}
+
+#[tokio::test]
+async fn doctor_says_what_is_missing_and_how_to_fix_it() {
+    let api = FakeApiServer::start().await;
+    let temp_home_dir = test_home_dir();
+    let home_dir = temp_home_dir.path();
+    std::fs::write(home_dir.join("config.json"), r#"{ "tracked_shards": [], "tracked_accounts": [] }"#).unwrap();
+    std::fs::write(home_dir.join("genesis.json"), r#"{ "num_block_producer_seats_per_shard": [100], "records": [] }"#).unwrap();
+
+    let home_checks = check_home_dir(&home_dir);
+    let failed: Vec<&str> = home_checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Failed)
+        .map(|check| check.name.as_str())
+        .collect();
+    assert_eq!(failed, vec!["home dir: node_key.json"]);
+
+    let contracts = vec![NFT_CONTRACT.to_string(), MARKET_CONTRACT.to_string()];
+    let shards_check = check_tracked_shards_setting(&home_dir, &contracts);
+    assert_eq!(shards_check.status, CheckStatus::Failed);
+    assert!(shards_check.detail.contains("\"tracked_shards\": [0]"), "{}", shards_check.detail);
+    patch_tracked_shards(&home_dir, 1).unwrap();
+    assert_eq!(check_tracked_shards_setting(&home_dir, &contracts).status, CheckStatus::Ok);
+
+    let mut given = localnet_market_settings();
+    given.insert("PRIVATE_API".to_string(), api.root.clone());
+    given.insert("HEADER".to_string(), SIGNATURE_HEADER.to_string());
+    write_starter_settings(&home_dir, given).unwrap();
+    let api_check = check_api(&Settings::load(&home_dir)).await;
+    assert_eq!(api_check.status, CheckStatus::Ok, "{:?}", api_check);
+    assert_eq!(api.requests()[0].signature.as_deref(), Some(SIGNATURE_HEADER));
+}
+
+#[test]
+fn free_disk_space_is_read_from_df() {
+    let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/sda1        102687672  50307188  47121588      52% /\n";
+    assert_eq!(parse_df_available(output), Some(47_121_588 * 1024));
+    assert_eq!(parse_df_available("df: /missing: No such file or directory\n"), None);
+}
+
+#[tokio::test]
+async fn status_reads_back_what_the_indexer_saved() {
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    context.stats.block_received(BLOCK_HEIGHT);
+    let receipt = ReceiptBuilder::new("remove_sale", "alice.test.near", MARKET_CONTRACT)
+        .function_call("remove_sale", serde_json::json!({ "nft_contract_id": NFT_CONTRACT, "token_id": "1" }), 1)
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+    context.stats.block_processed(BLOCK_HEIGHT);
+    save_status(&context.home_dir, &indexer_status(&context, false)).unwrap();
+
+    //the fake API isn't a node, so the head of the chain is unknown
+    let report = status_report(&context.home_dir, Some(api.root.clone())).await;
+    assert_eq!(report.node_head_height, None);
+    let status = report.status.unwrap();
+    assert!(!status.running);
+    assert_eq!(status.last_processed_height, BLOCK_HEIGHT);
+    assert!(status.outbox_backlog.is_empty());
+    let last_removal = &status.last_events["ListingRemoved"];
+    assert!(last_removal.delivered);
+    assert_eq!(last_removal.event, serde_json::json!({ "event": "ListingRemoved", "token_id": "1", "contract_id": NFT_CONTRACT }));
+}
+
+#[tokio::test]
+async fn status_reads_the_head_a_stopped_node_left_in_its_database() {
+    let home_dir = test_home_dir();
+    //the database of a node that stopped at block 1234
+    {
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = rocksdb::DB::open_cf(&options, home_dir.path().join("data"), vec!["col0", "col1"]).unwrap();
+        let mut tip = 1234u64.to_le_bytes().to_vec();
+        //the hashes of the head and its parent follow the height
+        tip.extend_from_slice(&[0; 64]);
+        db.put_cf(db.cf_handle("col1").unwrap(), b"HEAD", tip).unwrap();
+    }
+
+    let report = status_report(home_dir.path(), None).await;
+    assert_eq!(report.node_head_height, Some(1234));
+    assert_eq!(report.node_head_source, Some(NodeHeadSource::Database));
+}
+
+#[test]
+fn start_height_follows_the_cli_then_the_last_run() {
+    let stopped = Checkpoint { last_processed_height: 500, unfinished_heights: vec![] };
+    let interrupted = Checkpoint { last_processed_height: 500, unfinished_heights: vec![502, 501] };
+    let resume = StartFrom::Interruption { delta: 0 };
+
+    assert_eq!(start_at(StartFrom::Latest, Some(&interrupted), Some(510)), StartAt::Latest);
+    assert_eq!(start_at(StartFrom::Height(42), Some(&interrupted), None), StartAt::Height(42));
+    assert_eq!(start_at(resume, Some(&interrupted), Some(500)), StartAt::Height(501));
+    assert_eq!(start_at(StartFrom::Interruption { delta: 10 }, Some(&interrupted), None), StartAt::Height(491));
+    assert_eq!(start_at(resume, Some(&stopped), None), StartAt::Height(501));
+    //the status file is newer than the checkpoint, whose unfinished blocks were handled since
+    assert_eq!(start_at(resume, Some(&interrupted), Some(510)), StartAt::Height(511));
+    assert_eq!(start_at(StartFrom::Interruption { delta: 10 }, Some(&stopped), Some(510)), StartAt::Height(501));
+    //never ran from this home dir
+    assert_eq!(start_at(resume, None, None), StartAt::Latest);
+}
+
+#[tokio::test]
+async fn dry_run_writes_the_events_instead_of_sending_them() {
+    let api = FakeApiServer::start().await;
+    let mut context = test_context(MockChainViewer::default(), &api);
+    let path = dry_run_path(&context.home_dir);
+    context.dry_run = Some(DryRun::create(&path, DryRunFormat::Json).unwrap());
+
+    let receipt = ReceiptBuilder::new("update_price", "alice.test.near", MARKET_CONTRACT)
+        .function_call(
+            "update_price",
+            serde_json::json!({
+                "nft_contract_id": NFT_CONTRACT,
+                "token_id": "1",
+                "ft_token_id": "near",
+                "price": (7 * ONE_NEAR / 2).to_string(),
+            }),
+            1,
+        )
+        .build();
+    handle(successful_receipt(receipt), &context).await;
+
+    assert!(api.requests().is_empty());
+    let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![serde_json::json!({
+            "event": "PriceUpdated",
+            "token_id": "1",
+            "contract_id": NFT_CONTRACT,
+            "price_near": 3.5,
+            "block_timestamp": BLOCK_TIMESTAMP,
+        })]
+    );
+}
+
+#[test]
+fn bodies_still_match_the_released_schemas() {
+    //a breaking change needs a new SCHEMA_VERSION, written out with `schema --out-dir schemas/v<N>`
+    assert_eq!(check_released_schemas(), Vec::<String>::new());
+}
+
+#[test]
+fn breaking_changes_are_caught_but_new_fields_are_not() {
+    let released = serde_json::json!({
+        "required": ["price_near", "token_id"],
+        "properties": {
+            "token_id": { "type": "string" },
+            "price_near": { "type": "number" },
+            "role": { "$ref": "#/definitions/PayoutRole" },
+        },
+        "definitions": { "PayoutRole": { "type": "string", "enum": ["artist", "owner"] } },
+    });
+    let added_a_field = serde_json::json!({
+        "required": ["price_near", "token_id", "lazy"],
+        "properties": {
+            "token_id": { "type": "string" },
+            "price_near": { "type": "number" },
+            "role": { "$ref": "#/definitions/PayoutRole" },
+            "lazy": { "type": "boolean" },
+        },
+        "definitions": { "PayoutRole": { "type": "string", "enum": ["artist", "charity", "owner"] } },
+    });
+    assert_eq!(breaking_changes(&released, &added_a_field), Vec::<String>::new());
+
+    let broken = serde_json::json!({
+        "required": [],
+        "properties": {
+            "price_near": { "type": ["number", "null"] },
+            "role": { "$ref": "#/definitions/PayoutRole" },
+        },
+        "definitions": { "PayoutRole": { "type": "string", "enum": ["artist"] } },
+    });
+    assert_eq!(
+        breaking_changes(&released, &broken),
+        vec![
+            "field \"price_near\" is no longer always sent".to_string(),
+            "field \"price_near\" changed type from [\"number\"] to [\"null\", \"number\"]".to_string(),
+            "field \"token_id\" was removed".to_string(),
+            "PayoutRole no longer has the value \"owner\"".to_string(),
+        ]
+    );
+}
+
+fn sold(token_id: &str, price_near: Option<f64>, block_timestamp: u64) -> IndexerEvent {
+    IndexerEvent::TokenSold {
+        token_id: token_id.to_string(),
+        contract_id: NFT_CONTRACT.to_string(),
+        price_near,
+        purchaser_account_id: "bob.test.near".to_string(),
+        receipt_id: test_hash(token_id),
+        lazy: false,
+        block_timestamp,
+    }
+}
+
+#[tokio::test]
+async fn sales_are_rolled_up_per_collection_and_pushed_to_the_api() {
+    const ONE_HOUR: u64 = 3_600_000_000_000;
+    let api = FakeApiServer::start().await;
+    let context = test_context(MockChainViewer::default(), &api);
+
+    for (token_id, price_near) in [("1", 2.0), ("2", 4.0)] {
+        let listed = IndexerEvent::TokenListed {
+            token_id: token_id.to_string(),
+            contract_id: NFT_CONTRACT.to_string(),
+            price_near,
+            block_timestamp: BLOCK_TIMESTAMP,
+        };
+        deliver_event(listed, &context).await;
+    }
+    deliver_event(sold("1", Some(2.0), BLOCK_TIMESTAMP + ONE_HOUR), &context).await;
+    let offer_accepted = IndexerEvent::OfferAccepted {
+        token_id: "3".to_string(),
+        contract_id: NFT_CONTRACT.to_string(),
+        buyer_account_id: "carol.test.near".to_string(),
+        ft_token_id: "near".to_string(),
+        amount: (6 * ONE_NEAR).to_string(),
+        price_near: Some(6.0),
+        receipt_id: test_hash("accept_offer"),
+        block_timestamp: BLOCK_TIMESTAMP + 2 * ONE_HOUR,
+    };
+    deliver_event(offer_accepted, &context).await;
+    //paid in another FT, counted without a price
+    deliver_event(sold("4", None, BLOCK_TIMESTAMP + 24 * ONE_HOUR), &context).await;
+
+    let days = context.analytics.collection_stats(Some(NFT_CONTRACT), StatsPeriod::Day);
+    let summary: Vec<_> = days
+        .iter()
+        .map(|day| (day.period_start.as_str(), day.sales, day.volume_near, day.last_sale_near, day.average_sale_near, day.floor_near))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            //token 1 was listed at 2 before it sold, token 2 stays listed at 4
+            ("2021-06-15", 2, 8.0, Some(6.0), Some(4.0), Some(2.0)),
+            ("2021-06-16", 1, 0.0, None, None, Some(4.0)),
+        ]
+    );
+    let hours = context.analytics.collection_stats(None, StatsPeriod::Hour);
+    assert_eq!(hours.iter().map(|hour| hour.period_start.as_str()).collect::<Vec<_>>(), vec!["2021-06-15T00", "2021-06-15T01", "2021-06-15T02", "2021-06-16T00"]);
+    assert_eq!(hours[1].floor_near, Some(4.0));
+
+    push_collection_stats(&context).await;
+    let pushed = api.posts_to(COLLECTION_STATS_ENDPOINT);
+    assert_eq!(pushed.len(), 1);
+    assert_eq!(pushed[0]["collections"].as_array().unwrap().len(), 6);
+    //nothing changed since
+    push_collection_stats(&context).await;
+    assert_eq!(api.posts_to(COLLECTION_STATS_ENDPOINT).len(), 1);
+}
+
+#[test]
+fn old_buckets_are_pruned_and_reverted_listings_put_back() {
+    const ONE_DAY: u64 = 86_400_000_000_000;
+    let home_dir = test_home_dir();
+    let analytics = SaleAnalytics::load(home_dir.path());
+    let reverted = |event: IndexerEvent| IndexerEvent::Reverted {
+        block_height: BLOCK_HEIGHT,
+        block_hash: test_hash("forked"),
+        event: Box::new(event),
+    };
+    let listed = IndexerEvent::TokenListed {
+        token_id: "1".to_string(),
+        contract_id: NFT_CONTRACT.to_string(),
+        price_near: 2.0,
+        block_timestamp: BLOCK_TIMESTAMP,
+    };
+    let price_updated = IndexerEvent::PriceUpdated {
+        token_id: "1".to_string(),
+        contract_id: NFT_CONTRACT.to_string(),
+        price_near: 3.0,
+        block_timestamp: BLOCK_TIMESTAMP,
+    };
+
+    analytics.record(&listed);
+    analytics.record(&price_updated);
+    //the price update left the chain, the token is listed at 2 again
+    analytics.record(&reverted(price_updated));
+    analytics.record(&sold("9", Some(5.0), BLOCK_TIMESTAMP + 8 * ONE_DAY));
+    //and then the listing itself
+    analytics.record(&reverted(listed));
+    analytics.record(&sold("10", Some(5.0), BLOCK_TIMESTAMP + 9 * ONE_DAY));
+
+    let days = analytics.collection_stats(Some(NFT_CONTRACT), StatsPeriod::Day);
+    let floors: Vec<_> = days.iter().map(|day| (day.period_start.as_str(), day.floor_near)).collect();
+    assert_eq!(floors, vec![("2021-06-15", Some(2.0)), ("2021-06-23", Some(2.0)), ("2021-06-24", None)]);
+    //the hours of the listing are more than a week old
+    let hours = analytics.collection_stats(None, StatsPeriod::Hour);
+    assert_eq!(hours.iter().map(|hour| hour.period_start.as_str()).collect::<Vec<_>>(), vec!["2021-06-23T00", "2021-06-24T00"]);
+
+    analytics.save_if_changed();
+    assert_eq!(SaleAnalytics::load(home_dir.path()).collection_stats(None, StatsPeriod::Day), days);
+}
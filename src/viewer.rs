@@ -0,0 +1,442 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix::Addr;
+use async_trait::async_trait;
+use near_client::{Query, ViewClientActor};
+use near_client_primitives::types::QueryError;
+use near_indexer::near_primitives::types::{BlockId, BlockReference, FunctionArgs};
+use near_indexer::near_primitives::views::{QueryRequest, QueryResponseKind};
+use near_sdk::json_types::{U128, U64};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{FungibleTokenId, JsonToken, SaleConditions};
+
+// ------------------------------- VIEW CALLS ----------------------------------
+// all the view calls the handlers make go through the ChainViewer trait. the node
+//  backed implementation adds a timeout to every call and caches results that were
+//  asked for at a specific block height (those can never change).
+
+//the different ways a view call can fail
+#[derive(Debug, Clone)]
+pub(crate) enum ViewError {
+    //the node didn't answer in time
+    Timeout { method_name: String },
+    //the view client actor is gone
+    Unavailable(String),
+    //the state at the requested block is gone (garbage collected) or the node never had it
+    BlockUnavailable { method_name: String, error: String },
+    //the node answered the query with an error
+    Query { method_name: String, error: String },
+    //the node answered with something other than a function call result
+    UnexpectedResponse { method_name: String },
+    //the function call result couldn't be parsed into the type we expected
+    Decode { method_name: String, error: String },
+}
+
+impl fmt::Display for ViewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViewError::Timeout { method_name } => write!(f, "view call {} timed out", method_name),
+            ViewError::Unavailable(error) => write!(f, "view client unavailable: {}", error),
+            ViewError::BlockUnavailable { method_name, error } => {
+                write!(f, "state for view call {} is not available: {}", method_name, error)
+            }
+            ViewError::Query { method_name, error } => {
+                write!(f, "view call {} failed: {}", method_name, error)
+            }
+            ViewError::UnexpectedResponse { method_name } => {
+                write!(f, "view call {} returned an unexpected response", method_name)
+            }
+            ViewError::Decode { method_name, error } => {
+                write!(f, "could not decode result of view call {}: {}", method_name, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ViewError {}
+
+//lets handlers that return Result<(), String> use `?` on view calls
+impl From<ViewError> for String {
+    fn from(err: ViewError) -> String {
+        err.to_string()
+    }
+}
+
+//return type of ft_metadata on fungible token contracts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FungibleTokenMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub decimals: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Bid {
+    pub owner_id: String,
+    pub price: U128,
+}
+
+//return type of get_sale on the market contract --> only declaring the fields we care about
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sale {
+    pub owner_id: String,
+    pub approval_id: U64,
+    pub nft_contract_id: String,
+    pub token_id: String,
+    pub sale_conditions: SaleConditions,
+    #[serde(default)]
+    pub bids: HashMap<FungibleTokenId, Vec<Bid>>,
+    #[serde(default)]
+    pub is_auction: bool,
+}
+
+//separator the market contract uses between the nft contract and the token id in its sale keys
+const CONTRACT_TOKEN_DELIMITER: &str = "||";
+
+fn decode<T: DeserializeOwned>(method_name: &str, result: &[u8]) -> Result<T, ViewError> {
+    serde_json::from_slice(result).map_err(|err| ViewError::Decode {
+        method_name: method_name.to_string(),
+        error: err.to_string(),
+    })
+}
+
+#[async_trait]
+pub(crate) trait ChainViewer: Send + Sync {
+    //call a view method and return the raw bytes it returned
+    async fn call_function(
+        &self,
+        account_id: &str,
+        method_name: &str,
+        args: serde_json::Value,
+        block_reference: BlockReference,
+    ) -> Result<Vec<u8>, ViewError>;
+
+    async fn nft_tokens_batch(
+        &self,
+        nft_contract: &str,
+        token_ids: Vec<String>,
+        block_reference: BlockReference,
+    ) -> Result<Vec<JsonToken>, ViewError> {
+        let args = serde_json::json!({ "token_ids": token_ids });
+        let result = self
+            .call_function(nft_contract, "nft_tokens_batch", args, block_reference)
+            .await?;
+        decode("nft_tokens_batch", &result)
+    }
+
+    async fn nft_token(
+        &self,
+        nft_contract: &str,
+        token_id: &str,
+        block_reference: BlockReference,
+    ) -> Result<Option<JsonToken>, ViewError> {
+        let args = serde_json::json!({ "token_id": token_id });
+        let result = self
+            .call_function(nft_contract, "nft_token", args, block_reference)
+            .await?;
+        decode("nft_token", &result)
+    }
+
+    async fn ft_metadata(
+        &self,
+        ft_contract: &str,
+        block_reference: BlockReference,
+    ) -> Result<FungibleTokenMetadata, ViewError> {
+        let result = self
+            .call_function(ft_contract, "ft_metadata", serde_json::json!({}), block_reference)
+            .await?;
+        decode("ft_metadata", &result)
+    }
+
+    async fn get_sale(
+        &self,
+        market_contract: &str,
+        nft_contract: &str,
+        token_id: &str,
+        block_reference: BlockReference,
+    ) -> Result<Option<Sale>, ViewError> {
+        let args = serde_json::json!({
+            "nft_contract_token": format!("{}{}{}", nft_contract, CONTRACT_TOKEN_DELIMITER, token_id),
+        });
+        let result = self
+            .call_function(market_contract, "get_sale", args, block_reference)
+            .await?;
+        decode("get_sale", &result)
+    }
+
+    //storage the account has paid for on the market, in yocto
+    async fn storage_balance_of(
+        &self,
+        market_contract: &str,
+        account_id: &str,
+        block_reference: BlockReference,
+    ) -> Result<U128, ViewError> {
+        let args = serde_json::json!({ "account_id": account_id });
+        let result = self
+            .call_function(market_contract, "storage_balance_of", args, block_reference)
+            .await?;
+        decode("storage_balance_of", &result)
+    }
+
+    //storage the market needs for a single listing, in yocto
+    async fn storage_minimum_balance(
+        &self,
+        market_contract: &str,
+        block_reference: BlockReference,
+    ) -> Result<U128, ViewError> {
+        let result = self
+            .call_function(market_contract, "storage_minimum_balance", serde_json::json!({}), block_reference)
+            .await?;
+        decode("storage_minimum_balance", &result)
+    }
+
+    //how many listings the account has on the market
+    async fn get_supply_by_owner_id(
+        &self,
+        market_contract: &str,
+        account_id: &str,
+        block_reference: BlockReference,
+    ) -> Result<U64, ViewError> {
+        let args = serde_json::json!({ "account_id": account_id });
+        let result = self
+            .call_function(market_contract, "get_supply_by_owner_id", args, block_reference)
+            .await?;
+        decode("get_supply_by_owner_id", &result)
+    }
+}
+
+//key of a cached view call: (block height, contract, method, args)
+type ViewCacheKey = (u64, String, String, String);
+
+//small least-recently-used cache for view call results
+struct ViewCache {
+    capacity: usize,
+    entries: HashMap<ViewCacheKey, Vec<u8>>,
+    //least recently used key at the front
+    order: VecDeque<ViewCacheKey>,
+}
+
+impl ViewCache {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, entries: HashMap::new(), order: VecDeque::new() }
+    }
+
+    fn get(&mut self, key: &ViewCacheKey) -> Option<Vec<u8>> {
+        let value = self.entries.get(key)?.clone();
+        self.touch(key);
+        Some(value)
+    }
+
+    fn insert(&mut self, key: ViewCacheKey, value: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key.clone(), value).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.entries.remove(&evicted);
+            }
+        }
+    }
+
+    //move the key to the most recently used end
+    fn touch(&mut self, key: &ViewCacheKey) {
+        if let Some(position) = self.order.iter().position(|cached| cached == key) {
+            if let Some(key) = self.order.remove(position) {
+                self.order.push_back(key);
+            }
+        }
+    }
+}
+
+//what to do when a view call is pinned to a block whose state the node no longer has
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ViewFallback {
+    //run the view call again against the latest block
+    Latest,
+    //give up on the view call
+    Fail,
+}
+
+impl FromStr for ViewFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latest" => Ok(ViewFallback::Latest),
+            "fail" => Ok(ViewFallback::Fail),
+            _ => Err(format!("unknown view fallback {:?}, expected \"latest\" or \"fail\"", s)),
+        }
+    }
+}
+
+//view calls answered by the local node through the ViewClientActor
+pub(crate) struct NodeChainViewer {
+    view_client: Addr<ViewClientActor>,
+    timeout: Duration,
+    fallback: ViewFallback,
+    cache: Mutex<ViewCache>,
+}
+
+impl NodeChainViewer {
+    pub(crate) fn new(
+        view_client: Addr<ViewClientActor>,
+        timeout: Duration,
+        cache_size: usize,
+        fallback: ViewFallback,
+    ) -> Self {
+        Self { view_client, timeout, fallback, cache: Mutex::new(ViewCache::new(cache_size)) }
+    }
+
+    async fn query(
+        &self,
+        account_id: &str,
+        method_name: &str,
+        args: &serde_json::Value,
+        block_reference: BlockReference,
+    ) -> Result<Vec<u8>, ViewError> {
+        let request = QueryRequest::CallFunction {
+            //contract to call
+            account_id: account_id.parse().map_err(|err| ViewError::Query {
+                method_name: method_name.to_string(),
+                error: format!("invalid account id {:?}: {:?}", account_id, err),
+            })?,
+            //method to view
+            method_name: method_name.to_string(),
+            //passed in arguments
+            args: FunctionArgs::from(args.to_string().into_bytes()),
+        };
+        let query = Query::new(block_reference, request);
+
+        let response = tokio::time::timeout(self.timeout, self.view_client.send(query))
+            .await
+            .map_err(|_| ViewError::Timeout { method_name: method_name.to_string() })?
+            .map_err(|err| ViewError::Unavailable(err.to_string()))?
+            .map_err(|err| match err {
+                QueryError::GarbageCollectedBlock { .. } | QueryError::UnknownBlock { .. } => {
+                    ViewError::BlockUnavailable { method_name: method_name.to_string(), error: err.to_string() }
+                }
+                _ => ViewError::Query { method_name: method_name.to_string(), error: err.to_string() },
+            })?;
+
+        match response.kind {
+            QueryResponseKind::CallResult(call_result) => Ok(call_result.result),
+            _ => Err(ViewError::UnexpectedResponse { method_name: method_name.to_string() }),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainViewer for NodeChainViewer {
+    async fn call_function(
+        &self,
+        account_id: &str,
+        method_name: &str,
+        args: serde_json::Value,
+        block_reference: BlockReference,
+    ) -> Result<Vec<u8>, ViewError> {
+        //only calls pinned to a block height can be cached, "latest" moves on
+        let cache_key = match block_reference {
+            BlockReference::BlockId(BlockId::Height(height)) => {
+                Some((height, account_id.to_string(), method_name.to_string(), args.to_string()))
+            }
+            _ => None,
+        };
+        if let Some(cache_key) = &cache_key {
+            if let Some(cached) = self.cache.lock().unwrap().get(cache_key) {
+                return Ok(cached);
+            }
+        }
+
+        let result = match self.query(account_id, method_name, &args, block_reference).await {
+            Err(ViewError::BlockUnavailable { error, .. }) if self.fallback == ViewFallback::Latest => {
+                eprintln!(
+                    "State for view call {} on {} is not available ({}), falling back to the latest block",
+                    method_name, account_id, error
+                );
+                //the answer depends on the latest block now, so it must not be cached under the old height
+                return self.query(account_id, method_name, &args, BlockReference::latest()).await;
+            }
+            result => result?,
+        };
+
+        if let Some(cache_key) = cache_key {
+            self.cache.lock().unwrap().insert(cache_key, result.clone());
+        }
+        Ok(result)
+    }
+}
+
+//view calls answered from canned responses, so handlers can be tested without a node
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockChainViewer {
+    //(contract, method) --> height the answer holds from --> value returned by the view call
+    responses: HashMap<(String, String), std::collections::BTreeMap<u64, serde_json::Value>>,
+    //every call made, as (contract, method, args)
+    pub calls: Mutex<Vec<(String, String, serde_json::Value)>>,
+}
+
+#[cfg(test)]
+impl MockChainViewer {
+    //the answer at every height
+    pub(crate) fn with_response(self, account_id: &str, method_name: &str, response: serde_json::Value) -> Self {
+        self.with_response_from(0, account_id, method_name, response)
+    }
+
+    //the answer from this height on, until a later one replaces it
+    pub(crate) fn with_response_from(
+        mut self,
+        block_height: u64,
+        account_id: &str,
+        method_name: &str,
+        response: serde_json::Value,
+    ) -> Self {
+        self.responses
+            .entry((account_id.to_string(), method_name.to_string()))
+            .or_default()
+            .insert(block_height, response);
+        self
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl ChainViewer for MockChainViewer {
+    async fn call_function(
+        &self,
+        account_id: &str,
+        method_name: &str,
+        args: serde_json::Value,
+        block_reference: BlockReference,
+    ) -> Result<Vec<u8>, ViewError> {
+        self.calls.lock().unwrap().push((account_id.to_string(), method_name.to_string(), args));
+        let responses = self.responses.get(&(account_id.to_string(), method_name.to_string()));
+        //anything but a height is answered with the latest state
+        let response = match block_reference {
+            BlockReference::BlockId(BlockId::Height(height)) => {
+                responses.and_then(|responses| responses.range(..=height).next_back())
+            }
+            _ => responses.and_then(|responses| responses.iter().next_back()),
+        };
+        match response {
+            Some((_, response)) => Ok(response.to_string().into_bytes()),
+            None => Err(ViewError::Query {
+                method_name: method_name.to_string(),
+                error: format!("no mock response for {} on {}", method_name, account_id),
+            }),
+        }
+    }
+}